@@ -0,0 +1,58 @@
+//! Host-side benchmarks of request parsing and response building
+//!
+//! Run with `cargo bench`. Each case is warmed up and then timed over enough
+//! iterations to smooth out scheduler noise; the mean time per iteration is printed.
+
+use nanofish::{HttpHeader, HttpRequest, HttpResponse, StatusCode};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 200_000;
+
+const GET: &[u8] = b"GET /api/status?verbose=1 HTTP/1.1\r\n\
+    Host: 192.168.1.20\r\n\
+    User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36\r\n\
+    Accept: application/json\r\n\
+    Accept-Encoding: gzip, deflate\r\n\
+    Connection: keep-alive\r\n\r\n";
+
+const POST: &[u8] = b"POST /api/config HTTP/1.1\r\n\
+    Host: 192.168.1.20\r\n\
+    Content-Type: application/json\r\n\
+    Content-Length: 27\r\n\r\n\
+    {\"interval\":30,\"led\":true}";
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iteration = start.elapsed() / ITERATIONS;
+    println!("{name:<28} {:>8} ns/iter", per_iteration.as_nanos());
+}
+
+fn main() {
+    bench("parse GET", || {
+        let request = HttpRequest::try_from(black_box(GET)).unwrap();
+        black_box(request.path);
+    });
+    bench("parse POST with body", || {
+        let request = HttpRequest::try_from(black_box(POST)).unwrap();
+        black_box(request.body);
+    });
+    bench("build 200 text response", || {
+        let response = HttpResponse::ok(black_box("{\"status\":\"ok\"}"));
+        black_box(response.build_bytes::<512>().unwrap());
+    });
+    bench("build response with headers", || {
+        let mut response = HttpResponse::json(StatusCode::Ok, black_box("{\"id\":1}"));
+        let _ = response
+            .headers
+            .push(HttpHeader::new("Cache-Control", "no-store"));
+        let _ = response.headers.push(HttpHeader::new("X-Request-Id", "42"));
+        black_box(response.build_bytes::<512>().unwrap());
+    });
+}
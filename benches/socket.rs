@@ -0,0 +1,95 @@
+//! Host-side benchmark of creating a listening socket per connection versus reusing one
+//!
+//! Run with `cargo bench --bench socket`. The stack runs on a driver that never sends
+//! or receives, so only the socket bookkeeping the server loop does between two
+//! connections is measured: adding the socket to the stack and putting it into
+//! listening mode, and removing it again afterwards.
+
+use embassy_net::driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_net::{Config, StackResources, tcp::TcpSocket};
+use std::hint::black_box;
+use std::task::Context;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 200_000;
+const PORT: u16 = 80;
+
+/// Driver without a link, enough to build a stack
+struct NullDriver;
+
+/// Token that is never handed out
+struct NullToken;
+
+impl RxToken for NullToken {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, f: F) -> R {
+        f(&mut [])
+    }
+}
+
+impl TxToken for NullToken {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, _len: usize, f: F) -> R {
+        f(&mut [])
+    }
+}
+
+impl Driver for NullDriver {
+    type RxToken<'a> = NullToken;
+    type TxToken<'a> = NullToken;
+
+    fn receive(&mut self, _cx: &mut Context) -> Option<(NullToken, NullToken)> {
+        None
+    }
+
+    fn transmit(&mut self, _cx: &mut Context) -> Option<NullToken> {
+        None
+    }
+
+    fn link_state(&mut self, _cx: &mut Context) -> LinkState {
+        LinkState::Down
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        HardwareAddress::Ethernet([0x02, 0, 0, 0, 0, 1])
+    }
+}
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iteration = start.elapsed() / ITERATIONS;
+    println!("{name:<28} {:>8} ns/iter", per_iteration.as_nanos());
+}
+
+fn main() {
+    let mut resources = StackResources::<2>::new();
+    let (stack, _runner) = embassy_net::new(NullDriver, Config::default(), &mut resources, 0);
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    // What the server loop does: a fresh socket for every connection
+    bench("new socket + listen", || {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // The first poll puts the socket into listening mode
+        black_box(futures_lite::future::block_on(
+            futures_lite::future::poll_once(socket.accept(PORT)),
+        ));
+    });
+
+    // The alternative: reset one socket and listen again
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    bench("reused socket + listen", || {
+        socket.abort();
+        black_box(futures_lite::future::block_on(
+            futures_lite::future::poll_once(socket.accept(PORT)),
+        ));
+    });
+}
@@ -0,0 +1,142 @@
+use core::fmt;
+
+/// The common `Cache-Control` directives
+///
+/// Parse a request's header with [`CacheControl::parse`] (or
+/// [`HttpRequest::cache_control`](crate::HttpRequest::cache_control)), or build the
+/// value for a response and format it with `Display`:
+///
+/// ```rust
+/// use core::fmt::Write;
+/// use nanofish::CacheControl;
+///
+/// let policy = CacheControl {
+///     public: true,
+///     max_age: Some(3600),
+///     ..Default::default()
+/// };
+/// let mut value = heapless::String::<64>::new();
+/// write!(value, "{policy}").unwrap();
+/// assert_eq!(value, "public, max-age=3600");
+/// ```
+///
+/// Unknown directives are ignored when parsing.
+// Every flag is an independent directive
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// `public`: any cache may store the response
+    pub public: bool,
+    /// `private`: only the client's own cache may store the response
+    pub private: bool,
+    /// `no-cache`: stored responses must be revalidated before use
+    pub no_cache: bool,
+    /// `no-store`: the message must not be stored at all
+    pub no_store: bool,
+    /// `must-revalidate`: stale responses must not be used without revalidation
+    pub must_revalidate: bool,
+    /// `max-age=N`: the response is fresh for `N` seconds
+    pub max_age: Option<u32>,
+}
+
+impl CacheControl {
+    /// Parse the directives of a `Cache-Control` header value
+    ///
+    /// Directive names are case-insensitive. A `max-age` that is not a number is
+    /// ignored.
+    #[must_use]
+    pub fn parse(value: &str) -> Self {
+        let mut cache_control = Self::default();
+        for directive in value.split(',') {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim(), Some(argument.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            if name.eq_ignore_ascii_case("public") {
+                cache_control.public = true;
+            } else if name.eq_ignore_ascii_case("private") {
+                cache_control.private = true;
+            } else if name.eq_ignore_ascii_case("no-cache") {
+                cache_control.no_cache = true;
+            } else if name.eq_ignore_ascii_case("no-store") {
+                cache_control.no_store = true;
+            } else if name.eq_ignore_ascii_case("must-revalidate") {
+                cache_control.must_revalidate = true;
+            } else if name.eq_ignore_ascii_case("max-age") {
+                cache_control.max_age = argument.and_then(|age| age.parse().ok());
+            }
+        }
+        cache_control
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.must_revalidate, "must-revalidate"),
+        ];
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, "{separator}{name}")?;
+            separator = ", ";
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "{separator}max-age={max_age}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cache_control() {
+        let cache_control = CacheControl::parse("no-cache, Max-Age=60, x-custom=1");
+        assert_eq!(
+            cache_control,
+            CacheControl {
+                no_cache: true,
+                max_age: Some(60),
+                ..Default::default()
+            }
+        );
+
+        let cache_control = CacheControl::parse("private,no-store,must-revalidate");
+        assert!(cache_control.private);
+        assert!(cache_control.no_store);
+        assert!(cache_control.must_revalidate);
+        assert!(!cache_control.public);
+
+        assert_eq!(CacheControl::parse("max-age=\"10\"").max_age, Some(10));
+        assert_eq!(CacheControl::parse("max-age=soon").max_age, None);
+        assert_eq!(CacheControl::parse(""), CacheControl::default());
+    }
+
+    #[test]
+    fn test_format_cache_control() {
+        let cache_control = CacheControl {
+            private: true,
+            must_revalidate: true,
+            max_age: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            cache_control.to_string(),
+            "private, must-revalidate, max-age=0"
+        );
+
+        let no_store = CacheControl {
+            no_store: true,
+            ..Default::default()
+        };
+        assert_eq!(no_store.to_string(), "no-store");
+        assert_eq!(CacheControl::parse(&no_store.to_string()), no_store);
+        assert_eq!(CacheControl::default().to_string(), "");
+    }
+}
@@ -0,0 +1,108 @@
+use crate::StatusCode;
+use sha2::{Digest, Sha256};
+
+/// Incremental SHA-256 over an uploaded body, checked against the digest the client sent
+///
+/// For firmware or configuration uploads the client sends the hex SHA-256 of the body
+/// in `X-Checksum-SHA256`. Feed the body to [`update`](Self::update) as it arrives,
+/// e.g. record by record while writing it to flash, then [`verify`](Self::verify) the
+/// result before committing it. A body that is already complete can be checked in one
+/// go with [`HttpRequest::verify_checksum`](crate::HttpRequest::verify_checksum):
+///
+/// ```rust
+/// use nanofish::{BodyChecksum, StatusCode};
+///
+/// let mut checksum = BodyChecksum::new();
+/// for chunk in [&b"firm"[..], b"ware"] {
+///     checksum.update(chunk);
+/// }
+/// let expected = "c3bf47ea1f4a4a605470313cacb3a44f4a461f68c6faeab07e737610cb5ac835";
+/// assert_eq!(checksum.clone().verify(expected), Ok(()));
+/// assert_eq!(checksum.verify(&"0".repeat(64)), Err(StatusCode::UnprocessableEntity));
+/// ```
+#[derive(Clone, Default)]
+pub struct BodyChecksum {
+    hasher: Sha256,
+}
+
+impl BodyChecksum {
+    /// Start a checksum over an empty body
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next part of the body
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// The SHA-256 digest of everything passed to [`update`](Self::update)
+    #[must_use]
+    pub fn finish(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+
+    /// Compare the digest with `expected`, 64 hex digits in either case
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StatusCode::BadRequest`] if `expected` is not a hex SHA-256 digest,
+    /// and [`StatusCode::UnprocessableEntity`] if the body does not match it.
+    pub fn verify(self, expected: &str) -> Result<(), StatusCode> {
+        let expected = parse_digest(expected.trim()).ok_or(StatusCode::BadRequest)?;
+        if self.finish() == expected {
+            Ok(())
+        } else {
+            Err(StatusCode::UnprocessableEntity)
+        }
+    }
+}
+
+impl core::fmt::Debug for BodyChecksum {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BodyChecksum").finish_non_exhaustive()
+    }
+}
+
+/// Decode 64 hex digits into a digest
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let digit = |digit: u8| (digit as char).to_digit(16);
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        // Two hex digits always fit into a byte
+        *byte = u8::try_from(digit(pair[0])? << 4 | digit(pair[1])?).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of `b"firmware"`
+    const FIRMWARE: &str = "c3bf47ea1f4a4a605470313cacb3a44f4a461f68c6faeab07e737610cb5ac835";
+
+    #[test]
+    fn test_body_checksum() {
+        let mut checksum = BodyChecksum::new();
+        checksum.update(b"firm");
+        checksum.update(b"ware");
+        assert_eq!(checksum.clone().verify(FIRMWARE), Ok(()));
+        assert_eq!(checksum.clone().verify(&FIRMWARE.to_uppercase()), Ok(()));
+        assert_eq!(
+            checksum.clone().verify(&FIRMWARE.replace('a', "b")),
+            Err(StatusCode::UnprocessableEntity)
+        );
+        for malformed in ["", &FIRMWARE[2..], "zz", &FIRMWARE.replace('a', "g")] {
+            assert_eq!(
+                checksum.clone().verify(malformed),
+                Err(StatusCode::BadRequest),
+                "{malformed}"
+            );
+        }
+    }
+}
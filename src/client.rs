@@ -162,6 +162,7 @@ impl<
     ///         ResponseBody::Text(text) => println!("Text: {}", text),
     ///         ResponseBody::Binary(bytes) => println!("Binary: {} bytes", bytes.len()),
     ///         ResponseBody::Empty => println!("Empty response"),
+    ///         _ => {}
     ///     }
     ///     Ok(())
     /// }
@@ -773,6 +774,9 @@ impl<
         let mut content_length_present = false;
 
         for header in headers {
+            if !header.is_valid() {
+                return Err(Error::HeaderError("Invalid characters in header"));
+            }
             try_push!(http_request.push_str(header.name));
             try_push!(http_request.push_str(": "));
             try_push!(http_request.push_str(header.value));
@@ -864,6 +868,19 @@ mod tests {
         assert!(!DefaultHttpClient::is_response_complete(data));
     }
 
+    #[test]
+    fn test_build_http_request_rejects_header_injection() {
+        let build = |headers: &[HttpHeader<'_>]| {
+            DefaultHttpClient::build_http_request(HttpMethod::GET, "host", "/", headers, None)
+        };
+
+        let result = build(&[HttpHeader::new("X-Trace", "1\r\nX-Injected: yes")]);
+        assert!(matches!(result, Err(Error::HeaderError(_))));
+
+        let request = build(&[HttpHeader::new("X-Trace", "1")]).unwrap();
+        assert!(request.contains("\r\nX-Trace: 1\r\n"));
+    }
+
     #[test]
     fn test_new_and_with_options() {
         // This test only checks that the options are set correctly, not that the stack is valid.
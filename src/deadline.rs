@@ -0,0 +1,52 @@
+use core::future::Future;
+use embassy_time::{Duration, Instant, TimeoutError, with_deadline};
+
+/// Point in time by which a sequence of operations must be done
+///
+/// `with_timeout` starts counting afresh for every future it is given, so an operation
+/// split into several steps can take a multiple of the intended time. All futures run
+/// through the same `Deadline` share a single time budget instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Deadline(Instant);
+
+impl Deadline {
+    /// Create a deadline `duration` from now
+    pub(crate) fn after(duration: Duration) -> Self {
+        Self(Instant::now() + duration)
+    }
+
+    /// Run `future` until it completes or the deadline passes
+    ///
+    /// A future started after the deadline fails right away, unless it is ready on its
+    /// first poll.
+    pub(crate) async fn run<F: Future>(&self, future: F) -> Result<F::Output, TimeoutError> {
+        with_deadline(self.0, future).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_time::Timer;
+
+    #[test]
+    fn test_deadline_is_shared_across_futures() {
+        let deadline = Deadline::after(Duration::from_millis(100));
+        futures_lite::future::block_on(async {
+            assert!(
+                deadline
+                    .run(Timer::after(Duration::from_millis(60)))
+                    .await
+                    .is_ok()
+            );
+            // Would fit into a fresh 100 ms timeout, but not into what is left
+            assert!(
+                deadline
+                    .run(Timer::after(Duration::from_millis(60)))
+                    .await
+                    .is_err()
+            );
+            assert_eq!(deadline.run(async { 1 }).await, Ok(1));
+        });
+    }
+}
@@ -4,6 +4,7 @@
 /// during various stages of request processing, from URL parsing to connection
 /// establishment and response handling.
 #[derive(Debug)]
+#[non_exhaustive]
 /// All possible errors returned by the HTTP client.
 pub enum Error {
     /// The provided URL was invalid or malformed
@@ -29,6 +30,16 @@ pub enum Error {
     HeaderError(&'static str),
     /// Invalid status code received from the server
     InvalidStatusCode,
+    /// The serialized response does not fit into the response buffer
+    ResponseTooLarge,
+    /// Reading from or writing to the connection failed
+    IoError(embedded_io_async::ErrorKind),
+    /// The query string could not be deserialized, e.g. a missing or malformed parameter
+    InvalidQuery(&'static str),
+    /// The form body could not be deserialized, e.g. a missing field or bad encoding
+    InvalidForm(&'static str),
+    /// The body could not be parsed into a single value, e.g. a number
+    InvalidBody(&'static str),
 }
 
 #[cfg(feature = "defmt")]
@@ -67,21 +78,28 @@ impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Error::InvalidUrl => write!(f, "Invalid URL"),
-            Error::DnsError(_) => write!(f, "DNS resolution failed"),
+            Error::DnsError(e) => write!(f, "DNS resolution failed: {e:?}"),
             Error::IpAddressEmpty => write!(f, "No IP addresses returned by DNS"),
-            Error::ConnectionError(_) => write!(f, "Failed to establish TCP connection"),
-            Error::TcpError(_) => write!(f, "TCP communication error"),
+            Error::ConnectionError(e) => write!(f, "Failed to establish TCP connection: {e:?}"),
+            Error::TcpError(e) => write!(f, "TCP communication error: {e:?}"),
             Error::NoResponse => write!(f, "No response received from server"),
             Error::InvalidResponse(msg) => write!(f, "Invalid response: {msg}"),
             #[cfg(feature = "tls")]
-            Error::TlsError(_) => write!(f, "TLS error occurred"),
+            Error::TlsError(e) => write!(f, "TLS error: {e:?}"),
             Error::UnsupportedScheme(scheme) => write!(f, "Unsupported scheme: {scheme}"),
             Error::HeaderError(msg) => write!(f, "Header error: {msg}"),
             Error::InvalidStatusCode => write!(f, "Invalid status code"),
+            Error::ResponseTooLarge => write!(f, "Response too large for buffer"),
+            Error::IoError(kind) => write!(f, "I/O error: {kind}"),
+            Error::InvalidQuery(msg) => write!(f, "Invalid query: {msg}"),
+            Error::InvalidForm(msg) => write!(f, "Invalid form: {msg}"),
+            Error::InvalidBody(msg) => write!(f, "Invalid body: {msg}"),
         }
     }
 }
 
+impl core::error::Error for Error {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,6 +122,31 @@ mod tests {
         assert_eq!(format!("{e}"), "Header error: too long");
         let e = Error::InvalidStatusCode;
         assert_eq!(format!("{e}"), "Invalid status code");
+        let e = Error::ResponseTooLarge;
+        assert_eq!(format!("{e}"), "Response too large for buffer");
+        let e = Error::IoError(embedded_io_async::ErrorKind::ConnectionReset);
+        assert_eq!(format!("{e}"), "I/O error: ConnectionReset");
+        let e = Error::InvalidQuery("Missing query parameter");
+        assert_eq!(format!("{e}"), "Invalid query: Missing query parameter");
+        let e = Error::InvalidForm("Missing form field");
+        assert_eq!(format!("{e}"), "Invalid form: Missing form field");
+        let e = Error::InvalidBody("Not a valid value");
+        assert_eq!(format!("{e}"), "Invalid body: Not a valid value");
+        let e = Error::DnsError(dns::Error::InvalidName);
+        assert_eq!(format!("{e}"), "DNS resolution failed: InvalidName");
+        let e = Error::ConnectionError(tcp::ConnectError::TimedOut);
+        assert_eq!(
+            format!("{e}"),
+            "Failed to establish TCP connection: TimedOut"
+        );
+        let e = Error::TcpError(tcp::Error::ConnectionReset);
+        assert_eq!(format!("{e}"), "TCP communication error: ConnectionReset");
+    }
+
+    #[test]
+    fn test_error_trait() {
+        let e: &dyn core::error::Error = &Error::InvalidUrl;
+        assert_eq!(e.to_string(), "Invalid URL");
     }
 
     #[test]
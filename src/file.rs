@@ -0,0 +1,450 @@
+use crate::{
+    error::Error,
+    handler::HttpHandlerMut,
+    header::{HttpHeader, headers, mime_types},
+    method::HttpMethod,
+    request::HttpRequest,
+    response::HttpResponse,
+    server::MAX_PATH_SIZE,
+    status_code::StatusCode,
+    writer::ResponseWriter,
+};
+use core::fmt::Write as _;
+use embedded_io_async::{ErrorKind, Write};
+use heapless::String;
+
+/// Chunk size of a [`FileServer`] unless chosen otherwise
+///
+/// A common flash page and filesystem block size, and small enough for the stack
+/// of a server task.
+pub const DEFAULT_CHUNK_SIZE: usize = 512;
+
+/// Index files a [`FileServer`] looks for in a directory unless chosen otherwise
+pub const DEFAULT_INDEX: &[&str] = &["index.html"];
+
+/// Size and other details of a file returned by a [`FileProvider`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Source of files for a [`FileServer`], e.g. a littlefs or FAT filesystem on flash
+///
+/// Paths are passed percent-decoded, without the leading `/` and without the query
+/// string, e.g. `my notes.txt` for `/my%20notes.txt`. Requests with a decoded segment
+/// that is `.` or `..` or contains a `/` (from `%2F`) or `\` never reach the
+/// provider, so every `/` in a path separates two directory levels.
+#[allow(async_fn_in_trait)]
+pub trait FileProvider {
+    /// Handle to an open file
+    type File;
+
+    /// Open the file at `path`, returning `None` if it does not exist
+    async fn open(&mut self, path: &str) -> Result<Option<Self::File>, Error>;
+
+    /// Get the metadata of an open file
+    async fn metadata(&mut self, file: &Self::File) -> Result<FileMetadata, Error>;
+
+    /// Read the next chunk of `file` into `buf`, returning `0` at the end of the file
+    async fn read_chunk(&mut self, file: &mut Self::File, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// Handler streaming files from a [`FileProvider`] to the client
+///
+/// `GET` requests for existing files are answered with their content, read and sent
+/// `CHUNK_SIZE` bytes at a time so files larger than the response buffer can be
+/// served; `HEAD` requests get the same headers without reading the file. The
+/// `Content-Type` is derived from the file extension. If the client accepts gzip, a
+/// precompressed `<path>.gz` next to the file is sent in its place, with
+/// `Content-Encoding: gzip` and the `Content-Type` of the original name. A path that
+/// is empty or ends
+/// in `/` names a directory and is answered with the first of its index files that
+/// exists, [`DEFAULT_INDEX`] unless set with [`FileServer::with_index`]. Anything
+/// else, including paths that do not decode to a safe file name, is answered with
+/// `404 Not Found`.
+///
+/// `CHUNK_SIZE` trades memory for throughput: the chunk buffer lives on the stack
+/// while a file is sent, and every chunk costs a read from the provider plus a few
+/// bytes of chunk framing. It defaults to [`DEFAULT_CHUNK_SIZE`]; reads are fastest
+/// when it is a multiple of the flash page or filesystem block size, and there is
+/// little gain beyond the server's `TX_SIZE`.
+///
+/// ```rust,ignore
+/// use nanofish::{DefaultHttpServer, FileServer};
+///
+/// let mut server = DefaultHttpServer::new(80);
+/// let files: FileServer<_> = FileServer::new(LittleFsProvider::new(flash));
+/// server.serve(stack, files).await;
+///
+/// // Whole 4 KB flash sectors
+/// server.serve(stack, FileServer::<_, 4096>::new(LittleFsProvider::new(flash))).await;
+/// ```
+pub struct FileServer<P, const CHUNK_SIZE: usize = DEFAULT_CHUNK_SIZE> {
+    provider: P,
+    index: &'static [&'static str],
+}
+
+impl<P: FileProvider, const CHUNK_SIZE: usize> FileServer<P, CHUNK_SIZE> {
+    /// Create a handler serving files from `provider`
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            index: DEFAULT_INDEX,
+        }
+    }
+
+    /// Set the names of the index files served for a directory, tried in order
+    ///
+    /// With no names, requests for a directory are answered with `404 Not Found`.
+    #[must_use]
+    pub fn with_index(mut self, names: &'static [&'static str]) -> Self {
+        self.index = names;
+        self
+    }
+
+    /// Open the first index file in the directory `dir` that exists
+    ///
+    /// The path of the file is written into `path`. See [`Self::open_variant`] for
+    /// `gzip` and the result.
+    async fn open_index(
+        &mut self,
+        dir: &str,
+        gzip: bool,
+        path: &mut String<MAX_PATH_SIZE>,
+    ) -> Result<Option<(P::File, bool)>, Error> {
+        for name in self.index {
+            path.clear();
+            if write!(path, "{dir}{name}").is_err() {
+                continue;
+            }
+            if let Some(file) = self.open_variant(path, gzip).await? {
+                return Ok(Some(file));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Open the file at `path`, trying its precompressed `<path>.gz` first if `gzip`
+    ///
+    /// Returns the file and whether it is the compressed variant.
+    async fn open_variant(
+        &mut self,
+        path: &str,
+        gzip: bool,
+    ) -> Result<Option<(P::File, bool)>, Error> {
+        let mut gz_path = String::<MAX_PATH_SIZE>::new();
+        if gzip
+            && write!(gz_path, "{path}.gz").is_ok()
+            && let Some(file) = self.provider.open(&gz_path).await?
+        {
+            return Ok(Some((file, true)));
+        }
+        Ok(self.provider.open(path).await?.map(|file| (file, false)))
+    }
+}
+
+impl<P: FileProvider, const CHUNK_SIZE: usize> HttpHandlerMut for FileServer<P, CHUNK_SIZE> {
+    async fn handle_request(
+        &mut self,
+        _request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        // Only reached if `handle_stream` found nothing to send
+        Ok(HttpResponse::not_found())
+    }
+
+    async fn handle_stream<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        if request.method != HttpMethod::GET && request.method != HttpMethod::HEAD {
+            return Ok(());
+        }
+        let mut path_buf = [0u8; MAX_PATH_SIZE];
+        let Some(mut path) = file_path(request, &mut path_buf) else {
+            return Ok(());
+        };
+        let gzip = request.negotiate_encoding(&["gzip"]) == Some("gzip");
+        let mut index_path = String::<MAX_PATH_SIZE>::new();
+        let file = if path.is_empty() || path.ends_with('/') {
+            let file = self.open_index(path, gzip, &mut index_path).await?;
+            path = &index_path;
+            file
+        } else {
+            self.open_variant(path, gzip).await?
+        };
+        let Some((mut file, compressed)) = file else {
+            return Ok(());
+        };
+        let metadata = self.provider.metadata(&file).await?;
+        debug!("Serving {} ({} bytes)", path, metadata.size);
+
+        // Only sent for `HEAD`, the writer frames the body of a `GET` itself
+        let mut size = String::<20>::new();
+        let _ = write!(size, "{}", metadata.size);
+        let headers = [
+            HttpHeader::content_type(content_type_for(path)),
+            HttpHeader::new(headers::CONTENT_LENGTH, &size),
+            HttpHeader::new(headers::CONTENT_ENCODING, "gzip"),
+            HttpHeader::new(headers::VARY, headers::ACCEPT_ENCODING),
+        ];
+        let headers = if compressed {
+            &headers[..]
+        } else {
+            &headers[..2]
+        };
+        writer.start(StatusCode::Ok, headers).await?;
+        if request.method == HttpMethod::HEAD {
+            return Ok(());
+        }
+        let mut buf = [0u8; CHUNK_SIZE];
+        loop {
+            let n = self.provider.read_chunk(&mut file, &mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            // A provider claiming more than it was given must not panic the server
+            let chunk = buf.get(..n).ok_or(Error::IoError(ErrorKind::InvalidData))?;
+            writer.write_chunk(chunk).await?;
+        }
+    }
+}
+
+/// Decode the path of `request` into `buf` and strip the leading `/`
+///
+/// Returns `None` if the path does not decode, or a decoded segment is `.` or `..`
+/// or contains a `/` or `\`: file names must not escape the served directory.
+fn file_path<'b>(request: &HttpRequest<'_>, buf: &'b mut [u8]) -> Option<&'b str> {
+    let raw = request.path.split('?').next().unwrap_or_default();
+    let path = request.decoded_path(buf).ok()?;
+    // Any `/` not in the raw path was encoded inside a segment
+    let unsafe_segment = path.matches('/').count() != raw.matches('/').count()
+        || path
+            .split('/')
+            .any(|segment| matches!(segment, "." | "..") || segment.contains('\\'));
+    (!unsafe_segment).then(|| path.trim_start_matches('/'))
+}
+
+/// Guess the MIME type of a file from its extension
+#[must_use]
+pub fn content_type_for(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+    let types: [(&str, &'static str); 12] = [
+        ("html", mime_types::HTML),
+        ("htm", mime_types::HTML),
+        ("css", "text/css"),
+        ("js", "text/javascript"),
+        ("json", mime_types::JSON),
+        ("txt", mime_types::TEXT),
+        ("xml", mime_types::XML),
+        ("svg", "image/svg+xml"),
+        ("png", "image/png"),
+        ("jpg", "image/jpeg"),
+        ("ico", "image/x-icon"),
+        ("wasm", "application/wasm"),
+    ];
+    types
+        .iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map_or(mime_types::BINARY, |(_, mime)| mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestSocket;
+
+    /// Files kept in memory, read a few bytes at a time like from flash
+    struct MemoryProvider {
+        files: &'static [(&'static str, &'static [u8])],
+    }
+
+    impl FileProvider for MemoryProvider {
+        type File = (&'static [u8], usize);
+
+        async fn open(&mut self, path: &str) -> Result<Option<Self::File>, Error> {
+            Ok(self
+                .files
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, data)| (*data, 0)))
+        }
+
+        async fn metadata(&mut self, file: &Self::File) -> Result<FileMetadata, Error> {
+            Ok(FileMetadata {
+                size: file.0.len() as u64,
+            })
+        }
+
+        async fn read_chunk(
+            &mut self,
+            file: &mut Self::File,
+            buf: &mut [u8],
+        ) -> Result<usize, Error> {
+            let (data, pos) = file;
+            let n = buf.len().min(data.len() - *pos);
+            buf[..n].copy_from_slice(&data[*pos..*pos + n]);
+            *pos += n;
+            Ok(n)
+        }
+    }
+
+    fn files() -> MemoryProvider {
+        MemoryProvider {
+            files: &[
+                ("app.css", b"body{color:red}"),
+                ("app.css.gz", b"\x1f\x8b"),
+                ("empty.bin", b""),
+                ("my notes.txt", b"hi"),
+                ("a/b.txt", b"nested"),
+                ("index.html", b"home"),
+                ("docs/index.htm", b"docs"),
+            ],
+        }
+    }
+
+    fn serve(request: &[u8]) -> (bool, std::vec::Vec<u8>) {
+        serve_with(FileServer::<_, 4>::new(files()), request)
+    }
+
+    fn serve_with(
+        mut handler: FileServer<MemoryProvider, 4>,
+        request: &[u8],
+    ) -> (bool, std::vec::Vec<u8>) {
+        let request = HttpRequest::try_from(request).unwrap();
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let head = request.method == HttpMethod::HEAD;
+        let mut writer = ResponseWriter::new(&mut socket, true, head);
+        futures_lite::future::block_on(handler.handle_stream(&request, &mut writer)).unwrap();
+        let started = writer.is_started();
+        (started, socket.0)
+    }
+
+    #[test]
+    fn test_file_server_streams_file() {
+        let (started, output) = serve(b"GET /app.css?v=2 HTTP/1.1\r\n\r\n");
+        assert!(started);
+        assert_eq!(
+            core::str::from_utf8(&output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4\r\nbody\r\n4\r\n{col\r\n4\r\nor:r\r\n3\r\ned}\r\n"
+        );
+
+        let (started, output) = serve(b"GET /empty.bin HTTP/1.1\r\n\r\n");
+        assert!(started);
+        assert!(output.ends_with(b"Transfer-Encoding: chunked\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_file_server_decodes_path() {
+        let (started, output) = serve(b"GET /my%20notes.txt HTTP/1.1\r\n\r\n");
+        assert!(started);
+        assert!(output.ends_with(b"\r\n\r\n2\r\nhi\r\n"));
+        assert!(serve(b"GET /a/b.txt HTTP/1.1\r\n\r\n").0);
+
+        // Decoded segments must not reach outside the served directory
+        for path in [
+            "/a%2Fb.txt",
+            "/a/%2e%2e/a/b.txt",
+            "/%2E/app.css",
+            "/a%5Cb.txt",
+            "/%zz",
+        ] {
+            let request = std::format!("GET {path} HTTP/1.1\r\n\r\n");
+            assert!(!serve(request.as_bytes()).0, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_file_server_head() {
+        let (started, output) = serve(b"HEAD /app.css HTTP/1.1\r\n\r\n");
+        assert!(started);
+        assert_eq!(
+            core::str::from_utf8(&output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nContent-Length: 15\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_file_server_precompressed() {
+        let (started, output) =
+            serve(b"GET /app.css HTTP/1.1\r\nAccept-Encoding: gzip, br\r\n\r\n");
+        assert!(started);
+        assert_eq!(
+            core::str::from_utf8(&output[..output.len() - 7]).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nContent-Encoding: gzip\r\n\
+             Vary: Accept-Encoding\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+        assert!(output.ends_with(b"2\r\n\x1f\x8b\r\n"));
+
+        // Plain file if gzip is not accepted or there is no compressed variant
+        for request in [
+            &b"GET /app.css HTTP/1.1\r\n\r\n"[..],
+            b"GET /app.css HTTP/1.1\r\nAccept-Encoding: br, gzip;q=0\r\n\r\n",
+            b"GET /a/b.txt HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n",
+        ] {
+            let (started, output) = serve(request);
+            assert!(started);
+            let output = core::str::from_utf8(&output).unwrap();
+            assert!(!output.contains("Content-Encoding"), "{output}");
+            assert!(!output.contains("Vary"), "{output}");
+        }
+    }
+
+    #[test]
+    fn test_file_server_index() {
+        let (started, output) = serve(b"GET /?lang=en HTTP/1.1\r\n\r\n");
+        assert!(started);
+        assert!(output.starts_with(b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n"));
+        assert!(output.ends_with(b"\r\n\r\n4\r\nhome\r\n"));
+        // Only `index.html` is looked for by default
+        assert!(!serve(b"GET /docs/ HTTP/1.1\r\n\r\n").0);
+        assert!(!serve(b"GET /a/ HTTP/1.1\r\n\r\n").0);
+
+        let index = || FileServer::new(files()).with_index(&["index.htm", "index.html"]);
+        let (started, output) = serve_with(index(), b"GET /docs/ HTTP/1.1\r\n\r\n");
+        assert!(started);
+        assert!(output.ends_with(b"\r\n\r\n4\r\ndocs\r\n"));
+        assert!(serve_with(index(), b"GET / HTTP/1.1\r\n\r\n").0);
+
+        let no_index = FileServer::new(files()).with_index(&[]);
+        assert!(!serve_with(no_index, b"GET / HTTP/1.1\r\n\r\n").0);
+    }
+
+    #[test]
+    fn test_file_server_default_chunk_size() {
+        static FIRMWARE: [u8; 600] = [0xab; 600];
+        static FILES: [(&str, &[u8]); 1] = [("firmware.bin", &FIRMWARE)];
+        let mut handler: FileServer<_> = FileServer::new(MemoryProvider { files: &FILES });
+        let request = HttpRequest::try_from(&b"GET /firmware.bin HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        futures_lite::future::block_on(handler.handle_stream(&request, &mut writer)).unwrap();
+
+        // 512 bytes, then the remaining 88
+        let output = socket.0;
+        assert_eq!(output.windows(5).filter(|w| *w == b"200\r\n").count(), 1);
+        assert_eq!(output.windows(4).filter(|w| *w == b"58\r\n").count(), 1);
+    }
+
+    #[test]
+    fn test_file_server_falls_back_to_404() {
+        let (started, _) = serve(b"GET /missing.css HTTP/1.1\r\n\r\n");
+        assert!(!started);
+        let (started, _) = serve(b"POST /app.css HTTP/1.1\r\n\r\n");
+        assert!(!started);
+    }
+
+    #[test]
+    fn test_content_type_for() {
+        assert_eq!(content_type_for("index.html"), "text/html");
+        assert_eq!(content_type_for("js/app.JS"), "text/javascript");
+        assert_eq!(content_type_for("logo.svg"), "image/svg+xml");
+        assert_eq!(content_type_for("firmware"), "application/octet-stream");
+        assert_eq!(
+            content_type_for("archive.tar.gz"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -1,20 +1,285 @@
 use crate::{
     error::Error,
-    header::HttpHeader,
-    request::HttpRequest,
-    response::{HttpResponse, ResponseBody},
+    header::{HttpHeader, headers, mime_types},
+    method::HttpMethod,
+    options::HttpClientOptions,
+    request::{HttpRequest, find_double_crlf},
+    response::HttpResponse,
     status_code::StatusCode,
+    writer::ResponseWriter,
 };
-use heapless::Vec;
+use core::fmt::Write as _;
+use embassy_net::{
+    Stack,
+    dns::{DnsQueryType, DnsSocket},
+    tcp::TcpSocket,
+};
+use embassy_sync::semaphore::{Semaphore, SemaphoreReleaser};
+use embedded_io_async::{Error as _, ErrorKind, Read, Write};
+use heapless::String;
+
+/// Maximum length of the `Location` built by [`HttpsRedirect`]
+const MAX_REDIRECT_SIZE: usize = 256;
+
+/// Maximum number of request and response headers a [`Proxy`] forwards
+const MAX_FORWARDED_HEADERS: usize = 16;
+
+/// Maximum length of a DNS label, and so of a [`Subdomain`]
+const MAX_LABEL_SIZE: usize = 63;
 
 /// Trait for handling HTTP requests
+///
+/// Handlers must not panic: without `std` a panic cannot be caught and takes the
+/// whole firmware down. Report failures as an `Err` instead, which the server
+/// answers with `500 Internal Server Error` while it keeps serving. The server
+/// itself turns anything wrong with a handler's output, such as invalid or too many
+/// headers or a response that does not fit its buffer, into such a `500` as well.
+///
+/// The methods take `&self`, so one handler can answer the connections of several
+/// server tasks at once: each task is given a reference to it, which is a handler
+/// as well. Mutable state goes behind a [`SharedState`](crate::SharedState), an
+/// `embassy_sync` mutex or an atomic. A handler that needs `&mut self`, e.g. to
+/// render into a buffer of its own, implements [`HttpHandlerMut`] instead and is
+/// owned by a single task. The server accepts both.
+///
+/// ```rust,ignore
+/// static API: Api = Api::new();
+///
+/// // In each of several server tasks
+/// server.serve(stack, &API).await;
+/// ```
 #[allow(async_fn_in_trait)]
 pub trait HttpHandler {
+    /// Handle an incoming HTTP request and return a response
+    async fn handle_request(&self, request: &HttpRequest<'_>) -> Result<HttpResponse<'_>, Error>;
+
+    /// Handle an incoming HTTP request by streaming the response through `writer`
+    ///
+    /// See [`HttpHandlerMut::handle_stream`].
+    async fn handle_stream<W: Write>(
+        &self,
+        _request: &HttpRequest<'_>,
+        _writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Compute the `ETag` of the response to `request` without producing it
+    ///
+    /// See [`HttpHandlerMut::etag`].
+    async fn etag(&self, _request: &HttpRequest<'_>) -> Option<&str> {
+        None
+    }
+
+    /// Time `request` may take to answer in seconds
+    ///
+    /// See [`HttpHandlerMut::handler_timeout`].
+    fn handler_timeout(&self, _request: &HttpRequest<'_>) -> Option<u64> {
+        None
+    }
+
+    /// Called once by [`HttpServer::serve`](crate::HttpServer::serve) before the
+    /// first connection is accepted
+    ///
+    /// See [`HttpHandlerMut::on_start`].
+    async fn on_start(&self, _stack: Stack<'_>, _port: u16) {}
+
+    /// Called every [`ServerOptions::maintenance_interval`](crate::ServerOptions::maintenance_interval)
+    /// connections
+    ///
+    /// See [`HttpHandlerMut::on_maintenance`].
+    async fn on_maintenance(&self) {}
+
+    /// Take over the connection after a `101 Switching Protocols` response to `request`
+    ///
+    /// See [`HttpHandlerMut::take_over`].
+    async fn take_over<S: Read + Write>(
+        &self,
+        _request: &HttpRequest<'_>,
+        _socket: &mut S,
+        _buffered: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<H: HttpHandler + ?Sized> HttpHandler for &H {
+    async fn handle_request(&self, request: &HttpRequest<'_>) -> Result<HttpResponse<'_>, Error> {
+        (**self).handle_request(request).await
+    }
+
+    async fn handle_stream<W: Write>(
+        &self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        (**self).handle_stream(request, writer).await
+    }
+
+    async fn etag(&self, request: &HttpRequest<'_>) -> Option<&str> {
+        (**self).etag(request).await
+    }
+
+    fn handler_timeout(&self, request: &HttpRequest<'_>) -> Option<u64> {
+        (**self).handler_timeout(request)
+    }
+
+    async fn on_start(&self, stack: Stack<'_>, port: u16) {
+        (**self).on_start(stack, port).await;
+    }
+
+    async fn on_maintenance(&self) {
+        (**self).on_maintenance().await;
+    }
+
+    async fn take_over<S: Read + Write>(
+        &self,
+        request: &HttpRequest<'_>,
+        socket: &mut S,
+        buffered: &[u8],
+    ) -> Result<(), Error> {
+        (**self).take_over(request, socket, buffered).await
+    }
+}
+
+/// Trait for handlers that need `&mut self`, owned by a single server task
+///
+/// The variant of [`HttpHandler`] for handlers with state of their own, like a
+/// buffer the response is rendered into: the task owning the handler calls it for
+/// one request at a time, so no locking is needed. Every [`HttpHandler`] is an
+/// `HttpHandlerMut` as well, and the server is generic over this trait.
+///
+/// Handlers written before [`HttpHandler`] took `&self` keep working unchanged by
+/// implementing this trait instead.
+#[allow(async_fn_in_trait)]
+pub trait HttpHandlerMut {
     /// Handle an incoming HTTP request and return a response
     async fn handle_request(
         &mut self,
         request: &HttpRequest<'_>,
     ) -> Result<HttpResponse<'_>, Error>;
+
+    /// Handle an incoming HTTP request by streaming the response through `writer`
+    ///
+    /// This is called before [`HttpHandlerMut::handle_request`]. If the handler starts a
+    /// response on `writer`, the server finishes it once this method returns and
+    /// `handle_request` is not called. Leaving `writer` untouched (the default) falls
+    /// back to `handle_request`.
+    async fn handle_stream<W: Write>(
+        &mut self,
+        _request: &HttpRequest<'_>,
+        _writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Compute the `ETag` of the response to `request` without producing it
+    ///
+    /// This is called first. If it returns a tag that matches the request's
+    /// `If-None-Match` header, the server answers `304 Not Modified` with that `ETag`
+    /// and neither `handle_stream` nor `handle_request` is called. Returning `None`
+    /// (the default) always produces the response. The tag must include its quotes,
+    /// e.g. `"v42"`, and should also be sent with the full response.
+    async fn etag(&mut self, _request: &HttpRequest<'_>) -> Option<&str> {
+        None
+    }
+
+    /// Time `request` may take to answer in seconds, overriding
+    /// [`ServerTimeouts::handler_timeout`](crate::ServerTimeouts::handler_timeout)
+    ///
+    /// Lets slow endpoints like a firmware upload run for minutes while the default
+    /// stays tight for everything else. The timeout covers producing the response up
+    /// to [`ResponseWriter::start`]; when it expires the response is replaced by
+    /// `504 Gateway Timeout`. A streamed response that has started runs until the
+    /// handler returns, so event streams can stay open. Returning `None` (the default)
+    /// keeps the server's timeout.
+    fn handler_timeout(&self, _request: &HttpRequest<'_>) -> Option<u64> {
+        None
+    }
+
+    /// Called once by [`HttpServer::serve`](crate::HttpServer::serve) before the
+    /// first connection is accepted
+    ///
+    /// Runs with the network up, so it can announce the server, e.g. register with
+    /// a discovery service or log the device's URL from `stack.config_v4()` and
+    /// `port`. Connections are only accepted once it returns. Does nothing by
+    /// default.
+    async fn on_start(&mut self, _stack: Stack<'_>, _port: u16) {}
+
+    /// Called every [`ServerOptions::maintenance_interval`](crate::ServerOptions::maintenance_interval)
+    /// connections
+    ///
+    /// Runs between connections, after the server has zeroed its buffers, so periodic
+    /// upkeep on long-running devices, like resetting statistics or compacting a
+    /// cache, does not delay a request. Does nothing by default.
+    async fn on_maintenance(&mut self) {}
+
+    /// Take over the connection after a `101 Switching Protocols` response to `request`
+    ///
+    /// The escape hatch for protocols that continue on the connection after an HTTP
+    /// request, like WebSocket or a custom framing: when
+    /// [`handle_request`](Self::handle_request) answers with status `101` (see
+    /// [`HttpResponse::switching_protocols`]), the server sends the response and then
+    /// calls this with the connection itself. `buffered` holds what the client sent
+    /// after the request and the server has already read, e.g. a first frame, which
+    /// comes before anything read from `socket`. Once this returns, the server closes
+    /// the connection without reading another request from it; an error is only
+    /// logged.
+    ///
+    /// Runs without the handler timeout, but the socket keeps its own timeout, e.g. the
+    /// embassy socket's inactivity timeout. The socket borrows the buffers of the
+    /// server task, which serves no other connection meanwhile. Does nothing by
+    /// default, so the connection is closed right after the `101`.
+    async fn take_over<S: Read + Write>(
+        &mut self,
+        _request: &HttpRequest<'_>,
+        _socket: &mut S,
+        _buffered: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<H: HttpHandler + ?Sized> HttpHandlerMut for H {
+    async fn handle_request(
+        &mut self,
+        request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        HttpHandler::handle_request(self, request).await
+    }
+
+    async fn handle_stream<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        HttpHandler::handle_stream(self, request, writer).await
+    }
+
+    async fn etag(&mut self, request: &HttpRequest<'_>) -> Option<&str> {
+        HttpHandler::etag(self, request).await
+    }
+
+    fn handler_timeout(&self, request: &HttpRequest<'_>) -> Option<u64> {
+        HttpHandler::handler_timeout(self, request)
+    }
+
+    async fn on_start(&mut self, stack: Stack<'_>, port: u16) {
+        HttpHandler::on_start(self, stack, port).await;
+    }
+
+    async fn on_maintenance(&mut self) {
+        HttpHandler::on_maintenance(self).await;
+    }
+
+    async fn take_over<S: Read + Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        socket: &mut S,
+        buffered: &[u8],
+    ) -> Result<(), Error> {
+        HttpHandler::take_over(self, request, socket, buffered).await
+    }
 }
 
 /// A simple handler that serves basic endpoints for testing
@@ -22,36 +287,1055 @@ pub trait HttpHandler {
 pub struct SimpleHandler;
 
 impl HttpHandler for SimpleHandler {
+    async fn handle_request(&self, request: &HttpRequest<'_>) -> Result<HttpResponse<'_>, Error> {
+        match request.path {
+            "/" => Ok(HttpResponse::html(
+                StatusCode::Ok,
+                "<h1>Hello from nanofish HTTP server!</h1>",
+            )),
+            "/health" => Ok(HttpResponse::json(StatusCode::Ok, "{\"status\":\"ok\"}")),
+            _ => Ok(HttpResponse::text(StatusCode::NotFound, "404 Not Found")),
+        }
+    }
+}
+
+/// Handler calling an async closure, created with [`fn_handler`]
+pub struct FnHandler<F>(F);
+
+/// Use an async closure as a handler
+///
+/// Handy for one-off handlers that need no state of their own. The closure may read
+/// and borrow from the request while it runs, but the response it returns can only
+/// borrow `'static` data; implement [`HttpHandlerMut`] for handlers serving from a
+/// buffer they own.
+///
+/// ```rust
+/// use nanofish::{HttpRequest, HttpResponse, fn_handler};
+///
+/// let handler = fn_handler(async |request: &HttpRequest<'_>| {
+///     Ok(match request.path {
+///         "/" => HttpResponse::ok("hello"),
+///         _ => HttpResponse::not_found(),
+///     })
+/// });
+/// ```
+pub fn fn_handler<F>(f: F) -> FnHandler<F>
+where
+    F: AsyncFnMut(&HttpRequest<'_>) -> Result<HttpResponse<'static>, Error>,
+{
+    FnHandler(f)
+}
+
+impl<F> HttpHandlerMut for FnHandler<F>
+where
+    F: AsyncFnMut(&HttpRequest<'_>) -> Result<HttpResponse<'static>, Error>,
+{
     async fn handle_request(
         &mut self,
         request: &HttpRequest<'_>,
     ) -> Result<HttpResponse<'_>, Error> {
-        let mut headers = Vec::new();
-        match request.path {
-            "/" => {
-                let _ = headers.push(HttpHeader::new("Content-Type", "text/html"));
-                Ok(HttpResponse {
-                    status_code: StatusCode::Ok,
-                    headers,
-                    body: ResponseBody::Text("<h1>Hello from nanofish HTTP server!</h1>"),
-                })
+        (self.0)(request).await
+    }
+}
+
+/// Handler redirecting every request to the same URL on an HTTPS listener
+///
+/// Answers with `308 Permanent Redirect` to `https://<host>:<port><path>`, keeping
+/// the path and query and taking the host from the request's `Host` header. The
+/// port is left out for the default port 443. Requests without a `Host` header get
+/// `400 Bad Request`.
+///
+/// ```rust,ignore
+/// use nanofish::{DefaultHttpServer, HttpsRedirect};
+///
+/// let mut server = DefaultHttpServer::new(80);
+/// server.serve(stack, HttpsRedirect::new(443)).await;
+/// ```
+#[derive(Debug)]
+pub struct HttpsRedirect {
+    https_port: u16,
+    location: String<MAX_REDIRECT_SIZE>,
+}
+
+impl HttpsRedirect {
+    /// Create a handler redirecting to `https_port`
+    #[must_use]
+    pub fn new(https_port: u16) -> Self {
+        Self {
+            https_port,
+            location: String::new(),
+        }
+    }
+}
+
+impl HttpHandlerMut for HttpsRedirect {
+    async fn handle_request(
+        &mut self,
+        request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        let Some(host) = request.get_header(headers::HOST) else {
+            return Ok(HttpResponse::text(
+                StatusCode::BadRequest,
+                "Missing Host header",
+            ));
+        };
+        // Drop the port of the plain listener
+        let host = strip_port(host);
+
+        self.location.clear();
+        let written = if self.https_port == 443 {
+            write!(self.location, "https://{host}{}", request.path)
+        } else {
+            write!(
+                self.location,
+                "https://{host}:{}{}",
+                self.https_port, request.path
+            )
+        };
+        if written.is_err() {
+            return Ok(HttpResponse::text(
+                StatusCode::RequestUriTooLong,
+                "URI Too Long",
+            ));
+        }
+
+        let mut response = HttpResponse::empty(StatusCode::PermanentRedirect);
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::LOCATION, &self.location));
+        Ok(response)
+    }
+}
+
+/// Drop the port from a `Host` header value, keeping IPv6 literals like `[::1]`
+fn strip_port(host: &str) -> &str {
+    match host.find(']') {
+        Some(end) => &host[..=end],
+        None => host.split(':').next().unwrap_or(host),
+    }
+}
+
+/// Connections to the upstream server of a [`Proxy`]
+///
+/// [`TcpUpstream`] connects over an embassy-net stack. Implement this for anything
+/// else that can reach the upstream, e.g. a connection pool or a socket on another
+/// interface.
+#[allow(async_fn_in_trait)]
+pub trait Upstream {
+    /// An open connection, used for a single request
+    type Connection<'c>: Read + Write
+    where
+        Self: 'c;
+
+    /// Open a connection to `host` on `port`
+    async fn connect(&mut self, host: &str, port: u16) -> Result<Self::Connection<'_>, Error>;
+}
+
+/// [`Upstream`] opening TCP connections on an embassy-net stack
+///
+/// The socket buffers of `RX_SIZE` and `TX_SIZE` bytes are part of the struct, so
+/// they do not take up space on the stack of the server task while a request is
+/// forwarded.
+pub struct TcpUpstream<'a, const RX_SIZE: usize = 1024, const TX_SIZE: usize = 1024> {
+    stack: Stack<'a>,
+    options: HttpClientOptions,
+    rx_buffer: [u8; RX_SIZE],
+    tx_buffer: [u8; TX_SIZE],
+}
+
+impl<'a, const RX_SIZE: usize, const TX_SIZE: usize> TcpUpstream<'a, RX_SIZE, TX_SIZE> {
+    /// Create an upstream connecting over `stack` with the default options
+    #[must_use]
+    pub fn new(stack: Stack<'a>) -> Self {
+        Self::with_options(stack, HttpClientOptions::default())
+    }
+
+    /// Create an upstream connecting over `stack`
+    ///
+    /// Only [`HttpClientOptions::socket_timeout`] applies to proxied requests.
+    #[must_use]
+    pub fn with_options(stack: Stack<'a>, options: HttpClientOptions) -> Self {
+        Self {
+            stack,
+            options,
+            rx_buffer: [0; RX_SIZE],
+            tx_buffer: [0; TX_SIZE],
+        }
+    }
+}
+
+impl<const RX_SIZE: usize, const TX_SIZE: usize> Upstream for TcpUpstream<'_, RX_SIZE, TX_SIZE> {
+    type Connection<'c>
+        = TcpSocket<'c>
+    where
+        Self: 'c;
+
+    async fn connect(&mut self, host: &str, port: u16) -> Result<TcpSocket<'_>, Error> {
+        let addresses = DnsSocket::new(self.stack)
+            .query(host, DnsQueryType::A)
+            .await?;
+        let address = *addresses.first().ok_or(Error::IpAddressEmpty)?;
+
+        let mut socket = TcpSocket::new(self.stack, &mut self.rx_buffer, &mut self.tx_buffer);
+        socket.set_timeout(Some(self.options.socket_timeout));
+        if let Err(e) = socket.connect((address, port)).await {
+            socket.abort();
+            return Err(e.into());
+        }
+        Ok(socket)
+    }
+}
+
+/// Handler forwarding every request to an upstream HTTP server and relaying its answer
+///
+/// The method, path, query, headers and body are re-issued to `base` (e.g.
+/// `http://192.168.1.20:8080`, optionally with a path prefix) over a connection
+/// opened by `upstream`, and the upstream status, headers and body are streamed
+/// back. Hop-by-hop headers (`Connection`, `Transfer-Encoding`, `Keep-Alive`, ...,
+/// and any named in `Connection`) are not forwarded in either direction.
+///
+/// The upstream response head is read into a `BUF_SIZE` buffer on the stack and must
+/// fit into it; the body is then relayed `BUF_SIZE` bytes at a time whether the
+/// upstream delimits it with `Content-Length`, chunked framing or by closing the
+/// connection, so it can be larger than any buffer. If the upstream cannot be
+/// reached or does not answer with a valid response head the client gets
+/// `502 Bad Gateway`; a response that breaks off later ends without its final chunk.
+/// Only plain `http://` upstreams are supported.
+///
+/// ```rust,ignore
+/// use nanofish::{DefaultHttpServer, Proxy, TcpUpstream};
+///
+/// let upstream: TcpUpstream = TcpUpstream::new(stack);
+/// let mut server = DefaultHttpServer::new(80);
+/// server.serve(stack, Proxy::<_>::new(upstream, "http://192.168.1.20:8080")).await;
+/// ```
+pub struct Proxy<'a, U, const BUF_SIZE: usize = 1024> {
+    upstream: U,
+    base: &'a str,
+}
+
+impl<'a, U: Upstream, const BUF_SIZE: usize> Proxy<'a, U, BUF_SIZE> {
+    /// Create a handler forwarding to the base URL `base` over `upstream`
+    pub fn new(upstream: U, base: &'a str) -> Self {
+        Self {
+            upstream,
+            base: base.trim_end_matches('/'),
+        }
+    }
+
+    /// Forward `request` and relay the answer, using `buf` for the response
+    async fn forward<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+        buf: &mut [u8],
+    ) -> Result<(), Error> {
+        let authority = self.base.strip_prefix("http://").ok_or(Error::InvalidUrl)?;
+        let (authority, prefix) = authority
+            .find('/')
+            .map_or((authority, ""), |slash| authority.split_at(slash));
+        let host = strip_port(authority);
+        let port = match authority[host.len()..].strip_prefix(':') {
+            Some(port) => port.parse().map_err(|_| Error::InvalidUrl)?,
+            None => 80,
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        let mut connection = self.upstream.connect(host, port).await?;
+        send_request(&mut connection, request, authority, prefix).await?;
+
+        let (head_len, filled) = read_head(&mut connection, buf).await?;
+        let head = core::str::from_utf8(&buf[..head_len])
+            .map_err(|_| Error::InvalidResponse("Upstream response head is not UTF-8"))?;
+        let status_code = status_of(head).ok_or(Error::InvalidStatusCode)?;
+        let mut framing = if request.method == HttpMethod::HEAD || !status_code.allows_body() {
+            Framing::Length(0)
+        } else {
+            Framing::of(head)?
+        };
+
+        start_response(writer, status_code, head).await?;
+
+        let (mut start, mut end) = (head_len, filled);
+        loop {
+            while start < end && !framing.is_done() {
+                let (used, data) = framing.decode(&buf[start..end])?;
+                writer.write_chunk(data).await?;
+                start += used;
+            }
+            if framing.is_done() {
+                return Ok(());
+            }
+            (start, end) = (0, read(&mut connection, buf).await?);
+            if end == 0 {
+                return match framing {
+                    Framing::Close => Ok(()),
+                    _ => Err(Error::InvalidResponse("Upstream response truncated")),
+                };
+            }
+        }
+    }
+}
+
+impl<U: Upstream, const BUF_SIZE: usize> HttpHandlerMut for Proxy<'_, U, BUF_SIZE> {
+    async fn handle_request(
+        &mut self,
+        _request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        // Only reached if `handle_stream` was not called
+        Ok(HttpResponse::text(StatusCode::BadGateway, "Bad Gateway"))
+    }
+
+    async fn handle_stream<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        let mut buf = [0u8; BUF_SIZE];
+        match self.forward(request, writer, &mut buf).await {
+            Ok(()) => Ok(()),
+            Err(e) if writer.is_started() => Err(e),
+            Err(e) => {
+                warn!("Upstream request failed: {:?}", e);
+                writer
+                    .start(
+                        StatusCode::BadGateway,
+                        &[HttpHeader::content_type(mime_types::TEXT)],
+                    )
+                    .await?;
+                writer.write_chunk(b"Bad Gateway").await
+            }
+        }
+    }
+}
+
+/// Send `request` to the upstream at `authority`, below the path `prefix`
+///
+/// The upstream is asked to close the connection after its response, which is how
+/// a response without any other framing ends.
+async fn send_request<C: Write>(
+    connection: &mut C,
+    request: &HttpRequest<'_>,
+    authority: &str,
+    prefix: &str,
+) -> Result<(), Error> {
+    let line = [
+        request.method.as_str(),
+        " ",
+        prefix,
+        request.path,
+        " HTTP/1.1\r\nHost: ",
+        authority,
+        "\r\n",
+    ];
+    send(connection, &line).await?;
+
+    let connection_header = request.get_header(headers::CONNECTION);
+    let mut forwarded = 0;
+    for header in &request.headers {
+        // `Host` and `Content-Length` are set for the upstream connection
+        if !is_forwardable(header.name, connection_header)
+            || header.name.eq_ignore_ascii_case(headers::HOST)
+        {
+            continue;
+        }
+        if forwarded == MAX_FORWARDED_HEADERS {
+            warn!("Too many headers to forward, dropping {}", header.name);
+            continue;
+        }
+        send(connection, &[header.name, ": ", header.value, "\r\n"]).await?;
+        forwarded += 1;
+    }
+
+    let mut length = String::<20>::new();
+    if !request.body.is_empty() {
+        let _ = write!(length, "{}", request.body.len());
+        send(connection, &["Content-Length: ", &length, "\r\n"]).await?;
+    }
+    send(connection, &["Connection: close\r\n\r\n"]).await?;
+    connection
+        .write_all(request.body)
+        .await
+        .map_err(|e| Error::IoError(e.kind()))?;
+    connection
+        .flush()
+        .await
+        .map_err(|e| Error::IoError(e.kind()))
+}
+
+/// Write all `parts` to `connection`
+async fn send<C: Write>(connection: &mut C, parts: &[&str]) -> Result<(), Error> {
+    for part in parts {
+        connection
+            .write_all(part.as_bytes())
+            .await
+            .map_err(|e| Error::IoError(e.kind()))?;
+    }
+    Ok(())
+}
+
+/// Read from `connection` into `buf`, returning `0` once the upstream closed it
+async fn read<C: Read>(connection: &mut C, buf: &mut [u8]) -> Result<usize, Error> {
+    let n = connection
+        .read(buf)
+        .await
+        .map_err(|e| Error::IoError(e.kind()))?;
+    // A connection claiming more than it was given must not panic the server
+    if n > buf.len() {
+        return Err(Error::IoError(ErrorKind::InvalidData));
+    }
+    Ok(n)
+}
+
+/// Read the upstream response head into `buf`, skipping interim `1xx` responses
+///
+/// Returns the length of the head including the blank line ending it, and how much
+/// of `buf` was filled, which can include the start of the body.
+async fn read_head<C: Read>(connection: &mut C, buf: &mut [u8]) -> Result<(usize, usize), Error> {
+    let mut filled = 0;
+    loop {
+        if let Some(end) = find_double_crlf(&buf[..filled]) {
+            let head_len = end + 4;
+            let interim = core::str::from_utf8(&buf[..head_len])
+                .ok()
+                .and_then(status_of)
+                .is_some_and(|status| status.as_u16() < 200);
+            if !interim {
+                return Ok((head_len, filled));
+            }
+            buf.copy_within(head_len..filled, 0);
+            filled -= head_len;
+            continue;
+        }
+        if filled == buf.len() {
+            return Err(Error::InvalidResponse("Upstream response head too large"));
+        }
+        match read(connection, &mut buf[filled..]).await? {
+            0 => return Err(Error::NoResponse),
+            n => filled += n,
+        }
+    }
+}
+
+/// Start the response to the client with the status and end-to-end headers of `head`
+async fn start_response<W: Write>(
+    writer: &mut ResponseWriter<'_, W>,
+    status_code: StatusCode,
+    head: &str,
+) -> Result<(), Error> {
+    let connection = head_fields(head)
+        .find(|(name, _)| name.eq_ignore_ascii_case(headers::CONNECTION))
+        .map(|(_, value)| value);
+    let mut relayed = heapless::Vec::<HttpHeader<'_>, MAX_FORWARDED_HEADERS>::new();
+    for (name, value) in head_fields(head) {
+        // The writer leaves out `Content-Length` unless there is no body to frame
+        let forwardable =
+            is_forwardable(name, connection) || name.eq_ignore_ascii_case(headers::CONTENT_LENGTH);
+        if forwardable && relayed.push(HttpHeader::new(name, value)).is_err() {
+            warn!("Too many upstream headers, dropping {}", name);
+        }
+    }
+    writer.start(status_code, &relayed).await
+}
+
+/// Get the status of a response from its `head`, e.g. `HTTP/1.1 200 OK`
+fn status_of(head: &str) -> Option<StatusCode> {
+    let mut parts = head.split("\r\n").next()?.split(' ');
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    parts.next()?.parse::<u16>().ok().map(StatusCode::from)
+}
+
+/// Iterate over the `(name, value)` pairs of the header fields in a response `head`
+fn head_fields(head: &str) -> impl Iterator<Item = (&str, &str)> {
+    head.split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+/// How the body of an upstream response is delimited, and how much of it is left
+enum Framing {
+    /// The given number of bytes is left
+    Length(usize),
+    /// Chunked framing, currently at the given part of it
+    Chunked(Chunk),
+    /// The body ends when the upstream closes the connection
+    Close,
+}
+
+/// Position within a chunked body
+#[derive(Clone, Copy)]
+enum Chunk {
+    /// Reading the size of the next chunk, read so far
+    Size(usize),
+    /// Skipping the extensions after the size of the next chunk
+    Extension(usize),
+    /// The given number of data bytes of the current chunk is left
+    Data(usize),
+    /// Reading the line break after the data of a chunk
+    DataEnd,
+    /// Skipping the trailer fields after the last chunk
+    Trailer { line_start: bool },
+    /// The body is complete
+    Done,
+}
+
+impl Framing {
+    /// Get the framing announced in a response `head` that has a body
+    fn of(head: &str) -> Result<Self, Error> {
+        let field = |wanted: &str| {
+            head_fields(head)
+                .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                .map(|(_, value)| value)
+        };
+        if field(headers::TRANSFER_ENCODING).is_some_and(|codings| {
+            let last = codings.rsplit(',').next().unwrap_or_default();
+            last.trim().eq_ignore_ascii_case("chunked")
+        }) {
+            return Ok(Framing::Chunked(Chunk::Size(0)));
+        }
+        match field(headers::CONTENT_LENGTH) {
+            Some(length) => length
+                .parse()
+                .map(Framing::Length)
+                .map_err(|_| Error::InvalidResponse("Invalid upstream Content-Length")),
+            None => Ok(Framing::Close),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(self, Framing::Length(0) | Framing::Chunked(Chunk::Done))
+    }
+
+    /// Take the next piece of body data from `input`
+    ///
+    /// Returns how many bytes of `input` were used and the data among them, which is
+    /// empty if they only held framing.
+    fn decode<'b>(&mut self, input: &'b [u8]) -> Result<(usize, &'b [u8]), Error> {
+        match self {
+            Framing::Length(left) => {
+                let n = input.len().min(*left);
+                *left -= n;
+                Ok((n, &input[..n]))
+            }
+            Framing::Close => Ok((input.len(), input)),
+            Framing::Chunked(chunk) => {
+                if let Chunk::Data(left) = chunk {
+                    let n = input.len().min(*left);
+                    *left -= n;
+                    if *left == 0 {
+                        *chunk = Chunk::DataEnd;
+                    }
+                    return Ok((n, &input[..n]));
+                }
+                for (i, &byte) in input.iter().enumerate() {
+                    *chunk = next_chunk_state(*chunk, byte)?;
+                    if matches!(chunk, Chunk::Data(_) | Chunk::Done) {
+                        return Ok((i + 1, &[]));
+                    }
+                }
+                Ok((input.len(), &[]))
+            }
+        }
+    }
+}
+
+/// Advance a chunked body outside of chunk data by one framing `byte`
+fn next_chunk_state(chunk: Chunk, byte: u8) -> Result<Chunk, Error> {
+    const INVALID: Error = Error::InvalidResponse("Invalid upstream chunked framing");
+    Ok(match (chunk, byte) {
+        (Chunk::Size(0) | Chunk::Extension(0), b'\n') => Chunk::Trailer { line_start: true },
+        (Chunk::Size(size) | Chunk::Extension(size), b'\n') => Chunk::Data(size),
+        (Chunk::Extension(_), _) | (Chunk::DataEnd | Chunk::Trailer { .. }, b'\r') => chunk,
+        (Chunk::Size(size), b';' | b'\r' | b' ' | b'\t') => Chunk::Extension(size),
+        (Chunk::Size(size), _) => {
+            let digit = char::from(byte).to_digit(16).ok_or(INVALID)?;
+            size.checked_mul(16)
+                .and_then(|size| size.checked_add(digit as usize))
+                .map(Chunk::Size)
+                .ok_or(INVALID)?
+        }
+        (Chunk::DataEnd, b'\n') => Chunk::Size(0),
+        (Chunk::Trailer { line_start: true }, b'\n') => Chunk::Done,
+        (Chunk::Trailer { .. }, b'\n') => Chunk::Trailer { line_start: true },
+        (Chunk::Trailer { .. }, _) => Chunk::Trailer { line_start: false },
+        (Chunk::DataEnd | Chunk::Data(_) | Chunk::Done, _) => return Err(INVALID),
+    })
+}
+
+/// Check whether a proxy may pass on the header `name`
+///
+/// Hop-by-hop headers only apply to a single connection, as do the ones listed in
+/// the message's `Connection` header. `Content-Length` is recomputed when the
+/// message is sent on.
+fn is_forwardable(name: &str, connection: Option<&str>) -> bool {
+    const HOP_BY_HOP: [&str; 9] = [
+        headers::CONNECTION,
+        headers::CONTENT_LENGTH,
+        "Keep-Alive",
+        "Proxy-Authenticate",
+        "Proxy-Authorization",
+        "TE",
+        "Trailer",
+        "Transfer-Encoding",
+        "Upgrade",
+    ];
+    let listed = connection.is_some_and(|connection| {
+        connection
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case(name))
+    });
+    !listed && !HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name))
+}
+
+/// What a [`ConcurrencyLimit`] does when all permits are in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until another request finishes and a permit becomes available
+    Wait,
+    /// Answer immediately with `503 Service Unavailable`
+    Reject,
+}
+
+/// Handler wrapper that caps how many requests are handled at once
+///
+/// All wrapped handlers sharing the same semaphore (e.g. handlers of several server
+/// tasks listening on the same port) count against the same limit, which protects
+/// downstream resources such as flash or I2C devices from parallel access. Each
+/// request takes one permit: one taken for `handle_stream` is kept for
+/// `handle_request` if the response was not streamed.
+///
+/// ```rust,ignore
+/// use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, semaphore::GreedySemaphore};
+/// use nanofish::{ConcurrencyLimit, SimpleHandler};
+///
+/// static PERMITS: GreedySemaphore<CriticalSectionRawMutex> = GreedySemaphore::new(2);
+///
+/// let handler = ConcurrencyLimit::new(&PERMITS, SimpleHandler);
+/// ```
+pub struct ConcurrencyLimit<'a, S: Semaphore, H> {
+    semaphore: &'a S,
+    inner: H,
+    backpressure: Backpressure,
+    /// Permit of a request `handle_stream` passed on to `handle_request`
+    held: Option<SemaphoreReleaser<'a, S>>,
+}
+
+impl<'a, S, H> ConcurrencyLimit<'a, S, H>
+where
+    S: Semaphore,
+    H: HttpHandlerMut,
+{
+    /// Wrap `inner`, waiting for a free permit when the limit is reached
+    #[must_use]
+    pub fn new(semaphore: &'a S, inner: H) -> Self {
+        Self::with_backpressure(semaphore, inner, Backpressure::Wait)
+    }
+
+    /// Wrap `inner` with a custom [`Backpressure`] strategy
+    #[must_use]
+    pub fn with_backpressure(semaphore: &'a S, inner: H, backpressure: Backpressure) -> Self {
+        Self {
+            semaphore,
+            inner,
+            backpressure,
+            held: None,
+        }
+    }
+
+    async fn acquire(&self) -> Option<SemaphoreReleaser<'a, S>> {
+        match self.backpressure {
+            Backpressure::Wait => self.semaphore.acquire(1).await.ok(),
+            Backpressure::Reject => self.semaphore.try_acquire(1),
+        }
+    }
+}
+
+impl<S, H> HttpHandlerMut for ConcurrencyLimit<'_, S, H>
+where
+    S: Semaphore,
+    H: HttpHandlerMut,
+{
+    async fn handle_request(
+        &mut self,
+        request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        let permit = match self.held.take() {
+            Some(permit) => Some(permit),
+            None => self.acquire().await,
+        };
+        let Some(_permit) = permit else {
+            warn!("Concurrency limit reached, rejecting request");
+            return Ok(HttpResponse::text(
+                StatusCode::ServiceUnavailable,
+                "Service Unavailable",
+            ));
+        };
+
+        self.inner.handle_request(request).await
+    }
+
+    async fn handle_stream<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        self.held = None;
+        let Some(permit) = self.acquire().await else {
+            warn!("Concurrency limit reached, rejecting request");
+            writer
+                .start(
+                    StatusCode::ServiceUnavailable,
+                    &[HttpHeader::content_type(mime_types::TEXT)],
+                )
+                .await?;
+            return writer.write_chunk(b"Service Unavailable").await;
+        };
+
+        self.inner.handle_stream(request, writer).await?;
+        if !writer.is_started() {
+            // The request continues in `handle_request` under the same permit
+            self.held = Some(permit);
+        }
+        Ok(())
+    }
+
+    async fn etag(&mut self, request: &HttpRequest<'_>) -> Option<&str> {
+        // Cheap enough to answer without a permit
+        self.inner.etag(request).await
+    }
+
+    fn handler_timeout(&self, request: &HttpRequest<'_>) -> Option<u64> {
+        self.inner.handler_timeout(request)
+    }
+
+    async fn on_start(&mut self, stack: Stack<'_>, port: u16) {
+        self.inner.on_start(stack, port).await;
+    }
+
+    async fn on_maintenance(&mut self) {
+        self.inner.on_maintenance().await;
+    }
+
+    async fn take_over<T: Read + Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        socket: &mut T,
+        buffered: &[u8],
+    ) -> Result<(), Error> {
+        // The new protocol may use the protected resources just like a request
+        let Some(_permit) = self.acquire().await else {
+            warn!("Concurrency limit reached, closing upgraded connection");
+            return Ok(());
+        };
+
+        self.inner.take_over(request, socket, buffered).await
+    }
+}
+
+/// Handler wrapper rejecting requests that lack a required header
+///
+/// Centralizes a gatekeeping check like an API key that every request must carry.
+/// Requests passing it go to `inner` unchanged. A missing header is answered with
+/// `400 Bad Request`; with [`RequireHeader::with_secret`] a missing or different value
+/// is answered with `401 Unauthorized`, comparing values in constant time so the
+/// secret cannot be guessed byte by byte from response times.
+///
+/// ```rust,ignore
+/// use nanofish::{RequireHeader, headers};
+///
+/// let handler = RequireHeader::with_secret(headers::X_API_KEY, API_KEY, ApiHandler);
+/// ```
+pub struct RequireHeader<'a, H> {
+    name: &'a str,
+    secret: Option<&'a str>,
+    inner: H,
+}
+
+impl<'a, H: HttpHandlerMut> RequireHeader<'a, H> {
+    /// Pass requests carrying the header `name`, with any value, to `inner`
+    #[must_use]
+    pub fn new(name: &'a str, inner: H) -> Self {
+        Self {
+            name,
+            secret: None,
+            inner,
+        }
+    }
+
+    /// Pass requests whose header `name` has the value `secret` to `inner`
+    #[must_use]
+    pub fn with_secret(name: &'a str, secret: &'a str, inner: H) -> Self {
+        Self {
+            name,
+            secret: Some(secret),
+            inner,
+        }
+    }
+
+    /// The status to answer `request` with instead of passing it on, if any
+    fn rejection(&self, request: &HttpRequest<'_>) -> Option<StatusCode> {
+        let value = request.get_header(self.name);
+        match (self.secret, value) {
+            (None, Some(_)) => None,
+            (None, None) => Some(StatusCode::BadRequest),
+            (Some(secret), Some(value))
+                if constant_time_eq(secret.as_bytes(), value.as_bytes()) =>
+            {
+                None
+            }
+            (Some(_), _) => Some(StatusCode::Unauthorized),
+        }
+    }
+}
+
+/// Compare `secret` with `given` in a time that only depends on the length of `secret`
+fn constant_time_eq(secret: &[u8], given: &[u8]) -> bool {
+    let mut diff = usize::from(secret.len() != given.len());
+    for (i, &byte) in secret.iter().enumerate() {
+        diff |= usize::from(byte ^ given.get(i).copied().unwrap_or(0));
+    }
+    core::hint::black_box(diff) == 0
+}
+
+impl<H: HttpHandlerMut> HttpHandlerMut for RequireHeader<'_, H> {
+    async fn handle_request(
+        &mut self,
+        request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        match self.rejection(request) {
+            Some(status_code) => {
+                warn!("Rejecting request without valid {} header", self.name);
+                Ok(HttpResponse::text(status_code, status_code.text()))
+            }
+            None => self.inner.handle_request(request).await,
+        }
+    }
+
+    async fn handle_stream<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        // Rejected requests fall through to `handle_request`, which answers them
+        if self.rejection(request).is_some() {
+            return Ok(());
+        }
+        self.inner.handle_stream(request, writer).await
+    }
+
+    async fn etag(&mut self, request: &HttpRequest<'_>) -> Option<&str> {
+        // A `304` would confirm the resource to a rejected client
+        if self.rejection(request).is_some() {
+            return None;
+        }
+        self.inner.etag(request).await
+    }
+
+    fn handler_timeout(&self, request: &HttpRequest<'_>) -> Option<u64> {
+        self.inner.handler_timeout(request)
+    }
+
+    async fn on_start(&mut self, stack: Stack<'_>, port: u16) {
+        self.inner.on_start(stack, port).await;
+    }
+
+    async fn on_maintenance(&mut self) {
+        self.inner.on_maintenance().await;
+    }
+
+    async fn take_over<S: Read + Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        socket: &mut S,
+        buffered: &[u8],
+    ) -> Result<(), Error> {
+        // Only a request that passed reaches the inner handler's `101`
+        if self.rejection(request).is_some() {
+            return Ok(());
+        }
+        self.inner.take_over(request, socket, buffered).await
+    }
+}
+
+/// Subdomain matched by the wildcard of a [`VirtualHost`] pattern
+///
+/// Attached to the request's [`extensions`](HttpRequest::extensions) before the
+/// inner handler is called, lowercased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subdomain(String<MAX_LABEL_SIZE>);
+
+impl Subdomain {
+    /// The matched label, e.g. `sensor1` for `sensor1.local` matching `*.local`
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Handler wrapper routing requests by their `Host` header
+///
+/// Requests for `host` go to `inner`, all others to `fallback`. Hosts compare
+/// case-insensitively and without their port. A leading `*.` label in `host` matches
+/// exactly one label, like a wildcard certificate: `*.local` matches
+/// `sensor1.local` but neither `local` nor `a.sensor1.local`, and the matched label
+/// is attached to the request as a [`Subdomain`]. Several hosts are served by
+/// nesting, listing specific hosts before wildcards.
+///
+/// ```rust,ignore
+/// use nanofish::{SimpleHandler, Subdomain, VirtualHost};
+///
+/// // `sensor1.local`, `sensor2.local`, ... share one handler that reads the tenant
+/// // with `request.extensions.get::<Subdomain>()`
+/// let handler = VirtualHost::new(
+///     "admin.local",
+///     AdminHandler,
+///     VirtualHost::new("*.local", SensorHandler, SimpleHandler),
+/// );
+/// ```
+pub struct VirtualHost<'p, H, F> {
+    host: &'p str,
+    inner: H,
+    fallback: F,
+}
+
+/// Where a [`VirtualHost`] sends a request
+enum Route {
+    Inner(Option<Subdomain>),
+    Fallback,
+}
+
+impl<'p, H, F> VirtualHost<'p, H, F>
+where
+    H: HttpHandlerMut,
+    F: HttpHandlerMut,
+{
+    /// Send requests for `host` to `inner` and everything else to `fallback`
+    #[must_use]
+    pub fn new(host: &'p str, inner: H, fallback: F) -> Self {
+        Self {
+            host,
+            inner,
+            fallback,
+        }
+    }
+
+    fn route(&self, request: &HttpRequest<'_>) -> Route {
+        let Some(host) = request.get_header(headers::HOST) else {
+            return Route::Fallback;
+        };
+        let host = strip_port(host.trim());
+        let host = host.strip_suffix('.').unwrap_or(host);
+
+        let Some(suffix) = self.host.strip_prefix("*.") else {
+            return if host.eq_ignore_ascii_case(self.host) {
+                Route::Inner(None)
+            } else {
+                Route::Fallback
+            };
+        };
+        let Some((label, rest)) = host.split_once('.') else {
+            return Route::Fallback;
+        };
+        if label.is_empty() || !rest.eq_ignore_ascii_case(suffix) {
+            return Route::Fallback;
+        }
+        let mut subdomain = String::new();
+        for c in label.chars() {
+            if subdomain.push(c.to_ascii_lowercase()).is_err() {
+                return Route::Fallback;
+            }
+        }
+        Route::Inner(Some(Subdomain(subdomain)))
+    }
+}
+
+impl<H, F> HttpHandlerMut for VirtualHost<'_, H, F>
+where
+    H: HttpHandlerMut,
+    F: HttpHandlerMut,
+{
+    async fn handle_request(
+        &mut self,
+        request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<'_>, Error> {
+        match self.route(request) {
+            Route::Inner(None) => self.inner.handle_request(request).await,
+            Route::Inner(Some(subdomain)) => {
+                let mut request = request.clone();
+                let _ = request.extensions.insert(&subdomain);
+                self.inner.handle_request(&request).await
             }
-            "/health" => {
-                let _ = headers.push(HttpHeader::new("Content-Type", "application/json"));
-                Ok(HttpResponse {
-                    status_code: StatusCode::Ok,
-                    headers,
-                    body: ResponseBody::Text("{\"status\":\"ok\"}"),
-                })
+            Route::Fallback => self.fallback.handle_request(request).await,
+        }
+    }
+
+    async fn handle_stream<W: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<(), Error> {
+        match self.route(request) {
+            Route::Inner(None) => self.inner.handle_stream(request, writer).await,
+            Route::Inner(Some(subdomain)) => {
+                let mut request = request.clone();
+                let _ = request.extensions.insert(&subdomain);
+                self.inner.handle_stream(&request, writer).await
+            }
+            Route::Fallback => self.fallback.handle_stream(request, writer).await,
+        }
+    }
+
+    async fn etag(&mut self, request: &HttpRequest<'_>) -> Option<&str> {
+        match self.route(request) {
+            Route::Inner(None) => self.inner.etag(request).await,
+            Route::Inner(Some(subdomain)) => {
+                let mut request = request.clone();
+                let _ = request.extensions.insert(&subdomain);
+                self.inner.etag(&request).await
+            }
+            Route::Fallback => self.fallback.etag(request).await,
+        }
+    }
+
+    fn handler_timeout(&self, request: &HttpRequest<'_>) -> Option<u64> {
+        match self.route(request) {
+            Route::Inner(None) => self.inner.handler_timeout(request),
+            Route::Inner(Some(subdomain)) => {
+                let mut request = request.clone();
+                let _ = request.extensions.insert(&subdomain);
+                self.inner.handler_timeout(&request)
             }
-            _ => {
-                let _ = headers.push(HttpHeader::new("Content-Type", "text/plain"));
-                Ok(HttpResponse {
-                    status_code: StatusCode::NotFound,
-                    headers,
-                    body: ResponseBody::Text("404 Not Found"),
-                })
+            Route::Fallback => self.fallback.handler_timeout(request),
+        }
+    }
+
+    async fn on_start(&mut self, stack: Stack<'_>, port: u16) {
+        self.inner.on_start(stack, port).await;
+        self.fallback.on_start(stack, port).await;
+    }
+
+    async fn on_maintenance(&mut self) {
+        self.inner.on_maintenance().await;
+        self.fallback.on_maintenance().await;
+    }
+
+    async fn take_over<S: Read + Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        socket: &mut S,
+        buffered: &[u8],
+    ) -> Result<(), Error> {
+        match self.route(request) {
+            Route::Inner(None) => self.inner.take_over(request, socket, buffered).await,
+            Route::Inner(Some(subdomain)) => {
+                let mut request = request.clone();
+                let _ = request.extensions.insert(&subdomain);
+                self.inner.take_over(&request, socket, buffered).await
             }
+            Route::Fallback => self.fallback.take_over(request, socket, buffered).await,
         }
     }
 }
@@ -59,19 +1343,25 @@ impl HttpHandler for SimpleHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{HttpMethod, HttpRequest, StatusCode};
+    use crate::test_util::TestSocket;
+    use crate::{HttpMethod, HttpRequest, StatusCode, request::Extensions};
+    use embassy_sync::{blocking_mutex::raw::NoopRawMutex, semaphore::GreedySemaphore};
     use heapless::Vec;
 
     #[test]
     fn test_simple_handler() {
         // Test root path
-        let mut handler = SimpleHandler;
+        let handler = SimpleHandler;
         let request = HttpRequest {
             method: HttpMethod::GET,
             path: "/",
             version: "HTTP/1.1",
             headers: Vec::new(),
             body: b"",
+            extensions: Extensions::new(),
+            secure: false,
+            client_ip: None,
+            request_line: "",
         };
 
         let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
@@ -82,13 +1372,17 @@ mod tests {
         );
 
         // Test health endpoint
-        let mut handler = SimpleHandler;
+        let handler = SimpleHandler;
         let request = HttpRequest {
             method: HttpMethod::GET,
             path: "/health",
             version: "HTTP/1.1",
             headers: Vec::new(),
             body: b"",
+            extensions: Extensions::new(),
+            secure: false,
+            client_ip: None,
+            request_line: "",
         };
 
         let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
@@ -96,17 +1390,486 @@ mod tests {
         assert_eq!(response.body.as_str(), Some("{\"status\":\"ok\"}"));
 
         // Test 404 path
-        let mut handler = SimpleHandler;
+        let handler = SimpleHandler;
         let request = HttpRequest {
             method: HttpMethod::GET,
             path: "/nonexistent",
             version: "HTTP/1.1",
             headers: Vec::new(),
             body: b"",
+            extensions: Extensions::new(),
+            secure: false,
+            client_ip: None,
+            request_line: "",
         };
 
         let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
         assert_eq!(response.status_code, StatusCode::NotFound);
         assert_eq!(response.body.as_str(), Some("404 Not Found"));
     }
+
+    #[test]
+    fn test_concurrency_limit() {
+        let semaphore = GreedySemaphore::<NoopRawMutex>::new(1);
+        let request = HttpRequest {
+            method: HttpMethod::GET,
+            path: "/health",
+            version: "HTTP/1.1",
+            headers: Vec::new(),
+            body: b"",
+            extensions: Extensions::new(),
+            secure: false,
+            client_ip: None,
+            request_line: "",
+        };
+
+        let mut handler =
+            ConcurrencyLimit::with_backpressure(&semaphore, SimpleHandler, Backpressure::Reject);
+        let status = futures_lite::future::block_on(handler.handle_request(&request))
+            .unwrap()
+            .status_code;
+        assert_eq!(status, StatusCode::Ok);
+        // The permit is released once the request has been handled
+        assert!(semaphore.try_acquire(1).is_some());
+
+        // All permits taken by someone else
+        let held = semaphore.try_acquire(1).unwrap();
+        let status = futures_lite::future::block_on(handler.handle_request(&request))
+            .unwrap()
+            .status_code;
+        assert_eq!(status, StatusCode::ServiceUnavailable);
+        drop(held);
+
+        let mut handler = ConcurrencyLimit::new(&semaphore, SimpleHandler);
+        let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
+        assert_eq!(response.status_code, StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_concurrency_limit_one_permit_per_request() {
+        let semaphore = GreedySemaphore::<NoopRawMutex>::new(1);
+        let request = HttpRequest::try_from(&b"GET /health HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let mut handler =
+            ConcurrencyLimit::with_backpressure(&semaphore, SimpleHandler, Backpressure::Reject);
+        let mut out = [0u8; 256];
+
+        // Not streamed, so the permit is kept for `handle_request`
+        let mut socket = &mut out[..];
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        futures_lite::future::block_on(handler.handle_stream(&request, &mut writer)).unwrap();
+        assert!(!writer.is_started());
+        assert!(semaphore.try_acquire(1).is_none());
+        let status = futures_lite::future::block_on(handler.handle_request(&request))
+            .unwrap()
+            .status_code;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(semaphore.try_acquire(1).is_some());
+
+        // Rejected right away, without asking again in `handle_request`
+        let held = semaphore.try_acquire(1).unwrap();
+        let mut socket = &mut out[..];
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        futures_lite::future::block_on(handler.handle_stream(&request, &mut writer)).unwrap();
+        assert!(writer.is_started());
+        drop(held);
+        assert!(out.starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    struct User {
+        name: &'static str,
+    }
+
+    /// Wrapping handler attaching the authenticated user for the inner handler
+    struct Auth<H>(H);
+
+    impl<H: HttpHandlerMut> HttpHandlerMut for Auth<H> {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            let user = User { name: "fish" };
+            let mut request = request.clone();
+            let _ = request.extensions.insert(&user);
+            self.0.handle_request(&request).await
+        }
+    }
+
+    struct Greeter;
+
+    impl HttpHandlerMut for Greeter {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(match request.extensions.get::<User>() {
+                Some(user) if user.name == "fish" => HttpResponse::ok("hello fish"),
+                _ => HttpResponse::text(StatusCode::Unauthorized, "Unauthorized"),
+            })
+        }
+    }
+
+    #[test]
+    fn test_extensions_from_wrapping_handler() {
+        let request = HttpRequest::try_from(b"GET / HTTP/1.1\r\n\r\n".as_slice()).unwrap();
+
+        let status = futures_lite::future::block_on(Greeter.handle_request(&request))
+            .unwrap()
+            .status_code;
+        assert_eq!(status, StatusCode::Unauthorized);
+
+        let mut handler = Auth(Greeter);
+        let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
+        assert_eq!(response.body.as_str(), Some("hello fish"));
+    }
+
+    #[test]
+    fn test_fn_handler() {
+        let mut calls = 0;
+        let mut handler = fn_handler(async |request: &HttpRequest<'_>| {
+            calls += 1;
+            Ok(if request.path == "/ping" {
+                HttpResponse::ok("pong")
+            } else {
+                HttpResponse::not_found()
+            })
+        });
+
+        let request = HttpRequest::try_from(b"GET /ping HTTP/1.1\r\n\r\n".as_slice()).unwrap();
+        let pong = futures_lite::future::block_on(handler.handle_request(&request))
+            .unwrap()
+            .body
+            .as_str()
+            == Some("pong");
+        assert!(pong);
+
+        let request = HttpRequest::try_from(b"GET /other HTTP/1.1\r\n\r\n".as_slice()).unwrap();
+        let status = futures_lite::future::block_on(handler.handle_request(&request))
+            .unwrap()
+            .status_code;
+        assert_eq!(status, StatusCode::NotFound);
+
+        // The closure keeps its captured state between requests
+        assert_eq!(calls, 2);
+    }
+
+    fn redirect(
+        handler: &mut HttpsRedirect,
+        request: &[u8],
+    ) -> (StatusCode, Option<std::string::String>) {
+        let request = HttpRequest::try_from(request).unwrap();
+        let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
+        (
+            response.status_code,
+            response.get_header("Location").map(Into::into),
+        )
+    }
+
+    #[test]
+    fn test_https_redirect() {
+        let mut handler = HttpsRedirect::new(8443);
+        let (status, location) = redirect(
+            &mut handler,
+            b"POST /api/update?force=1 HTTP/1.1\r\nHost: device.local:8080\r\n\r\n",
+        );
+        assert_eq!(status, StatusCode::PermanentRedirect);
+        assert_eq!(
+            location.as_deref(),
+            Some("https://device.local:8443/api/update?force=1")
+        );
+
+        let mut handler = HttpsRedirect::new(443);
+        let (_, location) = redirect(
+            &mut handler,
+            b"GET / HTTP/1.1\r\nHost: [fe80::1]:80\r\n\r\n",
+        );
+        assert_eq!(location.as_deref(), Some("https://[fe80::1]/"));
+
+        let (status, location) = redirect(&mut handler, b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(status, StatusCode::BadRequest);
+        assert!(location.is_none());
+    }
+
+    #[test]
+    fn test_proxy_forwardable_headers() {
+        assert!(is_forwardable("Content-Type", None));
+        assert!(is_forwardable("Authorization", None));
+        assert!(is_forwardable("X-Custom", Some("close")));
+
+        for name in [
+            "Connection",
+            "transfer-encoding",
+            "Keep-Alive",
+            "Upgrade",
+            "TE",
+        ] {
+            assert!(!is_forwardable(name, None), "{name} is hop-by-hop");
+        }
+        assert!(!is_forwardable("Content-Length", None));
+        // Named in `Connection`, so only meant for this hop
+        assert!(!is_forwardable("X-Session", Some("keep-alive, x-session")));
+    }
+
+    /// Upstream answering with `response`, handed out a few bytes per read
+    struct FakeUpstream {
+        reachable: bool,
+        response: &'static [u8],
+        received: std::vec::Vec<u8>,
+    }
+
+    impl FakeUpstream {
+        fn new(response: &'static [u8]) -> Self {
+            Self {
+                reachable: true,
+                response,
+                received: std::vec::Vec::new(),
+            }
+        }
+    }
+
+    struct FakeConnection<'c> {
+        upstream: &'c mut FakeUpstream,
+        read: usize,
+    }
+
+    impl Upstream for FakeUpstream {
+        type Connection<'c> = FakeConnection<'c>;
+
+        async fn connect(&mut self, host: &str, port: u16) -> Result<FakeConnection<'_>, Error> {
+            assert_eq!((host, port), ("10.0.0.2", 8080));
+            if !self.reachable {
+                return Err(Error::NoResponse);
+            }
+            Ok(FakeConnection {
+                upstream: self,
+                read: 0,
+            })
+        }
+    }
+
+    impl embedded_io_async::ErrorType for FakeConnection<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for FakeConnection<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            // Splits the head and chunk framing across reads
+            let rest = &self.upstream.response[self.read..];
+            let n = rest.len().min(buf.len()).min(7);
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for FakeConnection<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.upstream.received.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Proxy `request` to `upstream`, returning what the upstream and the client got
+    fn proxy(
+        upstream: FakeUpstream,
+        request: &'static [u8],
+    ) -> (std::string::String, std::string::String) {
+        let request = HttpRequest::try_from(request).unwrap();
+        let mut proxy = Proxy::<_, 128>::new(upstream, "http://10.0.0.2:8080/api/");
+        let mut socket = TestSocket(std::vec::Vec::new());
+        // Close-delimited, so the relayed body arrives without new chunk framing
+        let mut writer = ResponseWriter::new(&mut socket, false, false);
+        futures_lite::future::block_on(proxy.handle_stream(&request, &mut writer)).unwrap();
+        (
+            std::string::String::from_utf8(proxy.upstream.received).unwrap(),
+            std::string::String::from_utf8(socket.0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_proxy_relays_response() {
+        let upstream = FakeUpstream::new(
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\
+              Connection: close, X-Hop\r\nX-Hop: 1\r\nX-Sensor: 7\r\n\r\nhello, ignored",
+        );
+        let (sent, received) = proxy(
+            upstream,
+            b"POST /pwm?duty=5 HTTP/1.1\r\nHost: device\r\nConnection: keep-alive\r\n\
+              X-Token: abc\r\nContent-Length: 2\r\n\r\n42",
+        );
+
+        assert_eq!(
+            sent,
+            "POST /api/pwm?duty=5 HTTP/1.1\r\nHost: 10.0.0.2:8080\r\nX-Token: abc\r\n\
+             Content-Length: 2\r\nConnection: close\r\n\r\n42"
+        );
+        assert_eq!(
+            received,
+            "HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\nX-Sensor: 7\r\n\
+             Connection: close\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn test_proxy_relays_chunked_and_close_delimited_bodies() {
+        // Larger than the proxy buffer, with a chunk extension and a trailer
+        let upstream = FakeUpstream::new(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              1a;name=value\r\nabcdefghijklmnopqrstuvwxyz\r\n\
+              40\r\n0123456789012345678901234567890123456789012345678901234567890123\r\n\
+              0\r\nX-Checksum: 1\r\n\r\n",
+        );
+        let (_, received) = proxy(upstream, b"GET /data HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n"));
+        assert!(received.ends_with(
+            "\r\n\r\nabcdefghijklmnopqrstuvwxyz\
+             0123456789012345678901234567890123456789012345678901234567890123"
+        ));
+
+        let upstream = FakeUpstream::new(b"HTTP/1.0 200 OK\r\n\r\nuntil the end");
+        let (_, received) = proxy(upstream, b"GET /data HTTP/1.1\r\n\r\n");
+        assert!(received.ends_with("\r\n\r\nuntil the end"));
+
+        // Cut off before the announced length
+        let upstream = FakeUpstream::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort");
+        let request = HttpRequest::try_from(&b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+        let mut proxy = Proxy::<_, 64>::new(upstream, "http://10.0.0.2:8080");
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        let result = futures_lite::future::block_on(proxy.handle_stream(&request, &mut writer));
+        assert!(result.is_err());
+        assert!(writer.is_started());
+    }
+
+    #[test]
+    fn test_proxy_bad_gateway() {
+        let mut unreachable = FakeUpstream::new(b"");
+        unreachable.reachable = false;
+        for upstream in [
+            unreachable,
+            FakeUpstream::new(b""),
+            FakeUpstream::new(b"SSH-2.0-OpenSSH\r\n\r\n"),
+            // The head does not fit into the proxy buffer
+            FakeUpstream::new(
+                b"HTTP/1.1 200 OK\r\nX-Long: 0123456789012345678901234567890123456789\r\n\
+                  X-Longer: 0123456789012345678901234567890123456789012345678901234567890\r\n\r\n",
+            ),
+        ] {
+            let (_, received) = proxy(upstream, b"GET / HTTP/1.1\r\n\r\n");
+            assert!(received.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+            assert!(received.ends_with("\r\n\r\nBad Gateway"));
+        }
+    }
+
+    /// Handler answering with the captured subdomain
+    struct Tenant;
+
+    impl HttpHandlerMut for Tenant {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(match request.extensions.get::<Subdomain>() {
+                Some(subdomain) if subdomain.as_str() == "sensor1" => HttpResponse::ok("sensor1"),
+                Some(_) => HttpResponse::ok("other tenant"),
+                None => HttpResponse::ok("no tenant"),
+            })
+        }
+    }
+
+    #[test]
+    fn test_virtual_host() {
+        let mut handler = VirtualHost::new(
+            "admin.local",
+            fn_handler(async |_: &HttpRequest<'_>| Ok(HttpResponse::ok("admin"))),
+            VirtualHost::new("*.local", Tenant, SimpleHandler),
+        );
+        let mut body = |host: &str| {
+            let head = format!("GET /health HTTP/1.1\r\nHost: {host}\r\n\r\n");
+            let request = HttpRequest::parse_from(&head, b"").unwrap();
+            let response = futures_lite::future::block_on(handler.handle_request(&request));
+            std::string::String::from(response.unwrap().body.as_str().unwrap())
+        };
+
+        assert_eq!(body("ADMIN.local:8080"), "admin");
+        assert_eq!(body("Sensor1.Local"), "sensor1");
+        assert_eq!(body("sensor1.local.:80"), "sensor1");
+        assert_eq!(body("sensor2.local"), "other tenant");
+        // The wildcard matches exactly one label
+        assert_eq!(body("local"), "{\"status\":\"ok\"}");
+        assert_eq!(body(".local"), "{\"status\":\"ok\"}");
+        assert_eq!(body("a.sensor1.local"), "{\"status\":\"ok\"}");
+        assert_eq!(body("sensor1.example"), "{\"status\":\"ok\"}");
+
+        let request = HttpRequest::try_from(b"GET /health HTTP/1.0\r\n\r\n".as_slice()).unwrap();
+        let response = futures_lite::future::block_on(handler.handle_request(&request)).unwrap();
+        assert_eq!(response.body.as_str(), Some("{\"status\":\"ok\"}"));
+    }
+
+    /// Handler with an `ETag`, to check it is not revealed to rejected clients
+    struct Tagged;
+
+    impl HttpHandlerMut for Tagged {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(HttpResponse::ok("secret data"))
+        }
+
+        async fn etag(&mut self, _request: &HttpRequest<'_>) -> Option<&str> {
+            Some("\"v1\"")
+        }
+    }
+
+    #[test]
+    fn test_require_header() {
+        let status = |handler: &mut RequireHeader<'_, Tagged>, head: &str| {
+            let request = HttpRequest::parse_from(head, b"").unwrap();
+            let etag = futures_lite::future::block_on(handler.etag(&request)).is_some();
+            let response = futures_lite::future::block_on(handler.handle_request(&request));
+            (response.unwrap().status_code, etag)
+        };
+
+        let mut handler = RequireHeader::with_secret("X-API-Key", "s3cret", Tagged);
+        assert_eq!(
+            status(&mut handler, "GET / HTTP/1.1\r\nx-api-key: s3cret\r\n\r\n"),
+            (StatusCode::Ok, true)
+        );
+        for head in [
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nX-API-Key: s3cre\r\n\r\n",
+            "GET / HTTP/1.1\r\nX-API-Key: s3cret!\r\n\r\n",
+            "GET / HTTP/1.1\r\nX-API-Key: S3CRET\r\n\r\n",
+        ] {
+            assert_eq!(
+                status(&mut handler, head),
+                (StatusCode::Unauthorized, false)
+            );
+        }
+
+        let mut handler = RequireHeader::new("X-Request-Id", Tagged);
+        assert_eq!(
+            status(&mut handler, "GET / HTTP/1.1\r\nX-Request-Id: 42\r\n\r\n").0,
+            StatusCode::Ok
+        );
+        assert_eq!(
+            status(&mut handler, "GET / HTTP/1.1\r\n\r\n").0,
+            StatusCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"key", b"key"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"key", b"kez"));
+        assert!(!constant_time_eq(b"key", b"ke"));
+        assert!(!constant_time_eq(b"key", b"key\0"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
 }
@@ -20,6 +20,86 @@ pub mod headers {
     pub const X_API_KEY: &str = "X-API-Key";
     /// Accept-Encoding header
     pub const ACCEPT_ENCODING: &str = "Accept-Encoding";
+    /// Location header
+    pub const LOCATION: &str = "Location";
+    /// `ETag` header
+    pub const ETAG: &str = "ETag";
+    /// If-None-Match header
+    pub const IF_NONE_MATCH: &str = "If-None-Match";
+    /// Content-Disposition header
+    pub const CONTENT_DISPOSITION: &str = "Content-Disposition";
+    /// Vary header
+    pub const VARY: &str = "Vary";
+    /// Accept-Charset header
+    pub const ACCEPT_CHARSET: &str = "Accept-Charset";
+    /// Accept-Language header
+    pub const ACCEPT_LANGUAGE: &str = "Accept-Language";
+    /// Content-Language header
+    pub const CONTENT_LANGUAGE: &str = "Content-Language";
+    /// Content-Encoding header
+    pub const CONTENT_ENCODING: &str = "Content-Encoding";
+    /// Range header
+    pub const RANGE: &str = "Range";
+    /// If-Range header
+    pub const IF_RANGE: &str = "If-Range";
+    /// Content-Range header
+    pub const CONTENT_RANGE: &str = "Content-Range";
+    /// Accept-Ranges header
+    pub const ACCEPT_RANGES: &str = "Accept-Ranges";
+    /// Date header
+    pub const DATE: &str = "Date";
+    /// Set-Cookie header
+    pub const SET_COOKIE: &str = "Set-Cookie";
+    /// Transfer-Encoding header
+    pub const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+    /// Upgrade header
+    pub const UPGRADE: &str = "Upgrade";
+    /// Keep-Alive header
+    pub const KEEP_ALIVE: &str = "Keep-Alive";
+    /// X-Forwarded-For header
+    pub const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+    /// X-Forwarded-Proto header
+    pub const X_FORWARDED_PROTO: &str = "X-Forwarded-Proto";
+    /// X-Checksum-SHA256 header, the hex SHA-256 digest of an uploaded body
+    pub const X_CHECKSUM_SHA256: &str = "X-Checksum-SHA256";
+
+    /// Every name above, for restoring their canonical casing
+    pub(crate) const KNOWN: [&str; 31] = [
+        CONTENT_TYPE,
+        AUTHORIZATION,
+        USER_AGENT,
+        ACCEPT,
+        CONTENT_LENGTH,
+        CACHE_CONTROL,
+        HOST,
+        CONNECTION,
+        X_API_KEY,
+        ACCEPT_ENCODING,
+        LOCATION,
+        ETAG,
+        IF_NONE_MATCH,
+        CONTENT_DISPOSITION,
+        VARY,
+        ACCEPT_CHARSET,
+        ACCEPT_LANGUAGE,
+        CONTENT_LANGUAGE,
+        CONTENT_ENCODING,
+        RANGE,
+        IF_RANGE,
+        CONTENT_RANGE,
+        ACCEPT_RANGES,
+        DATE,
+        SET_COOKIE,
+        TRANSFER_ENCODING,
+        UPGRADE,
+        KEEP_ALIVE,
+        X_FORWARDED_FOR,
+        X_FORWARDED_PROTO,
+        X_CHECKSUM_SHA256,
+    ];
+
+    /// Response headers sent before all others, in this order
+    pub(crate) const LEADING: [&str; 3] = [CONTENT_TYPE, DATE, CONNECTION];
 }
 
 /// Common MIME types for Content-Type header values
@@ -87,6 +167,82 @@ impl<'a> HttpHeader<'a> {
     pub const fn api_key(value: &'a str) -> Self {
         Self::new(headers::X_API_KEY, value)
     }
+
+    /// Check that the header can be sent as is
+    ///
+    /// The name must be a non-empty token and the value must not contain control
+    /// characters other than tab. In particular neither may contain CR or LF, which
+    /// would let the header end early and inject further headers into the message.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let token = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+        !self.name.is_empty()
+            && self.name.bytes().all(token)
+            && self
+                .value
+                .bytes()
+                .all(|b| b == b'\t' || !b.is_ascii_control())
+    }
+
+    /// The header name in its canonical casing if it is a well-known one
+    ///
+    /// `content-type` becomes `Content-Type`; unknown names are returned unchanged.
+    #[must_use]
+    pub fn canonical_name(&self) -> &'a str {
+        headers::KNOWN
+            .into_iter()
+            .find(|known| known.eq_ignore_ascii_case(self.name))
+            .unwrap_or(self.name)
+    }
+
+    /// Check if this is a `Connection` header with the `close` option
+    pub(crate) fn closes_connection(&self) -> bool {
+        self.name.eq_ignore_ascii_case(headers::CONNECTION)
+            && self
+                .value
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+    }
+}
+
+/// The headers named `name`, in the order they were added
+pub(crate) fn named<'h, 'a>(
+    headers: &'h [HttpHeader<'a>],
+    name: &'h str,
+) -> impl Iterator<Item = &'h HttpHeader<'a>> {
+    headers
+        .iter()
+        .filter(move |h| h.name.eq_ignore_ascii_case(name))
+}
+
+/// The headers not in [`headers::LEADING`], in the order they were added
+///
+/// With `framed`, a `Content-Length` added by the handler is skipped as well: the
+/// server frames the body itself then, deriving the length from the body it actually
+/// sends, which may differ, e.g. once the body has been compressed.
+pub(crate) fn others<'h, 'a>(
+    headers: &'h [HttpHeader<'a>],
+    framed: bool,
+) -> impl Iterator<Item = &'h HttpHeader<'a>> {
+    headers.iter().filter(move |h| {
+        !(headers::LEADING
+            .iter()
+            .any(|leading| h.name.eq_ignore_ascii_case(leading))
+            || framed && h.name.eq_ignore_ascii_case(headers::CONTENT_LENGTH))
+    })
+}
+
+/// Response headers in output order: [`headers::LEADING`] first, then the rest
+///
+/// See [`others`] for `framed`.
+pub(crate) fn ordered<'h, 'a>(
+    headers: &'h [HttpHeader<'a>],
+    framed: bool,
+) -> impl Iterator<Item = &'h HttpHeader<'a>> {
+    headers::LEADING
+        .into_iter()
+        .flat_map(|name| named(headers, name))
+        .chain(others(headers, framed))
 }
 
 #[cfg(test)]
@@ -102,4 +258,56 @@ mod tests {
         assert_eq!(header.name, "Content-Type");
         assert_eq!(header.value, "application/json");
     }
+
+    #[test]
+    fn test_header_validation() {
+        assert!(HttpHeader::new("Set-Cookie", "id=1; Path=/; HttpOnly").is_valid());
+        assert!(HttpHeader::new("X-Empty", "").is_valid());
+        assert!(HttpHeader::new("X-Tab", "a\tb").is_valid());
+
+        assert!(!HttpHeader::new("X-Evil", "a\r\nSet-Cookie: admin=1").is_valid());
+        assert!(!HttpHeader::new("X-Evil", "a\nb").is_valid());
+        assert!(!HttpHeader::new("X-Evil", "a\0b").is_valid());
+        assert!(!HttpHeader::new("X-Evil\r\nX-Other", "1").is_valid());
+        assert!(!HttpHeader::new("Has Space", "1").is_valid());
+        assert!(!HttpHeader::new("X:Colon", "1").is_valid());
+        assert!(!HttpHeader::new("", "1").is_valid());
+    }
+
+    #[test]
+    fn test_canonical_name() {
+        assert_eq!(
+            HttpHeader::new("content-type", "").canonical_name(),
+            "Content-Type"
+        );
+        assert_eq!(HttpHeader::new("ETAG", "").canonical_name(), "ETag");
+        assert_eq!(
+            HttpHeader::new("x-api-key", "").canonical_name(),
+            "X-API-Key"
+        );
+        assert_eq!(HttpHeader::new("x-custom", "").canonical_name(), "x-custom");
+    }
+
+    #[test]
+    fn test_response_header_order() {
+        let headers = [
+            HttpHeader::new("X-B", "1"),
+            HttpHeader::new("connection", "close"),
+            HttpHeader::new("X-A", "2"),
+            HttpHeader::new("Content-Type", "text/plain"),
+            HttpHeader::new("content-length", "4096"),
+        ];
+        let names: std::vec::Vec<_> = ordered(&headers, true)
+            .map(HttpHeader::canonical_name)
+            .collect();
+        assert_eq!(names, ["Content-Type", "Connection", "X-B", "X-A"]);
+    }
+
+    #[test]
+    fn test_closes_connection() {
+        assert!(HttpHeader::new("Connection", "close").closes_connection());
+        assert!(HttpHeader::new("connection", "TE, Close").closes_connection());
+        assert!(!HttpHeader::new("Connection", "keep-alive").closes_connection());
+        assert!(!HttpHeader::new("X-Connection", "close").closes_connection());
+    }
 }
@@ -0,0 +1,375 @@
+use crate::{
+    error::Error,
+    handler::HttpHandlerMut,
+    server::{Backoff, Disconnect, HttpServer, RequestTimings, ServerStop, elapsed},
+};
+use embassy_net::IpAddress;
+use embassy_time::{Instant, Timer};
+use std::{
+    io::{self, Read as _, Write as _},
+    net::{IpAddr, Shutdown, TcpListener, TcpStream},
+    time::Duration,
+};
+
+/// Blocking `std::net::TcpStream` exposed through the `embedded-io-async` traits
+struct HostSocket(TcpStream);
+
+impl embedded_io_async::ErrorType for HostSocket {
+    type Error = io::Error;
+}
+
+impl embedded_io_async::Read for HostSocket {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf)
+    }
+}
+
+impl embedded_io_async::Write for HostSocket {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.write(buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+/// Convert a peer address for the per-IP connection limit, mapping IPv4 into IPv6
+fn remote_address(stream: &TcpStream) -> Option<IpAddress> {
+    let ip = match stream.peer_addr().ok()?.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    Some(IpAddress::Ipv6(ip))
+}
+
+impl<
+    const RX_SIZE: usize,
+    const TX_SIZE: usize,
+    const REQ_SIZE: usize,
+    const MAX_RESPONSE_SIZE: usize,
+> HttpServer<RX_SIZE, TX_SIZE, REQ_SIZE, MAX_RESPONSE_SIZE>
+{
+    /// Serve connections accepted on a `std::net::TcpListener`
+    ///
+    /// The host counterpart of [`HttpServer::serve`] for integration tests and local
+    /// development: requests are parsed, handled and answered exactly as on the device,
+    /// so the server can be exercised with a real HTTP client. The listener's address
+    /// is used instead of the server's port.
+    ///
+    /// Socket I/O blocks the calling thread, so run the server on a thread of its own
+    /// with a simple executor such as `futures_lite::future::block_on`. The read
+    /// timeout is applied to the socket itself; the handler timeout only takes effect
+    /// while the handler awaits. Without an Embassy stack,
+    /// [`HttpHandlerMut::on_start`] is not called.
+    pub async fn serve_std<H>(&mut self, listener: &TcpListener, mut handler: H) -> !
+    where
+        H: HttpHandlerMut,
+    {
+        self.stop = None;
+        loop {
+            // Only a `ServerStop` ends serving, and there is none
+            self.run_std(listener, &mut handler).await;
+        }
+    }
+
+    /// Serve like [`serve_std`](Self::serve_std) until `stop` is triggered
+    ///
+    /// The host counterpart of [`HttpServer::serve_until`]. A triggered switch is only
+    /// noticed after a request or once the next connection is accepted, as accepting
+    /// blocks the thread.
+    pub async fn serve_std_until<H>(
+        &mut self,
+        listener: &TcpListener,
+        mut handler: H,
+        stop: &'static ServerStop,
+    ) where
+        H: HttpHandlerMut,
+    {
+        self.stop = Some(stop);
+        self.run_std(listener, &mut handler).await;
+        self.stop = None;
+    }
+
+    /// Accept and answer connections on `listener` until the server is stopped
+    async fn run_std<H>(&mut self, listener: &TcpListener, handler: &mut H)
+    where
+        H: HttpHandlerMut,
+    {
+        let port = listener.local_addr().map_or(0, |addr| addr.port());
+        info!("HTTP server started on port {} (std)", port);
+
+        let mut buf = [0; REQ_SIZE];
+        let mut backoff = Backoff::new();
+        let mut connections = 0;
+
+        while !self.stopping() {
+            if let Some(pause) = self.options.accept_pause {
+                pause.wait_resumed().await;
+            }
+
+            let mut timings = RequestTimings::default();
+            let start = Instant::now();
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    let delay = backoff.next();
+                    if backoff.failures.is_power_of_two() {
+                        warn!(
+                            "Accept error: {:?} ({} in a row)",
+                            Error::IoError(embedded_io_async::Error::kind(&e)),
+                            backoff.failures
+                        );
+                    }
+                    Timer::after(delay).await;
+                    continue;
+                }
+            };
+            backoff.reset();
+            timings.accept = elapsed(start);
+
+            let timeout = Some(Duration::from_secs(self.timeouts.read_timeout));
+            let _ = stream.set_read_timeout(timeout);
+            let _ = stream.set_write_timeout(timeout);
+            let remote = remote_address(&stream);
+            let mut socket = HostSocket(stream);
+            if self
+                .serve_connection(&mut socket, remote, handler, &mut buf, timings)
+                .await
+                == Disconnect::Close
+            {
+                let _ = socket.0.shutdown(Shutdown::Write);
+            }
+
+            connections += 1;
+            if self.maintenance_due(connections) {
+                buf.fill(0);
+                handler.on_maintenance().await;
+                connections = 0;
+            }
+        }
+        info!("HTTP server on port {} stopped (std)", port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AcceptPause, DefaultHttpServer, HttpHandler, HttpRequest, HttpResponse, ServerOptions,
+        ServerTimeouts, SimpleHandler, fn_handler,
+    };
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    fn request(listener: TcpListener, request: &[u8]) -> std::string::String {
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut server: DefaultHttpServer = HttpServer::new(0);
+            futures_lite::future::block_on(server.serve_std(&listener, SimpleHandler))
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(request).unwrap();
+        let mut response = std::string::String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_std() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let response = request(listener, b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("{\"status\":\"ok\"}"));
+    }
+
+    #[test]
+    fn test_serve_std_sequential_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let response = request(listener, b"GET /missing HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = std::string::String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Hello from nanofish"));
+    }
+
+    #[test]
+    fn test_serve_std_accept_pause() {
+        static PAUSE: AcceptPause = AcceptPause::new();
+        PAUSE.pause();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let options = ServerOptions {
+                accept_pause: Some(&PAUSE),
+                ..ServerOptions::default()
+            };
+            let mut server: DefaultHttpServer =
+                HttpServer::with_options(0, ServerTimeouts::default(), options);
+            futures_lite::future::block_on(server.serve_std(&listener, SimpleHandler))
+        });
+
+        // The OS completes the handshake, but the server does not pick the connection up
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut byte = [0u8];
+        assert!(client.read(&mut byte).is_err());
+
+        PAUSE.resume();
+        client.set_read_timeout(None).unwrap();
+        let mut response = std::string::String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Hello from nanofish"));
+    }
+
+    #[test]
+    fn test_serve_std_until_stopped() {
+        static STOP: ServerStop = ServerStop::new();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let options = ServerOptions {
+                keep_alive_timeout: Some(5),
+                ..ServerOptions::default()
+            };
+            let mut server: DefaultHttpServer =
+                HttpServer::with_options(0, ServerTimeouts::default(), options);
+            let handler = fn_handler(async |request: &HttpRequest<'_>| {
+                if request.path == "/reboot" {
+                    STOP.stop();
+                }
+                Ok(HttpResponse::ok("done"))
+            });
+            futures_lite::future::block_on(server.serve_std_until(&listener, handler, &STOP));
+        });
+
+        // The confirmation arrives and the kept-alive connection is closed after it
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /status HTTP/1.1\r\n\r\nGET /reboot HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut response = std::string::String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(response.ends_with("Connection: close\r\n\r\ndone"));
+
+        server.join().unwrap();
+        assert!(STOP.is_stopped());
+    }
+
+    #[test]
+    fn test_serve_std_until_shared_stop() {
+        static STOP: ServerStop = ServerStop::new();
+        static WAITING: AtomicBool = AtomicBool::new(false);
+        let spawn = |listener: TcpListener| {
+            std::thread::spawn(move || {
+                let options = ServerOptions {
+                    keep_alive_timeout: Some(5),
+                    ..ServerOptions::default()
+                };
+                let mut server: DefaultHttpServer =
+                    HttpServer::with_options(0, ServerTimeouts::default(), options);
+                let handler = fn_handler(async |request: &HttpRequest<'_>| {
+                    match request.path {
+                        "/reboot" => STOP.stop(),
+                        "/wait" => {
+                            WAITING.store(true, Ordering::Relaxed);
+                            while !STOP.is_stopped() {
+                                std::thread::sleep(Duration::from_millis(10));
+                            }
+                        }
+                        _ => {}
+                    }
+                    Ok(HttpResponse::ok("done"))
+                });
+                futures_lite::future::block_on(server.serve_std_until(&listener, handler, &STOP));
+            })
+        };
+        let exchange = |addr, request: &[u8]| {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(request).unwrap();
+            let mut response = std::string::String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+        let first = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (first_addr, second_addr) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+        let (first, second) = (spawn(first), spawn(second));
+
+        // The second task is busy with a request while the first one is stopped
+        let waiting = std::thread::spawn(move || {
+            exchange(
+                second_addr,
+                b"GET /wait HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            )
+        });
+        while !WAITING.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        exchange(first_addr, b"GET /reboot HTTP/1.1\r\n\r\n");
+        first.join().unwrap();
+
+        // It stops as well instead of answering the pipelined request
+        let response = waiting.join().unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+        second.join().unwrap();
+
+        STOP.reset();
+        assert!(!STOP.is_stopped());
+    }
+
+    #[test]
+    fn test_serve_std_maintenance() {
+        static STOP: ServerStop = ServerStop::new();
+        static MAINTENANCE: AtomicUsize = AtomicUsize::new(0);
+
+        struct Handler;
+
+        impl HttpHandler for Handler {
+            async fn handle_request(
+                &self,
+                request: &HttpRequest<'_>,
+            ) -> Result<HttpResponse<'_>, Error> {
+                if request.path == "/stop" {
+                    STOP.stop();
+                }
+                Ok(HttpResponse::ok("done"))
+            }
+
+            async fn on_maintenance(&self) {
+                MAINTENANCE.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let options = ServerOptions {
+                maintenance_interval: Some(2),
+                ..ServerOptions::default()
+            };
+            let mut server: DefaultHttpServer =
+                HttpServer::with_options(0, ServerTimeouts::default(), options);
+            futures_lite::future::block_on(server.serve_std_until(&listener, Handler, &STOP));
+        });
+
+        for path in ["/", "/", "/", "/", "/stop"] {
+            let mut client = TcpStream::connect(addr).unwrap();
+            write!(client, "GET {path} HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = std::string::String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.ends_with("done"));
+        }
+
+        server.join().unwrap();
+        assert_eq!(MAINTENANCE.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,38 +1,82 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![doc = include_str!("../README.md")]
 #![warn(missing_docs)]
 
 /// Logging macros
 pub(crate) mod fmt;
 
+/// `Cache-Control` directive parsing and formatting.
+pub mod cache;
+/// SHA-256 verification of request bodies.
+#[cfg(feature = "checksum")]
+pub mod checksum;
 /// HTTP client implementation and request logic.
 pub mod client;
+/// Deadlines shared by several operations
+pub(crate) mod deadline;
 /// Error types for HTTP operations.
 pub mod error;
+/// Serving files from a filesystem.
+pub mod file;
 /// HTTP request handlers and traits.
 pub mod handler;
 /// HTTP header types and helpers.
 pub mod header;
+/// Running the server on a host over `std::net`.
+#[cfg(feature = "std")]
+pub mod host;
 /// HTTP method enum and helpers.
 pub mod method;
 /// HTTP client configuration options.
 pub mod options;
+/// Query string deserialization
+#[cfg(feature = "serde")]
+pub(crate) mod query;
+/// `Range` header parsing.
+pub mod range;
 /// HTTP request types and parsing.
 pub mod request;
 /// HTTP response types and body handling.
 pub mod response;
 /// HTTP server implementation.
 pub mod server;
+/// Mutable state shared by handlers.
+pub mod state;
 /// Predefined HTTP status codes as per RFC 2616.
 pub mod status_code;
+/// Percent-encoded URL building.
+pub mod url;
+/// Streaming HTTP response writer.
+pub mod writer;
 
+/// Connection fixtures shared by the unit tests
+#[cfg(test)]
+mod test_util;
+
+pub use cache::CacheControl;
+#[cfg(feature = "checksum")]
+pub use checksum::BodyChecksum;
 pub use client::{DefaultHttpClient, HttpClient, SmallHttpClient};
 pub use error::Error;
-pub use handler::{HttpHandler, SimpleHandler};
+pub use file::{
+    DEFAULT_CHUNK_SIZE, DEFAULT_INDEX, FileMetadata, FileProvider, FileServer, content_type_for,
+};
+pub use handler::{
+    Backpressure, ConcurrencyLimit, FnHandler, HttpHandler, HttpHandlerMut, HttpsRedirect, Proxy,
+    RequireHeader, SimpleHandler, Subdomain, TcpUpstream, Upstream, VirtualHost, fn_handler,
+};
 pub use header::{HttpHeader, headers, mime_types};
 pub use method::HttpMethod;
 pub use options::HttpClientOptions;
-pub use request::HttpRequest;
-pub use response::{HttpResponse, ResponseBody};
-pub use server::{DefaultHttpServer, HttpServer, ServerTimeouts, SmallHttpServer};
+pub use range::ByteRange;
+pub use request::{Extensions, HttpRequest};
+pub use response::{HttpResponse, LazyBody, ResponseBody, ResponseSerializer};
+pub use server::{
+    AcceptPause, DefaultHttpServer, HttpServer, LargeRequestBuffer, MAX_TRACKED_IPS,
+    PerIpConnectionLimit, RequestTimings, ServerOptions, ServerStop, ServerTimeouts,
+    SmallHttpServer,
+};
+pub use state::SharedState;
 pub use status_code::StatusCode;
+pub use url::UrlBuilder;
+pub use writer::ResponseWriter;
@@ -54,6 +54,12 @@ impl HttpMethod {
             HttpMethod::HEAD => "HEAD",
         }
     }
+
+    /// Returns `true` for methods whose semantics define a request body (POST, PUT, PATCH).
+    #[must_use]
+    pub fn allows_body(self) -> bool {
+        matches!(self, HttpMethod::POST | HttpMethod::PUT | HttpMethod::PATCH)
+    }
 }
 
 impl TryFrom<&str> for HttpMethod {
@@ -111,6 +117,19 @@ mod tests {
         assert_eq!(HttpMethod::HEAD.as_str(), "HEAD");
     }
 
+    #[test]
+    fn test_allows_body() {
+        assert!(HttpMethod::POST.allows_body());
+        assert!(HttpMethod::PUT.allows_body());
+        assert!(HttpMethod::PATCH.allows_body());
+        assert!(!HttpMethod::GET.allows_body());
+        assert!(!HttpMethod::HEAD.allows_body());
+        assert!(!HttpMethod::DELETE.allows_body());
+        assert!(!HttpMethod::OPTIONS.allows_body());
+        assert!(!HttpMethod::TRACE.allows_body());
+        assert!(!HttpMethod::CONNECT.allows_body());
+    }
+
     #[test]
     fn test_try_from_str() {
         // Test valid HTTP methods
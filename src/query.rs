@@ -0,0 +1,383 @@
+use crate::url::{Component, DecodeError, percent_decode};
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor,
+    value::StrDeserializer,
+};
+
+/// Maximum length of a single form field name or value after percent-decoding
+pub(crate) const MAX_FORM_VALUE_SIZE: usize = 256;
+
+const INVALID: &str = "Invalid query parameter";
+const MISSING: &str = "Missing query parameter";
+const TOO_LONG: &str = "Query value too long";
+const INVALID_UTF8: &str = "Invalid UTF-8 in query value";
+
+/// Error raised while deserializing a query string
+#[derive(Debug)]
+pub(crate) struct QueryError(pub(crate) &'static str);
+
+impl QueryError {
+    /// The message to report if the pairs came from a form body
+    pub(crate) fn form_message(&self) -> &'static str {
+        match self.0 {
+            INVALID => "Invalid form field",
+            MISSING => "Missing form field",
+            TOO_LONG => "Form value too long",
+            INVALID_UTF8 => "Invalid UTF-8 in form value",
+            other => other,
+        }
+    }
+}
+
+impl core::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl core::error::Error for QueryError {}
+
+impl de::Error for QueryError {
+    fn custom<T: core::fmt::Display>(_msg: T) -> Self {
+        QueryError(INVALID)
+    }
+
+    fn missing_field(_field: &'static str) -> Self {
+        QueryError(MISSING)
+    }
+}
+
+/// Deserializer reading a struct or map from `key=value&key=value` pairs
+///
+/// Used for query strings and `application/x-www-form-urlencoded` bodies alike:
+/// `%XX` escapes are resolved and `+` stands for a space. Text without either is
+/// borrowed as-is.
+pub(crate) struct QueryDeserializer<'de> {
+    query: &'de str,
+}
+
+impl<'de> QueryDeserializer<'de> {
+    pub(crate) fn new(query: &'de str) -> Self {
+        Self { query }
+    }
+}
+
+impl<'de> Deserializer<'de> for QueryDeserializer<'de> {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(Pairs {
+            remaining: self.query.split('&'),
+            value: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Map access over the pairs of a query string
+struct Pairs<'de> {
+    remaining: core::str::Split<'de, char>,
+    value: Option<&'de str>,
+}
+
+impl<'de> MapAccess<'de> for Pairs<'de> {
+    type Error = QueryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some(pair) = self.remaining.find(|pair| !pair.is_empty()) else {
+            return Ok(None);
+        };
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        self.value = Some(value);
+        seed.deserialize(Value::new(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or(QueryError("Query value without key"))?;
+        seed.deserialize(Value::new(value))
+    }
+}
+
+/// Decode a form-encoded `value` into `buf`, turning `+` into a space
+fn decode_form_value<'b>(value: &str, buf: &'b mut [u8]) -> Result<&'b str, QueryError> {
+    percent_decode(value, Component::Query, buf).map_err(|e| {
+        QueryError(match e {
+            DecodeError::InvalidEscape => "Invalid percent-encoding",
+            DecodeError::TooLong => TOO_LONG,
+            DecodeError::InvalidUtf8 => INVALID_UTF8,
+        })
+    })
+}
+
+/// Text of a key or value, borrowed from the input unless it had to be decoded
+enum Text<'de, 'b> {
+    Borrowed(&'de str),
+    Decoded(&'b str),
+}
+
+impl Text<'_, '_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Text::Borrowed(text) | Text::Decoded(text) => text,
+        }
+    }
+}
+
+/// Deserializer for a single key or value, parsing numbers and booleans from text
+struct Value<'de> {
+    raw: &'de str,
+}
+
+impl<'de> Value<'de> {
+    fn new(raw: &'de str) -> Self {
+        Self { raw }
+    }
+
+    /// Get the text, decoding it into `buf` if it contains escapes
+    fn text<'b>(&self, buf: &'b mut [u8]) -> Result<Text<'de, 'b>, QueryError> {
+        if self.raw.contains(['%', '+']) {
+            decode_form_value(self.raw, buf).map(Text::Decoded)
+        } else {
+            Ok(Text::Borrowed(self.raw))
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                let mut buf = [0; MAX_FORM_VALUE_SIZE];
+                let value = self
+                    .text(&mut buf)?
+                    .as_str()
+                    .parse()
+                    .map_err(|_| QueryError(INVALID))?;
+                visitor.$visit(value)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Value<'de> {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut buf = [0; MAX_FORM_VALUE_SIZE];
+        match self.text(&mut buf)? {
+            Text::Borrowed(text) => visitor.visit_borrowed_str(text),
+            // Only owned fields can hold decoded text
+            Text::Decoded(text) => visitor.visit_str(text),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        // A present key always carries a value, even if it is empty
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let mut buf = [0; MAX_FORM_VALUE_SIZE];
+        let text = self.text(&mut buf)?;
+        let variant: StrDeserializer<'_, QueryError> = text.as_str().into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Pagination {
+        page: u32,
+        size: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Order {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Filters<'a> {
+        name: &'a str,
+        active: bool,
+        order: Option<Order>,
+        #[serde(default)]
+        limit: u8,
+    }
+
+    #[test]
+    fn test_deserialize_struct() {
+        let pagination = Pagination::deserialize(QueryDeserializer::new("page=2&size=10")).unwrap();
+        assert_eq!(pagination, Pagination { page: 2, size: 10 });
+    }
+
+    #[test]
+    fn test_deserialize_optional_and_default_fields() {
+        let filters =
+            Filters::deserialize(QueryDeserializer::new("name=bob&&active=true")).unwrap();
+        assert_eq!(
+            filters,
+            Filters {
+                name: "bob",
+                active: true,
+                order: None,
+                limit: 0,
+            }
+        );
+
+        let filters = Filters::deserialize(QueryDeserializer::new(
+            "active=false&order=desc&name=&limit=5",
+        ))
+        .unwrap();
+        assert_eq!(filters.name, "");
+        assert_eq!(filters.order, Some(Order::Desc));
+        assert_eq!(filters.limit, 5);
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let err = Pagination::deserialize(QueryDeserializer::new("page=2")).unwrap_err();
+        assert_eq!(err.0, "Missing query parameter");
+
+        let err = Pagination::deserialize(QueryDeserializer::new("page=two&size=10")).unwrap_err();
+        assert_eq!(err.0, "Invalid query parameter");
+
+        let err = Filters::deserialize(QueryDeserializer::new("name=a&active=true&order=up"))
+            .unwrap_err();
+        assert_eq!(err.0, "Invalid query parameter");
+    }
+
+    /// String field owning its text, as decoded form values cannot be borrowed
+    #[derive(Debug, PartialEq)]
+    struct Owned(std::string::String);
+
+    impl<'de> Deserialize<'de> for Owned {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct OwnedVisitor;
+
+            impl Visitor<'_> for OwnedVisitor {
+                type Value = Owned;
+
+                fn expecting(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    f.write_str("a string")
+                }
+
+                fn visit_str<E: de::Error>(self, text: &str) -> Result<Owned, E> {
+                    Ok(Owned(text.into()))
+                }
+            }
+
+            deserializer.deserialize_str(OwnedVisitor)
+        }
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Settings {
+        ssid: Owned,
+        channel: u8,
+        order: Order,
+        note: Option<Owned>,
+    }
+
+    #[test]
+    fn test_deserialize_form() {
+        let settings = Settings::deserialize(QueryDeserializer::new(
+            "ssid=My+Home%20Wi%2DFi&channel=%31%31&order=%61sc&note=a%2Bb",
+        ))
+        .unwrap();
+        assert_eq!(settings.ssid.0, "My Home Wi-Fi");
+        assert_eq!(settings.channel, 11);
+        assert_eq!(settings.order, Order::Asc);
+        assert_eq!(settings.note.unwrap().0, "a+b");
+
+        // Values without escapes can still be borrowed
+        let filters = Filters::deserialize(QueryDeserializer::new("name=bob&active=true")).unwrap();
+        assert_eq!(filters.name, "bob");
+
+        // Query strings are decoded the same way
+        let settings = Settings::deserialize(QueryDeserializer::new(
+            "ssid=John%20Doe&channel=6&order=%64esc",
+        ))
+        .unwrap();
+        assert_eq!(settings.ssid.0, "John Doe");
+        assert_eq!(settings.order, Order::Desc);
+    }
+
+    #[test]
+    fn test_deserialize_form_errors() {
+        let err = Filters::deserialize(QueryDeserializer::new("name=a%2&active=true")).unwrap_err();
+        assert_eq!(err.0, "Invalid percent-encoding");
+
+        let err = Filters::deserialize(QueryDeserializer::new("name=%FF&active=true")).unwrap_err();
+        assert_eq!(err.form_message(), "Invalid UTF-8 in form value");
+
+        // A decoded value cannot be borrowed
+        let err = Filters::deserialize(QueryDeserializer::new("name=a+b&active=true")).unwrap_err();
+        assert_eq!(err.form_message(), "Invalid form field");
+
+        let err = Pagination::deserialize(QueryDeserializer::new("page=1")).unwrap_err();
+        assert_eq!(err.form_message(), "Missing form field");
+
+        let mut buf = [0; 3];
+        assert_eq!(decode_form_value("a+%41", &mut buf).unwrap(), "a A");
+        assert_eq!(
+            decode_form_value("abcd", &mut buf).unwrap_err().0,
+            "Query value too long"
+        );
+    }
+}
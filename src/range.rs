@@ -0,0 +1,182 @@
+use crate::status_code::StatusCode;
+use core::fmt;
+
+/// A single byte range of a resource, resolved from a `Range` request header
+///
+/// All three forms of a byte range are understood: `bytes=0-499` (the first 500
+/// bytes), `bytes=500-` (everything from byte 500) and the suffix range `bytes=-500`
+/// (the last 500 bytes). The range is clamped to the length of the resource, and
+/// `Display` formats the matching `Content-Range` value:
+///
+/// ```rust
+/// use nanofish::ByteRange;
+///
+/// let range = ByteRange::parse("bytes=-500", 2000).unwrap().unwrap();
+/// assert_eq!((range.start, range.end), (1500, 1999));
+/// assert_eq!(range.to_string(), "bytes 1500-1999/2000");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Offset of the first byte
+    pub start: u64,
+    /// Offset of the last byte, inclusive
+    pub end: u64,
+    /// Length of the whole resource
+    pub length: u64,
+}
+
+impl ByteRange {
+    /// Resolve a `Range` header value against a resource of `length` bytes
+    ///
+    /// Returns `Ok(None)` for values that should be ignored, so the full resource is
+    /// sent: other units than `bytes`, malformed ranges and multiple ranges, which
+    /// would need a `multipart/byteranges` response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StatusCode::RequestedRangeNotSatisfiable`] if the range lies
+    /// entirely beyond the end of the resource, or is an empty suffix range
+    /// (`bytes=-0`). Answer it with a `Content-Range` of
+    /// [`ByteRange::unsatisfied`].
+    pub fn parse(value: &str, length: u64) -> Result<Option<Self>, StatusCode> {
+        let Some((unit, spec)) = value.trim().split_once('=') else {
+            return Ok(None);
+        };
+        if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+            return Ok(None);
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return Ok(None);
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let number = |s: &str| {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                None
+            } else {
+                // Larger than any resource, so clamping applies
+                Some(s.parse::<u64>().unwrap_or(u64::MAX))
+            }
+        };
+        let unsatisfiable = Err(StatusCode::RequestedRangeNotSatisfiable);
+
+        let (start, end) = if first.is_empty() {
+            // `bytes=-suffix`
+            let Some(suffix) = number(last) else {
+                return Ok(None);
+            };
+            if suffix == 0 || length == 0 {
+                return unsatisfiable;
+            }
+            (length.saturating_sub(suffix), length - 1)
+        } else {
+            // `bytes=start-end` and `bytes=start-`
+            let Some(start) = number(first) else {
+                return Ok(None);
+            };
+            let end = if last.is_empty() {
+                u64::MAX
+            } else {
+                match number(last) {
+                    Some(end) if end >= start => end,
+                    _ => return Ok(None),
+                }
+            };
+            if start >= length {
+                return unsatisfiable;
+            }
+            (start, end.min(length - 1))
+        };
+        Ok(Some(Self { start, end, length }))
+    }
+
+    /// Number of bytes in the range, the `Content-Length` of the partial response
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.end.saturating_add(1).saturating_sub(self.start)
+    }
+
+    /// Check if the range contains no bytes, which a parsed range never is
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    /// The `Content-Range` value of a `416` response for a resource of `length` bytes
+    ///
+    /// Formats as `bytes */<length>`.
+    #[must_use]
+    pub fn unsatisfied(length: u64) -> impl fmt::Display {
+        Unsatisfied(length)
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bytes {}-{}/{}", self.start, self.end, self.length)
+    }
+}
+
+/// `Content-Range` value of a range that could not be satisfied
+struct Unsatisfied(u64);
+
+impl fmt::Display for Unsatisfied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bytes */{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(value: &str, length: u64) -> Result<Option<(u64, u64)>, StatusCode> {
+        ByteRange::parse(value, length).map(|range| range.map(|r| (r.start, r.end)))
+    }
+
+    #[test]
+    fn test_parse_byte_ranges() {
+        assert_eq!(resolve("bytes=0-499", 1000), Ok(Some((0, 499))));
+        assert_eq!(resolve("bytes=500-", 1000), Ok(Some((500, 999))));
+        assert_eq!(resolve("bytes=-500", 1000), Ok(Some((500, 999))));
+        assert_eq!(resolve("Bytes = 10 - 10", 1000), Ok(Some((10, 10))));
+
+        // Clamped to the resource
+        assert_eq!(resolve("bytes=900-1999", 1000), Ok(Some((900, 999))));
+        assert_eq!(resolve("bytes=-5000", 1000), Ok(Some((0, 999))));
+        assert_eq!(
+            resolve("bytes=0-99999999999999999999999", 1000),
+            Ok(Some((0, 999)))
+        );
+    }
+
+    #[test]
+    fn test_parse_byte_ranges_unsatisfiable() {
+        let unsatisfiable = Err(StatusCode::RequestedRangeNotSatisfiable);
+        assert_eq!(resolve("bytes=1000-", 1000), unsatisfiable);
+        assert_eq!(resolve("bytes=1000-2000", 1000), unsatisfiable);
+        assert_eq!(resolve("bytes=-0", 1000), unsatisfiable);
+        assert_eq!(resolve("bytes=-10", 0), unsatisfiable);
+        assert_eq!(resolve("bytes=0-", 0), unsatisfiable);
+    }
+
+    #[test]
+    fn test_parse_byte_ranges_ignored() {
+        assert_eq!(resolve("items=0-10", 1000), Ok(None));
+        assert_eq!(resolve("bytes=0-10,20-30", 1000), Ok(None));
+        assert_eq!(resolve("bytes=10-5", 1000), Ok(None));
+        assert_eq!(resolve("bytes=-", 1000), Ok(None));
+        assert_eq!(resolve("bytes=a-b", 1000), Ok(None));
+        assert_eq!(resolve("bytes=5-x", 1000), Ok(None));
+        assert_eq!(resolve("bytes=+1-2", 1000), Ok(None));
+        assert_eq!(resolve("bytes", 1000), Ok(None));
+    }
+
+    #[test]
+    fn test_content_range() {
+        let range = ByteRange::parse("bytes=500-", 1000).unwrap().unwrap();
+        assert_eq!(range.len(), 500);
+        assert!(!range.is_empty());
+        assert_eq!(range.to_string(), "bytes 500-999/1000");
+        assert_eq!(ByteRange::unsatisfied(1000).to_string(), "bytes */1000");
+    }
+}
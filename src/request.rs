@@ -1,15 +1,108 @@
-use crate::{error::Error, header::HttpHeader, method::HttpMethod};
+use crate::{
+    cache::CacheControl,
+    error::Error,
+    header::{HttpHeader, headers},
+    method::HttpMethod,
+    range::ByteRange,
+    status_code::StatusCode,
+    url::{Component, DecodeError, percent_decode},
+};
+use core::any::Any;
+use core::net::IpAddr;
 use heapless::Vec;
 
 /// Maximum number of headers allowed in a request
+///
+/// Individual header values are not limited beyond the size of the request buffer,
+/// so large cookies or bearer tokens are accepted as long as the request fits. The
+/// server answers requests with more headers with `431 Request Header Fields Too
+/// Large`.
 pub const MAX_HEADERS: usize = 16;
 
+/// Message of the parse error for requests with more than [`MAX_HEADERS`] headers
+pub(crate) const TOO_MANY_HEADERS: &str = "Too many headers";
+
+/// Maximum number of values a request can carry in its [`Extensions`]
+pub const MAX_EXTENSIONS: usize = 4;
+
+/// Values attached to a request by wrapping handlers, looked up by type
+///
+/// A wrapping handler (e.g. for authentication) can compute data once and hand it
+/// to the inner handler without globals: it clones the request, inserts a reference
+/// to a value living on its own stack and passes the clone on. At most one value is
+/// stored per type.
+///
+/// ```rust
+/// use nanofish::HttpRequest;
+///
+/// struct User {
+///     id: u32,
+/// }
+///
+/// let request = HttpRequest::try_from(b"GET / HTTP/1.1\r\n\r\n".as_slice()).unwrap();
+/// let user = User { id: 7 };
+/// let mut request = request.clone();
+/// request.extensions.insert(&user).ok();
+///
+/// assert_eq!(request.extensions.get::<User>().map(|u| u.id), Some(7));
+/// ```
+#[derive(Clone, Default)]
+pub struct Extensions<'a> {
+    values: Vec<&'a dyn Any, MAX_EXTENSIONS>,
+}
+
+impl<'a> Extensions<'a> {
+    /// Create an empty container
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { values: Vec::new() }
+    }
+
+    /// Attach `value`, replacing any earlier value of the same type
+    ///
+    /// # Errors
+    ///
+    /// Returns `value` back if `MAX_EXTENSIONS` values of other types are already
+    /// stored.
+    pub fn insert<T: Any>(&mut self, value: &'a T) -> Result<(), &'a T> {
+        if let Some(slot) = self.values.iter_mut().find(|v| v.is::<T>()) {
+            *slot = value;
+            return Ok(());
+        }
+        self.values.push(value).map_err(|_| value)
+    }
+
+    /// Get the value of type `T`, if one was attached
+    #[must_use]
+    pub fn get<T: Any>(&self) -> Option<&'a T> {
+        self.values.iter().find_map(|v| v.downcast_ref::<T>())
+    }
+
+    /// Number of attached values
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Check if no values are attached
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
 /// HTTP request parsed from client
-#[derive(Debug)]
+///
+/// The `Debug` and `defmt::Format` output only summarizes the request (method, path,
+/// header count and body length) so header values and bodies never end up in logs.
+#[derive(Clone)]
 pub struct HttpRequest<'a> {
     /// HTTP method
     pub method: HttpMethod,
     /// Request path
+    ///
+    /// Always origin-form (`/path?query`) for parsed requests, also when the client
+    /// sent an absolute-form target like `http://host/path`.
     pub path: &'a str,
     /// HTTP version (e.g., "HTTP/1.1")
     pub version: &'a str,
@@ -17,24 +110,176 @@ pub struct HttpRequest<'a> {
     pub headers: Vec<HttpHeader<'a>, MAX_HEADERS>,
     /// Request body (if present)
     pub body: &'a [u8],
+    /// Values attached by wrapping handlers, empty for a freshly parsed request
+    pub extensions: Extensions<'a>,
+    /// Whether the request arrived over an encrypted (TLS) connection
+    ///
+    /// Parsing always yields `false`; the built-in server only accepts plain HTTP, but
+    /// sets it from `X-Forwarded-Proto` for requests from a trusted proxy (see
+    /// [`ServerOptions::trusted_proxies`](crate::ServerOptions::trusted_proxies)).
+    pub secure: bool,
+    /// Address of the client that sent the request
+    ///
+    /// Parsing yields `None`. The server sets the peer address of the connection, or
+    /// the client address from `X-Forwarded-For` for requests from a trusted proxy.
+    /// IPv4 addresses mapped into IPv6 are given as IPv4.
+    pub client_ip: Option<IpAddr>,
+    /// The request line exactly as the client sent it, e.g. `GET /path?x=1 HTTP/1.1`
+    ///
+    /// Unlike [`path`](Self::path) it is never rewritten, see
+    /// [`HttpRequest::request_line`].
+    pub request_line: &'a str,
+}
+
+impl core::fmt::Debug for HttpRequest<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HttpRequest")
+            .field("method", &self.method)
+            .field("path", &self.path)
+            .field("headers", &self.headers.len())
+            .field("body_len", &self.body.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for HttpRequest<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "HttpRequest {{ method: {=str}, path: {=str}, headers: {=usize}, body_len: {=usize} }}",
+            self.method.as_str(),
+            self.path,
+            self.headers.len(),
+            self.body.len()
+        );
+    }
+}
+
+/// Check whether `data` can start with a request line
+///
+/// Only visible ASCII characters and spaces may appear up to the first line break;
+/// anything else, like the binary record of a TLS handshake sent to a plain HTTP
+/// port, cannot be a request. `data` may end before the line does.
+pub(crate) fn is_request_line(data: &[u8]) -> bool {
+    data.iter()
+        .take_while(|&&b| b != b'\n')
+        .all(|&b| b == b' ' || b == b'\r' || b.is_ascii_graphic())
 }
 
 /// Find the position of the double CRLF sequence that separates headers from body
-fn find_double_crlf(data: &[u8]) -> Option<usize> {
+pub(crate) fn find_double_crlf(data: &[u8]) -> Option<usize> {
     const DOUBLE_CRLF: &[u8] = b"\r\n\r\n";
     (0..data.len().saturating_sub(3)).find(|&i| &data[i..i + 4] == DOUBLE_CRLF)
 }
 
+/// Get the `Content-Length` announced in a request head without parsing the request
+///
+/// `head` is everything before the blank line ending the headers. The lines are only
+/// compared byte-wise, skipping the UTF-8 validation and header list of a full parse,
+/// so a request without a declared body (the usual `GET`) costs a single pass.
+pub(crate) fn head_content_length(head: &[u8]) -> Option<usize> {
+    head.split(|&b| b == b'\n').skip(1).find_map(|line| {
+        let colon = line.iter().position(|&b| b == b':')?;
+        let (name, value) = (&line[..colon], &line[colon + 1..]);
+        if !name.trim_ascii().eq_ignore_ascii_case(b"content-length") {
+            return None;
+        }
+        // A malformed value counts as no body, like `declared_content_length`
+        Some(
+            core::str::from_utf8(value)
+                .ok()
+                .and_then(parse_content_length),
+        )
+    })?
+}
+
+/// Parse a `Content-Length` value, which must be a plain decimal number
+fn parse_content_length(value: &str) -> Option<usize> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Split an absolute-form request target like `http://host:8080/path?q` into its
+/// authority (`host:8080`) and the rest (`/path?q`)
+///
+/// Returns `None` for any other target, e.g. origin-form `/path` or `*`.
+fn split_absolute_form(target: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    // Drop deprecated `user:password@` credentials
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    Some((authority, path))
+}
+
+/// Parse a quality value like `0.5` into thousandths
+fn parse_qvalue(value: &str) -> Option<u16> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = match whole {
+        "0" => fraction
+            .bytes()
+            .chain(b"000".iter().copied())
+            .take(3)
+            .fold(0, |q, digit| q * 10 + u16::from(digit - b'0')),
+        "1" if fraction.bytes().all(|b| b == b'0') => 1000,
+        _ => return None,
+    };
+    Some(thousandths)
+}
+
+/// Iterate over the values of a list header with their quality, like `gzip;q=0.5, br`
+///
+/// Values without a `q` parameter have quality 1000; an unparsable quality value
+/// makes the value unacceptable (quality 0).
+fn weighted_values(header: &str) -> impl Iterator<Item = (&str, u16)> {
+    header.split(',').filter_map(|entry| {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        if name.is_empty() {
+            return None;
+        }
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(Some(1000), parse_qvalue);
+        Some((name, q.unwrap_or(0)))
+    })
+}
+
+/// Check whether the language tag `prefix` is `tag` with subtags removed, like `en` for `en-US`
+fn is_subtag_prefix(prefix: &str, tag: &str) -> bool {
+    tag.as_bytes().get(prefix.len()) == Some(&b'-')
+        && tag
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
 impl<'a> HttpRequest<'a> {
     /// Parse an HTTP request from headers string and body bytes
     ///
     /// # Errors
     ///
     /// Returns an error if:
-    /// - The request line is missing or malformed
+    /// - The request line is missing or malformed, or contains other characters than
+    ///   visible ASCII and spaces
     /// - The HTTP method is invalid or unsupported  
     /// - Required parts (method, path, version) are missing
     /// - Too many headers are provided (exceeds `MAX_HEADERS`)
+    /// - An absolute-form target has no host, or a query without a path
+    ///
+    /// Absolute-form targets as sent to proxies (`GET http://host:8080/path HTTP/1.1`)
+    /// are split up: `path` is set to `/path` and the `Host` header to `host:8080`,
+    /// replacing any `Host` header the client sent.
     pub fn parse_from(headers_str: &'a str, body: &'a [u8]) -> Result<Self, Error> {
         let mut lines = headers_str.lines();
 
@@ -42,12 +287,15 @@ impl<'a> HttpRequest<'a> {
         let request_line = lines
             .next()
             .ok_or(Error::InvalidResponse("Missing request line"))?;
+        if !is_request_line(request_line.as_bytes()) {
+            return Err(Error::InvalidResponse("Invalid character in request line"));
+        }
         let mut parts = request_line.split_whitespace();
 
         let method_str = parts
             .next()
             .ok_or(Error::InvalidResponse("Missing method"))?;
-        let path = parts.next().ok_or(Error::InvalidResponse("Missing path"))?;
+        let target = parts.next().ok_or(Error::InvalidResponse("Missing path"))?;
         let version = parts
             .next()
             .ok_or(Error::InvalidResponse("Missing version"))?;
@@ -55,6 +303,16 @@ impl<'a> HttpRequest<'a> {
         let method = HttpMethod::try_from(method_str)
             .map_err(|_| Error::InvalidResponse("Unknown HTTP method"))?;
 
+        let (authority, path) = match split_absolute_form(target) {
+            Some((authority, path)) => {
+                if authority.is_empty() || path.starts_with('?') {
+                    return Err(Error::InvalidResponse("Invalid request target"));
+                }
+                (Some(authority), if path.is_empty() { "/" } else { path })
+            }
+            None => (None, target),
+        };
+
         // Parse headers
         let mut headers = Vec::new();
         for line in lines {
@@ -69,24 +327,532 @@ impl<'a> HttpRequest<'a> {
                 let header = HttpHeader::new(name, value);
                 headers
                     .push(header)
-                    .map_err(|_| Error::InvalidResponse("Too many headers"))?;
+                    .map_err(|_| Error::InvalidResponse(TOO_MANY_HEADERS))?;
             }
         }
 
+        if let Some(authority) = authority {
+            headers.retain(|h| !h.name.eq_ignore_ascii_case(headers::HOST));
+            headers
+                .push(HttpHeader::new(headers::HOST, authority))
+                .map_err(|_| Error::InvalidResponse(TOO_MANY_HEADERS))?;
+        }
+
         Ok(HttpRequest {
             method,
             path,
             version,
             headers,
             body,
+            extensions: Extensions::new(),
+            secure: false,
+            client_ip: None,
+            request_line,
         })
     }
 }
 
+impl HttpRequest<'_> {
+    /// Get a header value by name (case-insensitive)
+    #[must_use]
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value)
+    }
+
+    /// Body length declared by the `Content-Length` header, if present and numeric
+    pub(crate) fn declared_content_length(&self) -> Option<usize> {
+        parse_content_length(self.get_header(headers::CONTENT_LENGTH)?)
+    }
+
+    /// The body length announced by the client in `Content-Length`
+    ///
+    /// Lets a handler reject an oversized upload or size a buffer before looking at
+    /// the body. Returns `None` without the header, for a value that is not a plain
+    /// decimal number, and for requests with a `Transfer-Encoding` (e.g. chunked),
+    /// whose length is not known up front.
+    #[must_use]
+    pub fn content_length(&self) -> Option<usize> {
+        if self.get_header(headers::TRANSFER_ENCODING).is_some() {
+            return None;
+        }
+        self.declared_content_length()
+    }
+
+    /// The body of a request that must have one, e.g. a `POST` creating a resource
+    ///
+    /// Lets a handler answer a missing body with the returned status instead of
+    /// checking `body.is_empty()` itself. The server has always read a body announced
+    /// with `Content-Length` completely before the handler runs, so an empty one here
+    /// was really sent empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StatusCode::BadRequest`] if the request has no body, and
+    /// [`StatusCode::LengthRequired`] if the client announced one with a
+    /// `Transfer-Encoding` (e.g. chunked): such bodies are not read into
+    /// [`body`](Self::body), so the client has to send a `Content-Length` instead.
+    pub fn require_body(&self) -> Result<&[u8], StatusCode> {
+        if self.get_header(headers::TRANSFER_ENCODING).is_some() {
+            Err(StatusCode::LengthRequired)
+        } else if self.body.is_empty() {
+            Err(StatusCode::BadRequest)
+        } else {
+            Ok(self.body)
+        }
+    }
+
+    /// The body, after checking it against the request's `X-Checksum-SHA256`
+    ///
+    /// Lets upload handlers reject a corrupted firmware image or configuration with
+    /// the returned status before storing it. To hash a body while processing it
+    /// piece by piece, use [`BodyChecksum`](crate::BodyChecksum) directly.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StatusCode::BadRequest`] if the header is missing or not a hex
+    /// SHA-256 digest, and [`StatusCode::UnprocessableEntity`] if the body does not
+    /// match it.
+    #[cfg(feature = "checksum")]
+    pub fn verify_checksum(&self) -> Result<&[u8], StatusCode> {
+        let expected = self
+            .get_header(headers::X_CHECKSUM_SHA256)
+            .ok_or(StatusCode::BadRequest)?;
+        let mut checksum = crate::BodyChecksum::new();
+        checksum.update(self.body);
+        checksum.verify(expected)?;
+        Ok(self.body)
+    }
+
+    /// The request line exactly as the client sent it, without the trailing CRLF
+    ///
+    /// Meant for access logs and debugging: it keeps the original method, target
+    /// (including an absolute-form URL and the query) and version, while
+    /// [`path`](Self::path) may have been normalized by the server.
+    #[must_use]
+    pub fn request_line(&self) -> &str {
+        self.request_line
+    }
+
+    /// Check whether the request arrived over TLS
+    ///
+    /// Handlers can use this to redirect to HTTPS or to decide on `Secure` cookies.
+    #[must_use]
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// The URL scheme the request was made with, `"https"` or `"http"`
+    #[must_use]
+    pub fn scheme(&self) -> &'static str {
+        if self.secure { "https" } else { "http" }
+    }
+
+    /// Parse the `Cache-Control` request header, if present
+    #[must_use]
+    pub fn cache_control(&self) -> Option<CacheControl> {
+        self.get_header(headers::CACHE_CONTROL)
+            .map(CacheControl::parse)
+    }
+
+    /// Resolve the `Range` header against a resource of `length` bytes
+    ///
+    /// Returns `Ok(None)` without a header or with one that should be ignored; see
+    /// [`ByteRange::parse`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StatusCode::RequestedRangeNotSatisfiable`] if the range lies beyond
+    /// the end of the resource.
+    pub fn range(&self, length: u64) -> Result<Option<ByteRange>, StatusCode> {
+        match self.get_header(headers::RANGE) {
+            Some(value) => ByteRange::parse(value, length),
+            None => Ok(None),
+        }
+    }
+
+    /// Resolve the `Range` header like [`range`](Self::range), honoring `If-Range`
+    ///
+    /// A client resuming a download sends the validator of the copy it already has in
+    /// `If-Range`. If that no longer matches the resource, `Ok(None)` is returned and
+    /// the full resource should be sent with `200 OK` instead of a `206` partial one.
+    /// See [`if_range_matches`](Self::if_range_matches) for how validators compare.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StatusCode::RequestedRangeNotSatisfiable`] if the validator matches
+    /// and the range lies beyond the end of the resource.
+    pub fn range_if(
+        &self,
+        length: u64,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> Result<Option<ByteRange>, StatusCode> {
+        if self.if_range_matches(etag, last_modified) {
+            self.range(length)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Check the `If-Range` header against the current validators of the resource
+    ///
+    /// `etag` is the quoted entity tag and `last_modified` the `Last-Modified` date the
+    /// resource would be sent with. An entity tag in the header must match `etag` with
+    /// the strong comparison of RFC 9110, so weak tags never match; a date must be
+    /// identical to `last_modified`. Returns `true` if the header is absent.
+    #[must_use]
+    pub fn if_range_matches(&self, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+        let Some(value) = self.get_header(headers::IF_RANGE) else {
+            return true;
+        };
+        let value = value.trim();
+        if value.starts_with('"') || value.starts_with("W/") {
+            etag.is_some_and(|etag| !value.starts_with("W/") && value == etag)
+        } else {
+            last_modified.is_some_and(|date| value == date.trim())
+        }
+    }
+
+    /// Pick the content coding to respond with from `supported`, based on `Accept-Encoding`
+    ///
+    /// `supported` lists the codings the handler can produce (e.g. `["br", "gzip"]`) in
+    /// order of preference. The one with the highest quality value wins, honoring
+    /// `q=0` refusals and `*` wildcards. If none of them is acceptable, `identity`
+    /// (no compression) is returned, unless the client refused that too with
+    /// `identity;q=0` or `*;q=0`: then the result is `None` and the handler should
+    /// answer `406 Not Acceptable`. Without an `Accept-Encoding` header only `identity`
+    /// is chosen. Responses chosen this way should be marked with
+    /// [`HttpResponse::with_vary`](crate::HttpResponse::with_vary)`("Accept-Encoding")`.
+    #[must_use]
+    pub fn negotiate_encoding<'s>(&self, supported: &[&'s str]) -> Option<&'s str> {
+        let Some(header) = self.get_header(headers::ACCEPT_ENCODING) else {
+            return Some("identity");
+        };
+        // Quality of `coding`, `None` if the header does not mention it
+        let quality = |coding: &str| {
+            weighted_values(header)
+                .find(|(name, _)| name.eq_ignore_ascii_case(coding))
+                .map(|(_, q)| q)
+        };
+        let wildcard = quality("*");
+
+        let mut best: Option<(&'s str, u16)> = None;
+        for &coding in supported {
+            let q = quality(coding).or(wildcard).unwrap_or(0);
+            if q > 0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((coding, q));
+            }
+        }
+        if let Some((coding, _)) = best {
+            return Some(coding);
+        }
+        let identity = quality("identity").or(wildcard).unwrap_or(1000);
+        (identity > 0).then_some("identity")
+    }
+
+    /// Pick the charset to respond in from `supported`, based on `Accept-Charset`
+    ///
+    /// `supported` lists the charsets the handler can produce in order of preference,
+    /// usually just `["utf-8"]`. The one with the highest quality value wins, honoring
+    /// `q=0` refusals and `*` wildcards; charsets the header does not mention are not
+    /// acceptable unless it has a `*`. Returns `None` if nothing in `supported` is
+    /// acceptable, e.g. `utf-8` for `Accept-Charset: iso-8859-1`, and the handler
+    /// should answer `406 Not Acceptable`. Without an `Accept-Charset` header the
+    /// first supported charset is chosen.
+    #[must_use]
+    pub fn negotiate_charset<'s>(&self, supported: &[&'s str]) -> Option<&'s str> {
+        let Some(header) = self.get_header(headers::ACCEPT_CHARSET) else {
+            return supported.first().copied();
+        };
+        let wildcard = weighted_values(header)
+            .find(|(name, _)| *name == "*")
+            .map(|(_, q)| q);
+
+        let mut best: Option<(&'s str, u16)> = None;
+        for &charset in supported {
+            let q = weighted_values(header)
+                .find(|(name, _)| name.eq_ignore_ascii_case(charset))
+                .map(|(_, q)| q)
+                .or(wildcard)
+                .unwrap_or(0);
+            if q > 0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((charset, q));
+            }
+        }
+        best.map(|(charset, _)| charset)
+    }
+
+    /// Pick the language to respond in from `offered`, based on `Accept-Language`
+    ///
+    /// `offered` lists the language tags the handler has (e.g. `["en", "de-CH"]`) in
+    /// order of preference. The one with the highest quality value wins. A range
+    /// matches its exact tag, any more specific tag (`en` matches `en-US`), and as a
+    /// fallback a more general tag (`en-US` matches `en`); the most specific match
+    /// decides a tag's quality and breaks ties. `*` matches every tag. Returns `None`
+    /// if all offered languages were refused, and the first offered one if the request
+    /// has no `Accept-Language` header. Send the result as `Content-Language` and mark
+    /// the response with
+    /// [`HttpResponse::with_vary`](crate::HttpResponse::with_vary)`("Accept-Language")`.
+    #[must_use]
+    pub fn preferred_language<'s>(&self, offered: &[&'s str]) -> Option<&'s str> {
+        let Some(header) = self.get_header(headers::ACCEPT_LANGUAGE) else {
+            return offered.first().copied();
+        };
+
+        let mut best: Option<(&'s str, u16, u8)> = None;
+        for &tag in offered {
+            // The quality of the most specific range matching `tag`
+            let mut matched: Option<(u16, u8)> = None;
+            for (range, q) in weighted_values(header) {
+                let specificity = if range == "*" {
+                    0
+                } else if range.eq_ignore_ascii_case(tag) {
+                    3
+                } else if is_subtag_prefix(range, tag) {
+                    2
+                } else if is_subtag_prefix(tag, range) {
+                    1
+                } else {
+                    continue;
+                };
+                if matched.is_none_or(|(_, best)| specificity > best) {
+                    matched = Some((q, specificity));
+                }
+            }
+            let Some((q, specificity)) = matched else {
+                continue;
+            };
+            if q > 0 && best.is_none_or(|(_, best_q, best_s)| (q, specificity) > (best_q, best_s)) {
+                best = Some((tag, q, specificity));
+            }
+        }
+        best.map(|(tag, _, _)| tag)
+    }
+
+    /// Check whether the `If-None-Match` header matches `etag`
+    ///
+    /// `etag` is the quoted entity tag, e.g. `"v42"`. Uses the weak comparison of RFC 9110,
+    /// so `W/` prefixes are ignored on both sides, and `*` matches any tag. Returns `false`
+    /// if the header is absent.
+    #[must_use]
+    pub fn etag_matches(&self, etag: &str) -> bool {
+        let Some(value) = self.get_header(headers::IF_NONE_MATCH) else {
+            return false;
+        };
+        let etag = etag.trim_start_matches("W/");
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+    }
+
+    /// Iterate over all headers as `(name, value)` pairs in the order they were received
+    ///
+    /// Both name and value borrow directly from the request buffer.
+    pub fn iter_headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|h| (h.name, h.value))
+    }
+
+    /// Iterate over the non-empty lines of the body, e.g. the records of an
+    /// `application/x-ndjson` upload
+    ///
+    /// Lines end with `\n` or `\r\n`; the line ending is not included, and a final
+    /// line without one is still yielded. Each record can be deserialized on its own
+    /// without copying. This does not stream the upload: the server has read the
+    /// whole body before the handler runs, so it must fit into the request buffer or
+    /// a [`LargeRequestBuffer`](crate::LargeRequestBuffer), and larger uploads are
+    /// answered with `413 Payload Too Large`.
+    pub fn body_lines(&self) -> impl Iterator<Item = &[u8]> {
+        self.body
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+    }
+
+    /// Parse the whole body as a single value, e.g. the `128` of a `POST /pwm`
+    ///
+    /// Surrounding whitespace, like a trailing newline sent by `curl` or `echo`, is
+    /// ignored. Any [`FromStr`](core::str::FromStr) type works, including numbers,
+    /// `bool` (`true` or `false`) and `IpAddr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidBody`] if the body is not valid UTF-8 or does not parse
+    /// into `T`. Handlers usually answer this with `400 Bad Request`.
+    pub fn body_parse<T: core::str::FromStr>(&self) -> Result<T, Error> {
+        let body = core::str::from_utf8(self.body)
+            .map_err(|_| Error::InvalidBody("Invalid UTF-8 in body"))?;
+        body.trim()
+            .parse()
+            .map_err(|_| Error::InvalidBody("Not a valid value"))
+    }
+
+    /// Get the query string of the request target (the part after `?`), if any
+    #[must_use]
+    pub fn query(&self) -> Option<&str> {
+        self.path.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Iterate over all query parameters as raw `(name, value)` pairs
+    ///
+    /// Repeated keys (`?a=1&a=2`) are yielded once per occurrence, in the order they
+    /// appear in the request target. Names and values are not percent-decoded, a
+    /// parameter without `=` yields an empty value and empty pairs (`&&`) are skipped.
+    pub fn query_params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+    }
+
+    /// Get the raw value of the first query parameter named `name`
+    #[must_use]
+    pub fn query_first(&self, name: &str) -> Option<&str> {
+        self.query_params()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the raw value of the last query parameter named `name`
+    #[must_use]
+    pub fn query_last(&self, name: &str) -> Option<&str> {
+        self.query_params()
+            .filter(|(key, _)| *key == name)
+            .last()
+            .map(|(_, value)| value)
+    }
+
+    /// Get the raw value of a query parameter by name
+    ///
+    /// If the key is repeated, the first occurrence wins (see [`Self::query_first`]).
+    /// The value is returned as it appears in the request target, without
+    /// percent-decoding (see [`Self::query_param_decoded`]). A parameter without `=`
+    /// yields an empty value.
+    #[must_use]
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query_first(name)
+    }
+
+    /// Get the value of a query parameter by name, percent-decoded into `buf`
+    ///
+    /// `%XX` escapes are resolved and `+` is read as a space, as browsers encode form
+    /// values in the query string; a literal plus arrives as `%2B`. If the key is
+    /// repeated, the first occurrence wins. Returns `Ok(None)` if the parameter is
+    /// missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidQuery`] if the value has a malformed escape, does not
+    /// fit into `buf` or does not decode to valid UTF-8.
+    pub fn query_param_decoded<'b>(
+        &self,
+        name: &str,
+        buf: &'b mut [u8],
+    ) -> Result<Option<&'b str>, Error> {
+        let Some(value) = self.query_first(name) else {
+            return Ok(None);
+        };
+        percent_decode(value, Component::Query, buf)
+            .map(Some)
+            .map_err(|e| {
+                Error::InvalidQuery(match e {
+                    DecodeError::InvalidEscape => "Invalid percent-encoding",
+                    DecodeError::TooLong => "Query value too long",
+                    DecodeError::InvalidUtf8 => "Invalid UTF-8 in query value",
+                })
+            })
+    }
+
+    /// Get the path of the request target without the query string, percent-decoded
+    /// into `buf`
+    ///
+    /// Unlike in a query string, `+` in a path is a literal plus and is kept, so
+    /// `/files/c++.txt` decodes to itself while `/files/my%20notes.txt` decodes to
+    /// `/files/my notes.txt`. An encoded `%2F` becomes a `/` like any other
+    /// character, so check the decoded path again before using it to open files.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidUrl`] if the path has a malformed escape, does not fit
+    /// into `buf` or does not decode to valid UTF-8.
+    pub fn decoded_path<'b>(&self, buf: &'b mut [u8]) -> Result<&'b str, Error> {
+        let path = self.path.split('?').next().unwrap_or_default();
+        percent_decode(path, Component::Path, buf).map_err(|_| Error::InvalidUrl)
+    }
+
+    /// Get a query parameter parsed into `T`
+    ///
+    /// Returns `None` if the parameter is missing or does not parse, e.g.
+    /// `request.query_param_parsed::<u32>("page")` for `?page=2`.
+    #[must_use]
+    pub fn query_param_parsed<T: core::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.query_param(name)?.parse().ok()
+    }
+
+    /// Deserialize the query string into `T`
+    ///
+    /// For example `?page=2&size=10` deserializes into
+    /// `struct Pagination { page: u32, size: u32 }`. Missing fields can be handled
+    /// with `Option` or `#[serde(default)]`; a request without a query string is
+    /// treated as an empty one. Names and values are percent-decoded and `+` is read
+    /// as a space, as for [`HttpRequest::form_as`]. Fields borrowing `&str` only work
+    /// for values without escapes; decoded values are limited to 256 bytes and need an
+    /// owned field type.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidQuery`] if a required parameter is missing, a value is
+    /// not properly encoded or does not parse into its field type. Handlers usually
+    /// answer this with `400 Bad Request`.
+    #[cfg(feature = "serde")]
+    pub fn query_as<'de, T: serde::Deserialize<'de>>(&'de self) -> Result<T, Error> {
+        let query = self.query().unwrap_or("");
+        T::deserialize(crate::query::QueryDeserializer::new(query))
+            .map_err(|crate::query::QueryError(msg)| Error::InvalidQuery(msg))
+    }
+
+    /// Deserialize an `application/x-www-form-urlencoded` body into `T`
+    ///
+    /// The body analogue of [`HttpRequest::query_as`] for classic HTML form
+    /// submissions. Names and values are percent-decoded and `+` is read as a space.
+    /// Fields borrowing `&str` only work for values without escapes; decoded values
+    /// are limited to 256 bytes and need an owned field type.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidForm`] if the request has a different `Content-Type`,
+    /// the body is not valid UTF-8 or not properly encoded, a required field is
+    /// missing or a value does not parse into its field type.
+    #[cfg(feature = "serde")]
+    pub fn form_as<'de, T: serde::Deserialize<'de>>(&'de self) -> Result<T, Error> {
+        let is_form = self.get_header(headers::CONTENT_TYPE).is_some_and(|value| {
+            let media_type = value.split(';').next().unwrap_or_default();
+            media_type
+                .trim()
+                .eq_ignore_ascii_case(crate::header::mime_types::FORM)
+        });
+        if !is_form {
+            return Err(Error::InvalidForm("Not a form body"));
+        }
+        let body = core::str::from_utf8(self.body)
+            .map_err(|_| Error::InvalidForm("Invalid UTF-8 in form body"))?;
+        T::deserialize(crate::query::QueryDeserializer::new(body))
+            .map_err(|e| Error::InvalidForm(e.form_message()))
+    }
+}
+
 impl<'a> TryFrom<&'a [u8]> for HttpRequest<'a> {
     type Error = Error;
 
     fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
+        if !is_request_line(buffer) {
+            return Err(Error::InvalidResponse("Invalid character in request line"));
+        }
+
         // Find the end of headers (double CRLF)
         let end_of_headers =
             find_double_crlf(buffer).ok_or(Error::InvalidResponse("Incomplete request headers"))?;
@@ -144,6 +910,39 @@ mod tests {
         assert_eq!(content_type_header.value, "application/json");
     }
 
+    #[test]
+    fn test_get_header_case_insensitive() {
+        let request_str = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let request = HttpRequest::parse_from(request_str, b"").unwrap();
+
+        assert_eq!(request.get_header("host"), Some("example.com"));
+        assert_eq!(request.get_header("HOST"), Some("example.com"));
+        assert_eq!(request.get_header("missing"), None);
+    }
+
+    #[test]
+    fn test_iter_headers() {
+        let request_str = "GET / HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n";
+        let request = HttpRequest::parse_from(request_str, b"").unwrap();
+
+        let mut iter = request.iter_headers();
+        assert_eq!(iter.next(), Some(("Host", "example.com")));
+        assert_eq!(iter.next(), Some(("Accept", "*/*")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_debug_omits_header_values_and_body() {
+        let request_str = "POST /login HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n";
+        let request = HttpRequest::parse_from(request_str, b"password=hunter2").unwrap();
+
+        let output = format!("{request:?}");
+        assert_eq!(
+            output,
+            "HttpRequest { method: POST, path: \"/login\", headers: 1, body_len: 16 }"
+        );
+    }
+
     #[test]
     fn test_parse_request_invalid_method() {
         let request_str = "INVALID /path HTTP/1.1\r\n\r\n";
@@ -266,4 +1065,584 @@ mod tests {
         let result = HttpRequest::try_from(buffer.as_slice());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_invalid_request_line() {
+        // Start of a TLS ClientHello
+        let client_hello = b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03";
+        assert!(!is_request_line(client_hello));
+        assert!(matches!(
+            HttpRequest::try_from(client_hello.as_slice()),
+            Err(Error::InvalidResponse("Invalid character in request line"))
+        ));
+
+        for line in [
+            "GET /a\tb HTTP/1.1",
+            "GET /\u{e9} HTTP/1.1",
+            "GET /\x7f HTTP/1.1",
+        ] {
+            let head = format!("{line}\r\n\r\n");
+            assert!(!is_request_line(head.as_bytes()), "{line}");
+            assert!(HttpRequest::parse_from(&head, b"").is_err(), "{line}");
+        }
+
+        // Only the request line is checked, and it may still be arriving
+        assert!(is_request_line(b"GET /caf"));
+        assert!(is_request_line(b"GET / HTTP/1.1\r\nX-Name: \xc3\xa9\r\n"));
+    }
+
+    #[test]
+    fn test_query_params() {
+        let request_str = "GET /users?id=42&name=bob&flag&id=7 HTTP/1.1\r\n\r\n";
+        let request = HttpRequest::parse_from(request_str, b"").unwrap();
+
+        assert_eq!(request.query(), Some("id=42&name=bob&flag&id=7"));
+        assert_eq!(request.query_param("name"), Some("bob"));
+        assert_eq!(request.query_param("flag"), Some(""));
+        assert_eq!(request.query_param("missing"), None);
+        assert_eq!(request.query_param_parsed::<u32>("id"), Some(42));
+        assert_eq!(request.query_param_parsed::<u32>("name"), None);
+        assert_eq!(request.query_param_parsed::<u32>("missing"), None);
+    }
+
+    #[test]
+    fn test_repeated_query_params() {
+        let request =
+            HttpRequest::parse_from("GET /?a=1&b=x&&a=2&a=3 HTTP/1.1\r\n\r\n", b"").unwrap();
+
+        let params: std::vec::Vec<_> = request.query_params().collect();
+        assert_eq!(params, [("a", "1"), ("b", "x"), ("a", "2"), ("a", "3")]);
+        assert_eq!(request.query_first("a"), Some("1"));
+        assert_eq!(request.query_last("a"), Some("3"));
+        assert_eq!(request.query_param("a"), Some("1"));
+        assert_eq!(request.query_last("b"), Some("x"));
+        assert_eq!(request.query_last("c"), None);
+    }
+
+    #[test]
+    fn test_cache_control_header() {
+        let request = HttpRequest::parse_from(
+            "GET / HTTP/1.1\r\ncache-control: no-cache, max-age=0\r\n\r\n",
+            b"",
+        )
+        .unwrap();
+        let cache_control = request.cache_control().unwrap();
+        assert!(cache_control.no_cache);
+        assert_eq!(cache_control.max_age, Some(0));
+
+        let request = HttpRequest::parse_from("GET / HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert!(request.cache_control().is_none());
+    }
+
+    #[test]
+    fn test_head_content_length() {
+        assert_eq!(head_content_length(b"GET / HTTP/1.1\r\nHost: a"), None);
+        assert_eq!(
+            head_content_length(b"POST / HTTP/1.1\r\nHost: a\r\ncontent-LENGTH:  42 "),
+            Some(42)
+        );
+        assert_eq!(
+            head_content_length(b"POST / HTTP/1.1\r\nContent-Length: 4x\r\nContent-Length: 5"),
+            None
+        );
+        // Only header lines count, not the request target
+        assert_eq!(head_content_length(b"GET /content-length:5 HTTP/1.1"), None);
+
+        let buffer = b"PUT /x HTTP/1.1\r\nX-A: 1\r\nContent-Length: 7\r\n\r\n";
+        let request = HttpRequest::try_from(buffer.as_slice()).unwrap();
+        assert_eq!(
+            head_content_length(&buffer[..buffer.len() - 4]),
+            request.declared_content_length()
+        );
+    }
+
+    #[test]
+    fn test_absolute_form_target() {
+        let request = HttpRequest::parse_from(
+            "GET HTTP://device.local:8080/api/status?full=1 HTTP/1.1\r\nHost: other\r\nAccept: */*\r\n\r\n",
+            b"",
+        )
+        .unwrap();
+        assert_eq!(request.path, "/api/status?full=1");
+        assert_eq!(
+            request.request_line(),
+            "GET HTTP://device.local:8080/api/status?full=1 HTTP/1.1"
+        );
+        assert_eq!(request.get_header("Host"), Some("device.local:8080"));
+        assert_eq!(request.headers.len(), 2);
+        assert_eq!(request.query_param("full"), Some("1"));
+
+        let request =
+            HttpRequest::parse_from("GET http://user:pw@[::1] HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert_eq!(request.path, "/");
+        assert_eq!(request.get_header("host"), Some("[::1]"));
+
+        // Origin-form and asterisk-form targets are left alone
+        let request =
+            HttpRequest::parse_from("GET /a?next=http://x/y HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert_eq!(request.path, "/a?next=http://x/y");
+        assert!(request.get_header("Host").is_none());
+        let request = HttpRequest::parse_from("OPTIONS * HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert_eq!(request.path, "*");
+
+        for target in ["http:///path", "http://host?q=1"] {
+            let head = format!("GET {target} HTTP/1.1\r\n\r\n");
+            assert!(HttpRequest::parse_from(&head, b"").is_err(), "{target}");
+        }
+    }
+
+    #[test]
+    fn test_content_length() {
+        let parse = |head: &'static str| HttpRequest::parse_from(head, b"").unwrap();
+
+        assert_eq!(
+            parse("POST / HTTP/1.1\r\ncontent-length: 12 \r\n\r\n").content_length(),
+            Some(12)
+        );
+        assert_eq!(parse("GET / HTTP/1.1\r\n\r\n").content_length(), None);
+        for value in ["-1", "+5", "0x10", "", "99999999999999999999999"] {
+            let head = format!("POST / HTTP/1.1\r\nContent-Length: {value}\r\n\r\n");
+            let request = HttpRequest::parse_from(&head, b"").unwrap();
+            assert_eq!(request.content_length(), None, "{value}");
+        }
+
+        let request =
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n");
+        assert_eq!(request.content_length(), None);
+    }
+
+    #[test]
+    fn test_require_body() {
+        let request =
+            HttpRequest::parse_from("POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n", b"{}").unwrap();
+        assert_eq!(request.require_body(), Ok(&b"{}"[..]));
+
+        for head in [
+            "POST / HTTP/1.1\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+        ] {
+            let request = HttpRequest::parse_from(head, b"").unwrap();
+            assert_eq!(
+                request.require_body(),
+                Err(StatusCode::BadRequest),
+                "{head}"
+            );
+        }
+
+        let request = HttpRequest::parse_from(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            b"2\r\n{}\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.require_body(), Err(StatusCode::LengthRequired));
+    }
+
+    #[test]
+    fn test_range_header() {
+        let request =
+            HttpRequest::parse_from("GET / HTTP/1.1\r\nrange: bytes=-100\r\n\r\n", b"").unwrap();
+        let range = request.range(1000).unwrap().unwrap();
+        assert_eq!((range.start, range.end), (900, 999));
+        assert_eq!(
+            request.range(0),
+            Err(StatusCode::RequestedRangeNotSatisfiable)
+        );
+
+        let request = HttpRequest::parse_from("GET / HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert_eq!(request.range(1000), Ok(None));
+    }
+
+    #[test]
+    fn test_if_range() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let head = |if_range: &str| {
+            format!("GET / HTTP/1.1\r\nRange: bytes=500-\r\nIf-Range: {if_range}\r\n\r\n")
+        };
+
+        let matching = head("\"v2\"");
+        let request = HttpRequest::parse_from(&matching, b"").unwrap();
+        assert!(request.if_range_matches(Some("\"v2\""), None));
+        let range = request.range_if(1000, Some("\"v2\""), None).unwrap();
+        assert_eq!(range.map(|r| (r.start, r.end)), Some((500, 999)));
+        assert_eq!(
+            request.range_if(100, Some("\"v2\""), None),
+            Err(StatusCode::RequestedRangeNotSatisfiable)
+        );
+
+        // Changed since, or only a date to compare against: send everything
+        assert_eq!(request.range_if(1000, Some("\"v3\""), None), Ok(None));
+        assert_eq!(request.range_if(1000, None, Some(date)), Ok(None));
+
+        // Weak tags never match
+        let weak = head("W/\"v2\"");
+        let request = HttpRequest::parse_from(&weak, b"").unwrap();
+        assert!(!request.if_range_matches(Some("W/\"v2\""), None));
+        assert!(!request.if_range_matches(Some("\"v2\""), None));
+
+        let dated = head(date);
+        let request = HttpRequest::parse_from(&dated, b"").unwrap();
+        assert!(request.range_if(1000, None, Some(date)).unwrap().is_some());
+        assert_eq!(
+            request.range_if(1000, None, Some("Thu, 22 Oct 2015 07:28:00 GMT")),
+            Ok(None)
+        );
+        assert_eq!(request.range_if(1000, Some("\"v2\""), None), Ok(None));
+
+        // Without `If-Range` the range always applies
+        let request =
+            HttpRequest::parse_from("GET / HTTP/1.1\r\nRange: bytes=0-9\r\n\r\n", b"").unwrap();
+        assert!(request.if_range_matches(None, None));
+        assert!(request.range_if(1000, None, None).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_parse_qvalue() {
+        assert_eq!(parse_qvalue("1"), Some(1000));
+        assert_eq!(parse_qvalue("1.000"), Some(1000));
+        assert_eq!(parse_qvalue("0"), Some(0));
+        assert_eq!(parse_qvalue("0.5"), Some(500));
+        assert_eq!(parse_qvalue("0.25"), Some(250));
+        assert_eq!(parse_qvalue("0.001"), Some(1));
+        assert_eq!(parse_qvalue("1.5"), None);
+        assert_eq!(parse_qvalue("0.1234"), None);
+        assert_eq!(parse_qvalue("high"), None);
+    }
+
+    /// Head of a `GET /` request with the header `name` set to `value`, if any
+    fn head_with(name: &str, value: Option<&str>) -> std::string::String {
+        match value {
+            Some(value) => format!("GET / HTTP/1.1\r\n{name}: {value}\r\n\r\n"),
+            None => "GET / HTTP/1.1\r\n\r\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        let negotiate = |accept: Option<&str>, supported: &[&'static str]| {
+            let head = head_with("Accept-Encoding", accept);
+            let request = HttpRequest::parse_from(&head, b"").unwrap();
+            request.negotiate_encoding(supported)
+        };
+
+        assert_eq!(negotiate(None, &["gzip"]), Some("identity"));
+        assert_eq!(negotiate(Some("gzip, deflate"), &["gzip"]), Some("gzip"));
+        assert_eq!(negotiate(Some("GZIP"), &["gzip"]), Some("gzip"));
+        assert_eq!(
+            negotiate(Some("gzip;q=0, identity"), &["gzip"]),
+            Some("identity")
+        );
+        assert_eq!(
+            negotiate(Some("br;q=0.5, gzip;q=0.8"), &["br", "gzip"]),
+            Some("gzip")
+        );
+        assert_eq!(negotiate(Some("br, gzip"), &["br", "gzip"]), Some("br"));
+        assert_eq!(negotiate(Some("*"), &["gzip"]), Some("gzip"));
+        assert_eq!(
+            negotiate(Some("*;q=0.1, gzip;q=0"), &["gzip", "br"]),
+            Some("br")
+        );
+        assert_eq!(negotiate(Some("deflate"), &["gzip"]), Some("identity"));
+        assert_eq!(negotiate(Some(""), &["gzip"]), Some("identity"));
+        assert_eq!(negotiate(Some("gzip;q=bad"), &["gzip"]), Some("identity"));
+
+        // Nothing acceptable: the handler answers 406
+        assert_eq!(negotiate(Some("gzip;q=0, identity;q=0"), &["gzip"]), None);
+        assert_eq!(negotiate(Some("*;q=0"), &["gzip"]), None);
+        assert_eq!(negotiate(Some("br, *;q=0"), &["gzip"]), None);
+    }
+
+    #[test]
+    fn test_negotiate_charset() {
+        let negotiate = |accept: Option<&str>, supported: &[&'static str]| {
+            let head = head_with("Accept-Charset", accept);
+            let request = HttpRequest::parse_from(&head, b"").unwrap();
+            request.negotiate_charset(supported)
+        };
+
+        assert_eq!(negotiate(None, &["utf-8"]), Some("utf-8"));
+        assert_eq!(negotiate(Some("UTF-8"), &["utf-8"]), Some("utf-8"));
+        assert_eq!(
+            negotiate(Some("iso-8859-1, *;q=0.1"), &["utf-8"]),
+            Some("utf-8")
+        );
+        assert_eq!(
+            negotiate(Some("iso-8859-1, utf-8;q=0.5"), &["utf-8", "iso-8859-1"]),
+            Some("iso-8859-1")
+        );
+
+        // Nothing acceptable: the handler answers 406
+        assert_eq!(negotiate(Some("iso-8859-1"), &["utf-8"]), None);
+        assert_eq!(negotiate(Some("utf-8;q=0, *"), &["utf-8"]), None);
+        assert_eq!(negotiate(Some("*;q=0"), &["utf-8"]), None);
+    }
+
+    #[test]
+    fn test_preferred_language() {
+        let preferred = |accept: Option<&str>, offered: &[&'static str]| {
+            let head = head_with("Accept-Language", accept);
+            let request = HttpRequest::parse_from(&head, b"").unwrap();
+            request.preferred_language(offered)
+        };
+
+        assert_eq!(preferred(None, &["en", "de"]), Some("en"));
+        assert_eq!(preferred(Some("de"), &["en", "de"]), Some("de"));
+        assert_eq!(
+            preferred(Some("fr;q=0.9, de;q=0.8, en;q=0.5"), &["en", "de"]),
+            Some("de")
+        );
+        // A range matches more specific tags and falls back to more general ones
+        assert_eq!(preferred(Some("en"), &["de", "en-US"]), Some("en-US"));
+        assert_eq!(
+            preferred(Some("en-US, de;q=0.5"), &["de", "en"]),
+            Some("en")
+        );
+        assert_eq!(preferred(Some("EN-us"), &["en-GB", "en-US"]), Some("en-US"));
+        // The most specific range decides
+        assert_eq!(
+            preferred(Some("de-CH, de;q=0.3"), &["de", "de-CH"]),
+            Some("de-CH")
+        );
+        assert_eq!(preferred(Some("en;q=0, *"), &["en", "fr"]), Some("fr"));
+        assert_eq!(preferred(Some("*"), &["en", "fr"]), Some("en"));
+        // `en` must not match `eng`
+        assert_eq!(preferred(Some("en"), &["eng"]), None);
+        assert_eq!(preferred(Some("fr"), &["en", "de"]), None);
+        assert_eq!(preferred(Some("*;q=0"), &["en"]), None);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let request = HttpRequest::parse_from(
+            "GET / HTTP/1.1\r\nIf-None-Match: \"a\", W/\"v42\"\r\n\r\n",
+            b"",
+        )
+        .unwrap();
+        assert!(request.etag_matches("\"a\""));
+        assert!(request.etag_matches("\"v42\""));
+        assert!(request.etag_matches("W/\"v42\""));
+        assert!(!request.etag_matches("\"v4\""));
+        assert!(!request.etag_matches("v42"));
+
+        let request =
+            HttpRequest::parse_from("GET / HTTP/1.1\r\nIf-None-Match: *\r\n\r\n", b"").unwrap();
+        assert!(request.etag_matches("\"anything\""));
+
+        let request = HttpRequest::parse_from("GET / HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert!(!request.etag_matches("\"a\""));
+    }
+
+    #[test]
+    fn test_is_secure() {
+        let mut request = HttpRequest::parse_from("GET / HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert!(!request.is_secure());
+        assert_eq!(request.scheme(), "http");
+
+        request.secure = true;
+        assert!(request.is_secure());
+        assert_eq!(request.scheme(), "https");
+    }
+
+    #[test]
+    fn test_body_lines() {
+        let request = HttpRequest::parse_from(
+            "POST /telemetry HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\r\n",
+            b"{\"t\":1}\n{\"t\":2}\r\n\n{\"t\":3}",
+        )
+        .unwrap();
+        let lines: std::vec::Vec<&[u8]> = request.body_lines().collect();
+        assert_eq!(lines, [&b"{\"t\":1}"[..], b"{\"t\":2}", b"{\"t\":3}"]);
+
+        let request = HttpRequest::parse_from("POST / HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert_eq!(request.body_lines().count(), 0);
+    }
+
+    #[test]
+    fn test_body_parse() {
+        let parse = |body: &'static [u8]| {
+            HttpRequest::parse_from("POST /pwm HTTP/1.1\r\n\r\n", body).unwrap()
+        };
+
+        assert_eq!(parse(b"128").body_parse::<u8>().unwrap(), 128);
+        assert_eq!(parse(b" -5\r\n").body_parse::<i32>().unwrap(), -5);
+        assert!(parse(b"true\n").body_parse::<bool>().unwrap());
+        assert!((parse(b"0.5").body_parse::<f32>().unwrap() - 0.5).abs() < f32::EPSILON);
+
+        for body in [&b"256"[..], b"", b"1 2", b"\xff"] {
+            assert!(
+                matches!(parse(body).body_parse::<u8>(), Err(Error::InvalidBody(_))),
+                "{body:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_query_params_without_query() {
+        let request = HttpRequest::parse_from("GET /users HTTP/1.1\r\n\r\n", b"").unwrap();
+
+        assert_eq!(request.query(), None);
+        assert_eq!(request.query_param("id"), None);
+        assert_eq!(request.query_params().count(), 0);
+    }
+
+    #[test]
+    fn test_plus_in_path_and_query() {
+        let request = HttpRequest::parse_from(
+            "GET /files/c++%20notes.txt?q=fish+%26+chips&tag=a%2Bb&bad=%G0 HTTP/1.1\r\n\r\n",
+            b"",
+        )
+        .unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            request.decoded_path(&mut buf).unwrap(),
+            "/files/c++ notes.txt"
+        );
+        assert_eq!(
+            request.query_param_decoded("q", &mut buf).unwrap(),
+            Some("fish & chips")
+        );
+        assert_eq!(
+            request.query_param_decoded("tag", &mut buf).unwrap(),
+            Some("a+b")
+        );
+        assert_eq!(
+            request.query_param_decoded("missing", &mut buf).unwrap(),
+            None
+        );
+        assert!(matches!(
+            request.query_param_decoded("bad", &mut buf),
+            Err(Error::InvalidQuery("Invalid percent-encoding"))
+        ));
+        assert!(matches!(
+            request.decoded_path(&mut [0u8; 4]),
+            Err(Error::InvalidUrl)
+        ));
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn test_verify_checksum() {
+        // SHA-256 of `b"firmware"`
+        let head = "PUT /firmware HTTP/1.1\r\n\
+            x-checksum-sha256: c3bf47ea1f4a4a605470313cacb3a44f4a461f68c6faeab07e737610cb5ac835\r\n\r\n";
+        let request = HttpRequest::parse_from(head, b"firmware").unwrap();
+        assert_eq!(request.verify_checksum(), Ok(&b"firmware"[..]));
+
+        let request = HttpRequest::parse_from(head, b"malware!").unwrap();
+        assert_eq!(
+            request.verify_checksum(),
+            Err(StatusCode::UnprocessableEntity)
+        );
+
+        let request =
+            HttpRequest::parse_from("PUT /firmware HTTP/1.1\r\n\r\n", b"firmware").unwrap();
+        assert_eq!(request.verify_checksum(), Err(StatusCode::BadRequest));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query_as() {
+        #[derive(serde::Deserialize)]
+        struct Pagination {
+            page: u32,
+            size: Option<u32>,
+        }
+
+        let request = HttpRequest::parse_from("GET /items?page=2 HTTP/1.1\r\n\r\n", b"").unwrap();
+        let pagination: Pagination = request.query_as().unwrap();
+        assert_eq!(pagination.page, 2);
+        assert_eq!(pagination.size, None);
+
+        // Values are percent-decoded before parsing
+        let request =
+            HttpRequest::parse_from("GET /items?page=%33&size=1%30 HTTP/1.1\r\n\r\n", b"").unwrap();
+        let pagination: Pagination = request.query_as().unwrap();
+        assert_eq!(pagination.page, 3);
+        assert_eq!(pagination.size, Some(10));
+
+        let request = HttpRequest::parse_from("GET /items HTTP/1.1\r\n\r\n", b"").unwrap();
+        assert!(matches!(
+            request.query_as::<Pagination>(),
+            Err(Error::InvalidQuery("Missing query parameter"))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_form_as() {
+        #[derive(serde::Deserialize)]
+        struct Login<'a> {
+            user: &'a str,
+            remember: bool,
+        }
+
+        let head = "POST /login HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=UTF-8\r\n\r\n";
+        let request = HttpRequest::parse_from(head, b"user=admin&remember=true").unwrap();
+        let login: Login = request.form_as().unwrap();
+        assert_eq!(login.user, "admin");
+        assert!(login.remember);
+
+        let request = HttpRequest::parse_from(head, b"user=admin").unwrap();
+        assert!(matches!(
+            request.form_as::<Login>(),
+            Err(Error::InvalidForm("Missing form field"))
+        ));
+
+        let head = "POST /login HTTP/1.1\r\nContent-Type: application/json\r\n\r\n";
+        let request = HttpRequest::parse_from(head, b"user=admin&remember=true").unwrap();
+        assert!(matches!(
+            request.form_as::<Login>(),
+            Err(Error::InvalidForm("Not a form body"))
+        ));
+    }
+
+    #[test]
+    fn test_extensions() {
+        struct User(u32);
+        struct Session(&'static str);
+
+        let user = User(1);
+        let other = User(2);
+        let session = Session("abc");
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+        assert!(extensions.get::<User>().is_none());
+
+        extensions.insert(&user).ok().unwrap();
+        extensions.insert(&session).ok().unwrap();
+        assert_eq!(extensions.get::<User>().map(|u| u.0), Some(1));
+        assert_eq!(extensions.get::<Session>().map(|s| s.0), Some("abc"));
+
+        // Same type replaces the earlier value
+        extensions.insert(&other).ok().unwrap();
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get::<User>().map(|u| u.0), Some(2));
+    }
+
+    #[test]
+    fn test_extensions_full() {
+        let extra = 5u64;
+        let mut extensions = Extensions::new();
+        extensions.insert(&1u8).ok().unwrap();
+        extensions.insert(&1u16).ok().unwrap();
+        extensions.insert(&1u32).ok().unwrap();
+        extensions.insert(&1i8).ok().unwrap();
+
+        assert!(extensions.insert(&extra).is_err());
+        assert_eq!(extensions.len(), MAX_EXTENSIONS);
+    }
+
+    #[test]
+    fn test_parse_large_header_value() {
+        let token = "a".repeat(2048);
+        let request_str = format!(
+            "GET /api HTTP/1.1\r\nHost: device.local\r\nAuthorization: Bearer {token}\r\nAccept: */*\r\n\r\n"
+        );
+
+        let request = HttpRequest::try_from(request_str.as_bytes()).unwrap();
+
+        let authorization = request.get_header("Authorization").unwrap();
+        assert_eq!(authorization.len(), 2048 + 7);
+        assert!(authorization.ends_with(&token));
+        assert_eq!(request.get_header("Accept"), Some("*/*"));
+    }
 }
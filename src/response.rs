@@ -1,8 +1,29 @@
-use crate::{HttpHeader, StatusCode};
+use crate::{
+    Error, HttpHeader, StatusCode,
+    header::{headers, mime_types, named, others},
+};
 use heapless::Vec;
 
+/// Maximum number of headers in a response
+pub const MAX_RESPONSE_HEADERS: usize = 16;
+
+/// Maximum size in bytes of the headers a handler adds to a response
+///
+/// Counts every header line (`Name: value` and its CRLF) apart from those the server
+/// adds itself, like `Content-Length` or `Transfer-Encoding`. Responses over the
+/// budget are answered with `500 Internal Server Error` instead of being sent with
+/// headers that crowd out or truncate the body.
+pub const MAX_RESPONSE_HEADER_BYTES: usize = 2048;
+
+/// Function writing a lazily produced body into a buffer, see [`ResponseBody::Lazy`]
+pub type LazyBody<'a> = &'a dyn Fn(&mut [u8]) -> Result<usize, Error>;
+
 /// HTTP Response body that can handle both text and binary data using zero-copy references
-#[derive(Debug)]
+///
+/// Client responses only use [`Text`](Self::Text), [`Binary`](Self::Binary) and
+/// [`Empty`](Self::Empty). More server-side variants may follow, so `match`es outside
+/// this crate need a wildcard arm.
+#[non_exhaustive]
 pub enum ResponseBody<'a> {
     /// Text content (UTF-8 encoded) - borrowed from the response buffer
     Text(&'a str),
@@ -10,6 +31,64 @@ pub enum ResponseBody<'a> {
     Binary(&'a [u8]),
     /// Empty body (e.g., for HEAD requests or 204 No Content)
     Empty,
+    /// Body produced on demand by a server handler
+    ///
+    /// The function writes the body into the given buffer and returns its length. The
+    /// server only calls it when the body is actually sent, so rendering is skipped
+    /// for `HEAD` requests and statuses without a body like `304 Not Modified`. Until
+    /// then its content is unknown: [`as_bytes`](Self::as_bytes) and
+    /// [`len`](Self::len) see nothing, and [`as_str`](Self::as_str) returns `None`.
+    /// Client responses never use this variant.
+    Lazy(LazyBody<'a>),
+}
+
+impl core::fmt::Debug for ResponseBody<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ResponseBody::Text(s) => f.debug_tuple("Text").field(s).finish(),
+            ResponseBody::Binary(bytes) => f.debug_tuple("Binary").field(bytes).finish(),
+            ResponseBody::Empty => f.write_str("Empty"),
+            ResponseBody::Lazy(_) => f.debug_tuple("Lazy").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<'a> ResponseBody<'a> {
+    /// Format `args` into `buf` and use the result as a text body
+    ///
+    /// Saves handlers returning dynamic text a `heapless::String` of their own: the
+    /// text is written into the handler-owned `buf`, which the body then borrows. See
+    /// [`HttpResponse::text_fmt`] for a `text/plain` response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResponseTooLarge`] if the text does not fit into `buf`.
+    pub fn from_fmt(buf: &'a mut [u8], args: core::fmt::Arguments<'_>) -> Result<Self, Error> {
+        let mut writer = SliceWriter { buf, len: 0 };
+        core::fmt::write(&mut writer, args).map_err(|_| Error::ResponseTooLarge)?;
+        let SliceWriter { buf, len } = writer;
+        // Only whole `str`s were copied in
+        let text = core::str::from_utf8(&buf[..len]).map_err(|_| Error::ResponseTooLarge)?;
+        Ok(ResponseBody::Text(text))
+    }
+}
+
+/// Formatting target filling a byte slice, failing once it is full
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl core::fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len.checked_add(s.len()).ok_or(core::fmt::Error)?;
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
 }
 
 impl ResponseBody<'_> {
@@ -20,6 +99,7 @@ impl ResponseBody<'_> {
             ResponseBody::Text(s) => Some(s),
             ResponseBody::Binary(bytes) => core::str::from_utf8(bytes).ok(),
             ResponseBody::Empty => Some(""),
+            ResponseBody::Lazy(_) => None,
         }
     }
 
@@ -29,7 +109,7 @@ impl ResponseBody<'_> {
         match self {
             ResponseBody::Text(s) => s.as_bytes(),
             ResponseBody::Binary(bytes) => bytes,
-            ResponseBody::Empty => &[],
+            ResponseBody::Empty | ResponseBody::Lazy(_) => &[],
         }
     }
 
@@ -40,6 +120,7 @@ impl ResponseBody<'_> {
             ResponseBody::Text(s) => s.is_empty(),
             ResponseBody::Binary(bytes) => bytes.is_empty(),
             ResponseBody::Empty => true,
+            ResponseBody::Lazy(_) => false,
         }
     }
 
@@ -49,25 +130,453 @@ impl ResponseBody<'_> {
         match self {
             ResponseBody::Text(s) => s.len(),
             ResponseBody::Binary(bytes) => bytes.len(),
-            ResponseBody::Empty => 0,
+            ResponseBody::Empty | ResponseBody::Lazy(_) => 0,
         }
     }
 }
 
+/// Serializer writing a response body in a custom format
+///
+/// Implement this for CBOR, `MessagePack`, `postcard` or any other encoding and pass it to
+/// [`HttpResponse::serialized`], which writes the body into a handler-owned buffer and
+/// sets the `Content-Type` header.
+///
+/// ```rust
+/// use nanofish::{Error, ResponseSerializer};
+///
+/// struct Reading {
+///     celsius: i16,
+/// }
+///
+/// impl ResponseSerializer for Reading {
+///     fn content_type(&self) -> &'static str {
+///         "application/octet-stream"
+///     }
+///
+///     fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+///         let bytes = self.celsius.to_be_bytes();
+///         buf.get_mut(..bytes.len())
+///             .ok_or(Error::ResponseTooLarge)?
+///             .copy_from_slice(&bytes);
+///         Ok(bytes.len())
+///     }
+/// }
+/// ```
+pub trait ResponseSerializer {
+    /// MIME type of the serialized body, e.g. `application/cbor`
+    fn content_type(&self) -> &'static str;
+
+    /// Write the serialized body into `buf` and return the number of bytes written
+    ///
+    /// # Errors
+    ///
+    /// Should return [`Error::ResponseTooLarge`] if the body does not fit into `buf`.
+    fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
 /// HTTP Response struct with status code, headers and body
 ///
 /// This struct represents the response received from an HTTP server.
 /// It contains the status code, headers, and the response body which can be
 /// either text or binary data using zero-copy references.
+///
+/// The `Debug` and `defmt::Format` output only show the status, header count and
+/// body length, never the body itself.
 pub struct HttpResponse<'a> {
     /// The HTTP status code (e.g., 200 for OK, 404 for Not Found)
     pub status_code: StatusCode,
     /// A collection of response headers with both names and values
-    pub headers: Vec<HttpHeader<'a>, 16>,
+    pub headers: Vec<HttpHeader<'a>, MAX_RESPONSE_HEADERS>,
     /// The response body that can handle both text and binary data
     pub body: ResponseBody<'a>,
 }
 
+impl core::fmt::Debug for HttpResponse<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("status_code", &self.status_code.as_u16())
+            .field("headers", &self.headers.len())
+            .field("body_len", &self.body.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for HttpResponse<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "HttpResponse {{ status_code: {=u16}, headers: {=usize}, body_len: {=usize} }}",
+            self.status_code.as_u16(),
+            self.headers.len(),
+            self.body.len()
+        );
+    }
+}
+
+impl<'a> HttpResponse<'a> {
+    /// Create a response with a body and a matching `Content-Type` header
+    fn with_content_type(
+        status_code: StatusCode,
+        content_type: &'static str,
+        body: ResponseBody<'a>,
+    ) -> Self {
+        let mut headers = Vec::new();
+        let _ = headers.push(HttpHeader::content_type(content_type));
+        HttpResponse {
+            status_code,
+            headers,
+            body,
+        }
+    }
+
+    /// Create a `text/plain` response
+    #[must_use]
+    pub fn text(status_code: StatusCode, text: &'a str) -> Self {
+        Self::with_content_type(status_code, mime_types::TEXT, ResponseBody::Text(text))
+    }
+
+    /// Create a `text/html` response
+    #[must_use]
+    pub fn html(status_code: StatusCode, html: &'a str) -> Self {
+        Self::with_content_type(status_code, mime_types::HTML, ResponseBody::Text(html))
+    }
+
+    /// Create an `application/json` response from already serialized JSON
+    #[must_use]
+    pub fn json(status_code: StatusCode, json: &'a str) -> Self {
+        Self::with_content_type(status_code, mime_types::JSON, ResponseBody::Text(json))
+    }
+
+    /// Create a `text/plain` response with text formatted into `buf`
+    ///
+    /// ```rust
+    /// use nanofish::{HttpResponse, StatusCode};
+    ///
+    /// let mut buf = [0u8; 32];
+    /// let celsius = 21;
+    /// let response =
+    ///     HttpResponse::text_fmt(StatusCode::Ok, &mut buf, format_args!("Temperature: {celsius}C"))
+    ///         .unwrap();
+    /// assert_eq!(response.body.as_str(), Some("Temperature: 21C"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResponseTooLarge`] if the text does not fit into `buf`.
+    pub fn text_fmt(
+        status_code: StatusCode,
+        buf: &'a mut [u8],
+        args: core::fmt::Arguments<'_>,
+    ) -> Result<Self, Error> {
+        let body = ResponseBody::from_fmt(buf, args)?;
+        Ok(Self::with_content_type(status_code, mime_types::TEXT, body))
+    }
+
+    /// Create a response whose body is only rendered by `produce` when it is sent
+    ///
+    /// Useful for bodies that are expensive to build, e.g. a page rendered from
+    /// sensor readings, when the request may turn out to be a `HEAD` or the status
+    /// has no body. See [`ResponseBody::Lazy`].
+    ///
+    /// ```rust
+    /// use nanofish::{Error, HttpResponse, StatusCode};
+    ///
+    /// let response = HttpResponse::lazy(StatusCode::Ok, "text/plain", &|buf: &mut [u8]| {
+    ///     let body = b"rendered";
+    ///     buf.get_mut(..body.len())
+    ///         .ok_or(Error::ResponseTooLarge)?
+    ///         .copy_from_slice(body);
+    ///     Ok(body.len())
+    /// });
+    /// let bytes = response.build_bytes::<128>().unwrap();
+    /// assert!(bytes.ends_with(b"\r\n\r\nrendered"));
+    /// ```
+    #[must_use]
+    pub fn lazy(
+        status_code: StatusCode,
+        content_type: &'static str,
+        produce: LazyBody<'a>,
+    ) -> Self {
+        Self::with_content_type(status_code, content_type, ResponseBody::Lazy(produce))
+    }
+
+    /// Create a response without headers or body, e.g. `204 No Content`
+    #[must_use]
+    pub fn empty(status_code: StatusCode) -> Self {
+        HttpResponse {
+            status_code,
+            headers: Vec::new(),
+            body: ResponseBody::Empty,
+        }
+    }
+
+    /// Create a `200 OK` response with a `text/plain` body
+    #[must_use]
+    pub fn ok(text: &'a str) -> Self {
+        Self::text(StatusCode::Ok, text)
+    }
+
+    /// Create a `404 Not Found` response
+    #[must_use]
+    pub fn not_found() -> Self {
+        Self::text(StatusCode::NotFound, "Not Found")
+    }
+
+    /// Create a `201 Created` response pointing to the new resource at `location`
+    ///
+    /// The answer to a `PUT` or `POST` that created a resource. Use
+    /// [`HttpResponse::no_content`] (or a `200 OK` with a body) when a `PUT` replaced
+    /// an existing one instead.
+    #[must_use]
+    pub fn created(location: &'a str) -> Self {
+        let mut response = Self::empty(StatusCode::Created);
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::LOCATION, location));
+        response
+    }
+
+    /// Create a `202 Accepted` response for work that continues in the background
+    ///
+    /// Acknowledges a long operation like a calibration right after starting it
+    /// instead of blocking until it completes. With `status_url` the `Location`
+    /// header points to an endpoint the client can poll for the outcome.
+    #[must_use]
+    pub fn accepted(status_url: Option<&'a str>) -> Self {
+        let mut response = Self::empty(StatusCode::Accepted);
+        if let Some(status_url) = status_url {
+            let _ = response
+                .headers
+                .push(HttpHeader::new(headers::LOCATION, status_url));
+        }
+        response
+    }
+
+    /// Create a redirect to `location`, e.g. `303 See Other` after a form submission
+    ///
+    /// Build locations with query parameters with a
+    /// [`UrlBuilder`](crate::UrlBuilder), which encodes them properly.
+    #[must_use]
+    pub fn redirect(status_code: StatusCode, location: &'a str) -> Self {
+        let mut response = Self::empty(status_code);
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::LOCATION, location));
+        response
+    }
+
+    /// Create a `426 Upgrade Required` response asking the client to switch to `protocol`
+    ///
+    /// `protocol` goes into the `Upgrade` header, e.g. `"websocket"`, `"h2c"` or
+    /// `"TLS/1.2, HTTP/1.1"`, and `Connection: Upgrade` marks it as hop-by-hop.
+    #[must_use]
+    pub fn upgrade_required(protocol: &'a str) -> Self {
+        let mut response = Self::text(StatusCode::UpgradeRequired, "Upgrade Required");
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::UPGRADE, protocol));
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::CONNECTION, "Upgrade"));
+        response
+    }
+
+    /// Create a `101 Switching Protocols` response handing the connection to `protocol`
+    ///
+    /// After sending it the server calls
+    /// [`HttpHandlerMut::take_over`](crate::HttpHandlerMut::take_over), which speaks
+    /// `protocol` on the connection from then on. Protocol specific headers like
+    /// `Sec-WebSocket-Accept` can be pushed to `headers`.
+    #[must_use]
+    pub fn switching_protocols(protocol: &'a str) -> Self {
+        let mut response = Self::empty(StatusCode::SwitchingProtocols);
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::UPGRADE, protocol));
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::CONNECTION, "Upgrade"));
+        response
+    }
+
+    /// Create a `204 No Content` response, e.g. for an update that has nothing to return
+    #[must_use]
+    pub fn no_content() -> Self {
+        Self::empty(StatusCode::NoContent)
+    }
+
+    /// Set the `Cache-Control` header, replacing any existing one
+    ///
+    /// `value` can be a literal like `"no-store"` or a
+    /// [`CacheControl`](crate::CacheControl) formatted into a buffer that outlives
+    /// the response.
+    #[must_use]
+    pub fn with_cache_control(mut self, value: &'a str) -> Self {
+        self.headers
+            .retain(|h| !h.name.eq_ignore_ascii_case(headers::CACHE_CONTROL));
+        let _ = self
+            .headers
+            .push(HttpHeader::new(headers::CACHE_CONTROL, value));
+        self
+    }
+
+    /// Add a `Set-Cookie` header with `value`, e.g. `"session=abc; HttpOnly; Path=/"`
+    ///
+    /// Every call adds its own header line, as cookies must never be folded into one
+    /// comma-separated header. Cookies beyond [`MAX_RESPONSE_HEADERS`] are dropped.
+    #[must_use]
+    pub fn with_cookie(mut self, value: &'a str) -> Self {
+        let _ = self
+            .headers
+            .push(HttpHeader::new(headers::SET_COOKIE, value));
+        self
+    }
+
+    /// Mark the response as depending on the request header `name`, e.g. `Accept-Encoding`
+    ///
+    /// Adds a `Vary` header so caches store one variant per value of that request
+    /// header. Call it once per header the response was negotiated on; a name that is
+    /// already listed is not added again.
+    #[must_use]
+    pub fn with_vary(mut self, name: &'a str) -> Self {
+        let listed = self
+            .headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case(headers::VARY))
+            .flat_map(|h| h.value.split(','))
+            .any(|listed| listed.trim().eq_ignore_ascii_case(name));
+        if !listed {
+            let _ = self.headers.push(HttpHeader::new(headers::VARY, name));
+        }
+        self
+    }
+
+    /// Close the connection after this response, even if the client asked to keep it open
+    ///
+    /// Adds `Connection: close`, e.g. after an error that leaves the connection in an
+    /// unknown state, or to free the server task for other clients.
+    #[must_use]
+    pub fn with_connection_close(mut self) -> Self {
+        if !self.headers.iter().any(HttpHeader::closes_connection) {
+            let _ = self
+                .headers
+                .push(HttpHeader::new(headers::CONNECTION, "close"));
+        }
+        self
+    }
+
+    /// Append a pre-formatted `Name: Value` header line
+    ///
+    /// An escape hatch for headers without a typed helper, e.g. a `Set-Cookie` with
+    /// several attributes. The line must not include the trailing CRLF.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::HeaderError`] if the line has no colon, the name is not a valid
+    /// token, the line contains CR, LF or other control characters (which would allow
+    /// response splitting), or the response already has the maximum number of headers.
+    pub fn raw_header(&mut self, line: &'a str) -> Result<(), Error> {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(Error::HeaderError("Header line without colon"))?;
+        let header = HttpHeader::new(name, value.trim_matches([' ', '\t']));
+        if !header.is_valid() {
+            return Err(Error::HeaderError("Invalid characters in header line"));
+        }
+        self.headers
+            .push(header)
+            .map_err(|_| Error::HeaderError("Too many headers"))
+    }
+
+    /// Create a response whose body is produced by `serializer`
+    ///
+    /// The body is serialized into `buf`, which the response then borrows, and the
+    /// `Content-Type` header is taken from the serializer.
+    ///
+    /// # Errors
+    ///
+    /// Returns the serializer's error, e.g. [`Error::ResponseTooLarge`] if the body
+    /// does not fit into `buf`.
+    pub fn serialized<S: ResponseSerializer + ?Sized>(
+        status_code: StatusCode,
+        serializer: &S,
+        buf: &'a mut [u8],
+    ) -> Result<Self, Error> {
+        let len = serializer.serialize_into(buf)?;
+        let body = buf.get(..len).ok_or(Error::ResponseTooLarge)?;
+        Ok(Self::with_content_type(
+            status_code,
+            serializer.content_type(),
+            ResponseBody::Binary(body),
+        ))
+    }
+
+    /// Create a `200 OK` response that browsers save as a file named `filename`
+    ///
+    /// Sets `Content-Disposition: attachment` with the filename quoted and escaped.
+    /// Names with non-ASCII characters get an ASCII fallback plus a
+    /// percent-encoded `filename*` parameter. The header value is written into `buf`,
+    /// which the response then borrows.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::HeaderError`] if the header value does not fit into `buf`.
+    pub fn attachment(
+        content_type: &'static str,
+        filename: &str,
+        body: &'a [u8],
+        buf: &'a mut [u8],
+    ) -> Result<Self, Error> {
+        let value = content_disposition(filename, buf).ok_or(Error::HeaderError(
+            "Content-Disposition does not fit into the buffer",
+        ))?;
+        let mut response =
+            Self::with_content_type(StatusCode::Ok, content_type, ResponseBody::Binary(body));
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::CONTENT_DISPOSITION, value));
+        Ok(response)
+    }
+}
+
+/// Write the `Content-Disposition` value for downloading `filename` into `buf`
+///
+/// Returns the value borrowed from `buf`, or `None` if `buf` is too small.
+fn content_disposition<'b>(filename: &str, buf: &'b mut [u8]) -> Option<&'b str> {
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| {
+        let end = len + bytes.len();
+        buf.get_mut(len..end)?.copy_from_slice(bytes);
+        len = end;
+        Some(())
+    };
+
+    push(b"attachment; filename=\"")?;
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' => push(&[b'\\', c as u8])?,
+            ' '..='~' => push(&[c as u8])?,
+            _ => push(b"_")?,
+        }
+    }
+    push(b"\"")?;
+
+    if !filename.is_ascii() {
+        push(b"; filename*=UTF-8''")?;
+        for &b in filename.as_bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                push(&[b])?;
+            } else {
+                const HEX: &[u8; 16] = b"0123456789ABCDEF";
+                push(&[b'%', HEX[usize::from(b >> 4)], HEX[usize::from(b & 0xf)]])?;
+            }
+        }
+    }
+    // Only ASCII is ever written
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
 impl HttpResponse<'_> {
     /// Get a header value by name (case-insensitive)
     #[must_use]
@@ -109,67 +618,193 @@ impl HttpResponse<'_> {
     }
 
     /// Build HTTP response bytes from this `HttpResponse`
-    #[must_use]
-    pub fn build_bytes<const MAX_RESPONSE_SIZE: usize>(&self) -> Vec<u8, MAX_RESPONSE_SIZE> {
+    ///
+    /// For status codes that do not allow a body (1xx, 204 and 304, see
+    /// [`StatusCode::allows_body`]) the body and `Content-Length` are left out. For
+    /// all others `Content-Length` is always written, `0` for an empty body, so that
+    /// the client knows where the response ends on a kept-alive connection. A
+    /// [`ResponseBody::Lazy`] body is produced directly into the output buffer.
+    /// `Content-Length` always counts the body bytes written; one added to `headers`,
+    /// e.g. the size before compression, is ignored then. Where no length is written,
+    /// e.g. for a `304 Not Modified`, a `Content-Length` from `headers` is passed on.
+    ///
+    /// The output is deterministic: `Content-Type`, `Content-Length`, `Date` and
+    /// `Connection` come first in this order, followed by all other headers in the
+    /// order they were added. Well-known header names are written in their canonical
+    /// casing (see [`HttpHeader::canonical_name`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResponseTooLarge`] if the status line, headers and body do not
+    /// fit into `MAX_RESPONSE_SIZE` bytes, or [`Error::HeaderError`] if a header fails
+    /// [`HttpHeader::is_valid`], e.g. because a value contains CR or LF, or the headers
+    /// exceed [`MAX_RESPONSE_HEADER_BYTES`]. Errors of a lazy body are passed on.
+    pub fn build_bytes<const MAX_RESPONSE_SIZE: usize>(
+        &self,
+    ) -> Result<Vec<u8, MAX_RESPONSE_SIZE>, Error> {
+        self.build(true)
+    }
+
+    /// Build the response bytes, leaving out the body if `with_body` is false
+    ///
+    /// Used for `HEAD` requests: an eager body still sets `Content-Length`, while a
+    /// lazy body is not produced at all and so has none.
+    pub(crate) fn build<const MAX_RESPONSE_SIZE: usize>(
+        &self,
+        with_body: bool,
+    ) -> Result<Vec<u8, MAX_RESPONSE_SIZE>, Error> {
+        check_headers(&self.headers)?;
         let mut bytes = Vec::new();
+        let allows_body = self.status_code.allows_body();
 
-        // Status line: HTTP/1.1 <code> <reason>\r\n
-        write_status_line(&mut bytes, self.status_code);
-
-        // Headers
-        for header in &self.headers {
-            let _ = bytes.extend_from_slice(header.name.as_bytes());
-            let _ = bytes.extend_from_slice(b": ");
-            let _ = bytes.extend_from_slice(header.value.as_bytes());
-            let _ = bytes.extend_from_slice(b"\r\n");
+        if let (ResponseBody::Lazy(produce), true, true) = (&self.body, allows_body, with_body) {
+            // Produce the body first, then move it behind the head once its size is known
+            bytes
+                .resize(MAX_RESPONSE_SIZE, 0)
+                .map_err(|_| Error::ResponseTooLarge)?;
+            let len = produce(&mut bytes)?;
+            let mut head_len = 0;
+            self.write_head(Some(len), |data| {
+                head_len += data.len();
+                Ok(())
+            })?;
+            if len > MAX_RESPONSE_SIZE || head_len + len > MAX_RESPONSE_SIZE {
+                return Err(Error::ResponseTooLarge);
+            }
+            bytes.copy_within(..len, head_len);
+            let mut pos = 0;
+            self.write_head(Some(len), |data| {
+                let end = pos + data.len();
+                bytes
+                    .get_mut(pos..end)
+                    .ok_or(Error::ResponseTooLarge)?
+                    .copy_from_slice(data);
+                pos = end;
+                Ok(())
+            })?;
+            bytes.truncate(head_len + len);
+            return Ok(bytes);
         }
 
-        // Content-Length header if body is present
-        let body_bytes = self.body.as_bytes();
-        if !body_bytes.is_empty() {
-            let _ = bytes.extend_from_slice(b"Content-Length: ");
-            write_decimal_to_buffer(&mut bytes, body_bytes.len());
-            let _ = bytes.extend_from_slice(b"\r\n");
+        let body_bytes = if allows_body {
+            self.body.as_bytes()
+        } else {
+            &[]
+        };
+        // The size of a lazy body that was not produced is unknown
+        let content_length = (allows_body && !matches!(self.body, ResponseBody::Lazy(_)))
+            .then_some(body_bytes.len());
+        self.write_head(content_length, |data| extend(&mut bytes, data))?;
+        if with_body {
+            extend(&mut bytes, body_bytes)?;
         }
+        Ok(bytes)
+    }
 
-        // End of headers
-        let _ = bytes.extend_from_slice(b"\r\n");
+    /// Pass the status line and headers, including the blank line ending them, to `write`
+    ///
+    /// `Content-Length` is written if `content_length` is given.
+    fn write_head(
+        &self,
+        content_length: Option<usize>,
+        mut write: impl FnMut(&[u8]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        // Status line: HTTP/1.1 <code> <reason>\r\n
+        let mut status_line = Vec::<u8, 64>::new();
+        write_status_line(&mut status_line, self.status_code)?;
+        write(&status_line)?;
 
-        // Body
-        let _ = bytes.extend_from_slice(body_bytes);
+        // Content-Type, Content-Length, Date and Connection come first, then the rest
+        for header in named(&self.headers, headers::CONTENT_TYPE) {
+            write_header(&mut write, header)?;
+        }
+        if let Some(content_length) = content_length {
+            let mut digits = Vec::<u8, 20>::new();
+            write_decimal_to_buffer(&mut digits, content_length)?;
+            write(b"Content-Length: ")?;
+            write(&digits)?;
+            write(b"\r\n")?;
+        }
+        let rest = [headers::DATE, headers::CONNECTION]
+            .into_iter()
+            .flat_map(|name| named(&self.headers, name))
+            .chain(others(&self.headers, content_length.is_some()));
+        for header in rest {
+            write_header(&mut write, header)?;
+        }
 
-        bytes
+        // End of headers
+        write(b"\r\n")
     }
 }
 
+/// Check that response headers are well-formed and within the count and size limits
+pub(crate) fn check_headers(headers: &[HttpHeader<'_>]) -> Result<(), Error> {
+    if !headers.iter().all(HttpHeader::is_valid) {
+        return Err(Error::HeaderError("Invalid characters in header"));
+    }
+    if headers.len() > MAX_RESPONSE_HEADERS {
+        return Err(Error::HeaderError("Too many response headers"));
+    }
+    let size: usize = headers
+        .iter()
+        .map(|header| header.name.len() + header.value.len() + 4)
+        .sum();
+    if size > MAX_RESPONSE_HEADER_BYTES {
+        return Err(Error::HeaderError("Response headers too large"));
+    }
+    Ok(())
+}
+
+/// Pass a `Name: value` header line to `write`
+fn write_header(
+    write: &mut impl FnMut(&[u8]) -> Result<(), Error>,
+    header: &HttpHeader,
+) -> Result<(), Error> {
+    write(header.canonical_name().as_bytes())?;
+    write(b": ")?;
+    write(header.value.as_bytes())?;
+    write(b"\r\n")
+}
+
+/// Append `data` to the buffer, failing if it does not fit
+fn extend<const MAX_RESPONSE_SIZE: usize>(
+    bytes: &mut Vec<u8, MAX_RESPONSE_SIZE>,
+    data: &[u8],
+) -> Result<(), Error> {
+    bytes
+        .extend_from_slice(data)
+        .map_err(|_| Error::ResponseTooLarge)
+}
+
 /// Write HTTP status line to the given buffer
-fn write_status_line<const MAX_RESPONSE_SIZE: usize>(
+pub(crate) fn write_status_line<const MAX_RESPONSE_SIZE: usize>(
     bytes: &mut Vec<u8, MAX_RESPONSE_SIZE>,
     status_code: StatusCode,
-) {
+) -> Result<(), Error> {
     // Write "HTTP/1.1 "
-    let _ = bytes.extend_from_slice(b"HTTP/1.1 ");
+    extend(bytes, b"HTTP/1.1 ")?;
 
     // Write status code as decimal
-    write_decimal_to_buffer(bytes, status_code.as_u16() as usize);
+    write_decimal_to_buffer(bytes, status_code.as_u16() as usize)?;
 
     // Write " <reason>\r\n"
-    let _ = bytes.push(b' ');
-    let _ = bytes.extend_from_slice(status_code.text().as_bytes());
-    let _ = bytes.extend_from_slice(b"\r\n");
+    extend(bytes, b" ")?;
+    extend(bytes, status_code.text().as_bytes())?;
+    extend(bytes, b"\r\n")
 }
 
 /// Write a decimal number to the buffer
 fn write_decimal_to_buffer<const MAX_RESPONSE_SIZE: usize>(
     bytes: &mut Vec<u8, MAX_RESPONSE_SIZE>,
     mut num: usize,
-) {
+) -> Result<(), Error> {
     if num == 0 {
-        let _ = bytes.push(b'0');
-        return;
+        return extend(bytes, b"0");
     }
 
-    let mut digits = [0u8; 10];
+    // Enough for `usize::MAX` on 64-bit targets
+    let mut digits = [0u8; 20];
     let mut i = 0;
 
     while num > 0 {
@@ -182,9 +817,8 @@ fn write_decimal_to_buffer<const MAX_RESPONSE_SIZE: usize>(
     }
 
     // Write digits in reverse order
-    for j in (0..i).rev() {
-        let _ = bytes.push(digits[j]);
-    }
+    digits[..i].reverse();
+    extend(bytes, &digits[..i])
 }
 
 #[cfg(test)]
@@ -237,6 +871,19 @@ mod tests {
         assert_eq!(resp.get_header("missing"), None);
     }
 
+    #[test]
+    fn test_http_response_debug_summary() {
+        let response = HttpResponse {
+            status_code: StatusCode::NotFound,
+            headers: Vec::new(),
+            body: ResponseBody::Text("secret page"),
+        };
+        assert_eq!(
+            format!("{response:?}"),
+            "HttpResponse { status_code: 404, headers: 0, body_len: 11 }"
+        );
+    }
+
     #[test]
     fn test_build_http_response_ok() {
         let mut headers = Vec::new();
@@ -249,7 +896,7 @@ mod tests {
             body: ResponseBody::Text("Hello World!"),
         };
 
-        let bytes = response.build_bytes::<4096>();
+        let bytes = response.build_bytes::<4096>().unwrap();
         let response_str = core::str::from_utf8(&bytes).unwrap();
 
         assert!(response_str.starts_with("HTTP/1.1 200 OK\r\n"));
@@ -266,7 +913,7 @@ mod tests {
             body: ResponseBody::Text("Not Found"),
         };
 
-        let bytes = response.build_bytes::<4096>();
+        let bytes = response.build_bytes::<4096>().unwrap();
         let response_str = core::str::from_utf8(&bytes).unwrap();
 
         assert!(response_str.starts_with("HTTP/1.1 404 Not Found\r\n"));
@@ -282,7 +929,7 @@ mod tests {
             body: ResponseBody::Empty,
         };
 
-        let bytes = response.build_bytes::<4096>();
+        let bytes = response.build_bytes::<4096>().unwrap();
         let response_str = core::str::from_utf8(&bytes).unwrap();
 
         assert!(response_str.starts_with("HTTP/1.1 204 No Content\r\n"));
@@ -299,7 +946,7 @@ mod tests {
             body: ResponseBody::Binary(binary_data),
         };
 
-        let bytes = response.build_bytes::<4096>();
+        let bytes = response.build_bytes::<4096>().unwrap();
 
         // Check that the response contains the binary data at the end
         assert!(bytes.ends_with(binary_data));
@@ -309,30 +956,479 @@ mod tests {
         assert!(response_str.contains("Content-Length: 4\r\n"));
     }
 
+    #[test]
+    fn test_build_bytes_response_too_large() {
+        let response = HttpResponse {
+            status_code: StatusCode::Ok,
+            headers: Vec::new(),
+            body: ResponseBody::Text("This body does not fit"),
+        };
+
+        let result = response.build_bytes::<32>();
+        assert!(matches!(result, Err(Error::ResponseTooLarge)));
+
+        // Exactly enough room for status line, Content-Length, blank line and body
+        let response = HttpResponse {
+            status_code: StatusCode::Ok,
+            headers: Vec::new(),
+            body: ResponseBody::Text("hi"),
+        };
+        let bytes = response.build_bytes::<40>().unwrap();
+        assert_eq!(bytes, b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi");
+        assert!(response.build_bytes::<39>().is_err());
+    }
+
+    #[test]
+    fn test_response_shortcuts() {
+        let response = HttpResponse::text(StatusCode::BadRequest, "bad");
+        assert_eq!(response.status_code, StatusCode::BadRequest);
+        assert_eq!(response.content_type(), Some("text/plain"));
+        assert_eq!(response.body.as_str(), Some("bad"));
+
+        let response = HttpResponse::html(StatusCode::Ok, "<p>hi</p>");
+        assert_eq!(response.content_type(), Some("text/html"));
+
+        let response = HttpResponse::json(StatusCode::Created, "{\"id\":1}");
+        assert_eq!(response.status_code, StatusCode::Created);
+        assert_eq!(response.content_type(), Some("application/json"));
+        assert_eq!(response.body.as_str(), Some("{\"id\":1}"));
+
+        let response = HttpResponse::ok("fine");
+        assert_eq!(response.status_code, StatusCode::Ok);
+        assert_eq!(response.body.as_str(), Some("fine"));
+
+        let response = HttpResponse::not_found();
+        assert_eq!(response.status_code, StatusCode::NotFound);
+        assert_eq!(response.body.as_str(), Some("Not Found"));
+
+        let response = HttpResponse::empty(StatusCode::NoContent);
+        assert!(response.headers.is_empty());
+        assert!(response.body.is_empty());
+
+        let response = HttpResponse::created("/sensors/3");
+        assert_eq!(response.status_code, StatusCode::Created);
+        assert_eq!(response.get_header("location"), Some("/sensors/3"));
+        assert!(response.body.is_empty());
+
+        let response = HttpResponse::accepted(Some("/calibration/status"));
+        assert_eq!(response.status_code, StatusCode::Accepted);
+        assert_eq!(response.get_header("Location"), Some("/calibration/status"));
+        assert!(response.body.is_empty());
+
+        let response = HttpResponse::accepted(None);
+        assert_eq!(response.status_code, StatusCode::Accepted);
+        assert!(response.headers.is_empty());
+
+        let response = HttpResponse::redirect(StatusCode::SeeOther, "/done?id=1");
+        assert_eq!(response.status_code, StatusCode::SeeOther);
+        assert_eq!(response.get_header("Location"), Some("/done?id=1"));
+
+        let response = HttpResponse::upgrade_required("websocket");
+        assert_eq!(response.status_code, StatusCode::UpgradeRequired);
+        assert_eq!(response.get_header("upgrade"), Some("websocket"));
+        assert_eq!(response.get_header("Connection"), Some("Upgrade"));
+
+        let response = HttpResponse::switching_protocols("websocket");
+        assert_eq!(response.status_code, StatusCode::SwitchingProtocols);
+        assert_eq!(response.get_header("Upgrade"), Some("websocket"));
+        assert!(response.body.is_empty());
+
+        let response = HttpResponse::no_content();
+        assert_eq!(response.status_code, StatusCode::NoContent);
+        assert!(response.headers.is_empty());
+    }
+
+    #[test]
+    fn test_with_cache_control() {
+        let response = HttpResponse::ok("cached")
+            .with_cache_control("no-store")
+            .with_cache_control("public, max-age=60");
+
+        assert_eq!(response.headers.len(), 2);
+        assert_eq!(
+            response.get_header("Cache-Control"),
+            Some("public, max-age=60")
+        );
+    }
+
+    /// Length-prefixed encoding of a list of readings
+    struct Readings<'a>(&'a [u16]);
+
+    impl ResponseSerializer for Readings<'_> {
+        fn content_type(&self) -> &'static str {
+            "application/x-readings"
+        }
+
+        fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+            let len = 1 + self.0.len() * 2;
+            let out = buf.get_mut(..len).ok_or(Error::ResponseTooLarge)?;
+            out[0] = u8::try_from(self.0.len()).map_err(|_| Error::ResponseTooLarge)?;
+            for (chunk, reading) in out[1..].chunks_mut(2).zip(self.0) {
+                chunk.copy_from_slice(&reading.to_be_bytes());
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_serialized_response() {
+        let mut buf = [0u8; 16];
+        let response =
+            HttpResponse::serialized(StatusCode::Ok, &Readings(&[1, 0x0203]), &mut buf).unwrap();
+
+        assert_eq!(response.content_type(), Some("application/x-readings"));
+        assert_eq!(response.body.as_bytes(), &[2, 0, 1, 2, 3]);
+
+        let bytes = response.build_bytes::<128>().unwrap();
+        assert!(bytes.starts_with(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-readings\r\n"));
+        assert!(bytes.ends_with(b"Content-Length: 5\r\n\r\n\x02\x00\x01\x02\x03"));
+    }
+
+    #[test]
+    fn test_serialized_response_too_large() {
+        let mut buf = [0u8; 4];
+        let result = HttpResponse::serialized(StatusCode::Ok, &Readings(&[1, 2]), &mut buf);
+        assert!(matches!(result, Err(Error::ResponseTooLarge)));
+    }
+
+    #[test]
+    fn test_attachment_response() {
+        let mut buf = [0u8; 64];
+        let response =
+            HttpResponse::attachment("text/csv", "log.csv", b"t,v\n1,2\n", &mut buf).unwrap();
+        assert_eq!(response.content_type(), Some("text/csv"));
+        assert_eq!(
+            response.get_header("Content-Disposition"),
+            Some("attachment; filename=\"log.csv\"")
+        );
+        assert_eq!(response.body.as_bytes(), b"t,v\n1,2\n");
+    }
+
+    #[test]
+    fn test_attachment_filename_escaping() {
+        let mut buf = [0u8; 128];
+        let response =
+            HttpResponse::attachment(mime_types::BINARY, "my \"cfg\"\\1.bin", b"", &mut buf)
+                .unwrap();
+        assert_eq!(
+            response.get_header("Content-Disposition"),
+            Some("attachment; filename=\"my \\\"cfg\\\"\\\\1.bin\"")
+        );
+
+        let mut buf = [0u8; 128];
+        let response =
+            HttpResponse::attachment(mime_types::BINARY, "Grüße\r\n.txt", b"", &mut buf).unwrap();
+        assert_eq!(
+            response.get_header("Content-Disposition"),
+            Some(
+                "attachment; filename=\"Gr__e__.txt\"; filename*=UTF-8''Gr%C3%BC%C3%9Fe%0D%0A.txt"
+            )
+        );
+
+        let mut buf = [0u8; 16];
+        let result = HttpResponse::attachment(mime_types::BINARY, "backup.json", b"", &mut buf);
+        assert!(matches!(result, Err(Error::HeaderError(_))));
+    }
+
+    #[test]
+    fn test_with_vary() {
+        let response = HttpResponse::ok("hi")
+            .with_vary("Accept-Encoding")
+            .with_vary("Accept-Language")
+            .with_vary("accept-encoding");
+        let vary: std::vec::Vec<&str> = response
+            .headers
+            .iter()
+            .filter(|h| h.name == "Vary")
+            .map(|h| h.value)
+            .collect();
+        assert_eq!(vary, ["Accept-Encoding", "Accept-Language"]);
+
+        let bytes = response.build_bytes::<256>().unwrap();
+        let text = core::str::from_utf8(&bytes).unwrap();
+        assert!(text.contains("\r\nVary: Accept-Encoding\r\nVary: Accept-Language\r\n"));
+    }
+
+    #[test]
+    fn test_with_connection_close() {
+        let response = HttpResponse::ok("bye")
+            .with_connection_close()
+            .with_connection_close();
+        let closing = response
+            .headers
+            .iter()
+            .filter(|h| h.name == "Connection")
+            .count();
+        assert_eq!(closing, 1);
+        assert_eq!(response.get_header("connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_raw_header() {
+        let mut response = HttpResponse::ok("hi");
+        response
+            .raw_header("Set-Cookie: session=abc; Path=/; HttpOnly; SameSite=Strict")
+            .unwrap();
+        response.raw_header("X-Vendor:1").unwrap();
+        assert_eq!(
+            response.get_header("set-cookie"),
+            Some("session=abc; Path=/; HttpOnly; SameSite=Strict")
+        );
+        assert_eq!(response.get_header("X-Vendor"), Some("1"));
+
+        let bytes = response.build_bytes::<256>().unwrap();
+        let text = core::str::from_utf8(&bytes).unwrap();
+        assert!(
+            text.contains("\r\nSet-Cookie: session=abc; Path=/; HttpOnly; SameSite=Strict\r\n")
+        );
+        assert!(text.contains("\r\nX-Vendor: 1\r\n"));
+    }
+
+    #[test]
+    fn test_write_decimal_max() {
+        let mut bytes = Vec::<u8, 32>::new();
+        write_decimal_to_buffer(&mut bytes, usize::MAX).unwrap();
+        assert_eq!(bytes.as_slice(), usize::MAX.to_string().as_bytes());
+    }
+
+    #[test]
+    fn test_build_bytes_header_budget() {
+        let value = "v".repeat(MAX_RESPONSE_HEADER_BYTES - "X-Big: \r\n".len());
+        let mut response = HttpResponse::empty(StatusCode::Ok);
+        let _ = response.headers.push(HttpHeader::new("X-Big", &value));
+        assert!(response.build_bytes::<4096>().is_ok());
+
+        let _ = response.headers.push(HttpHeader::new("X", ""));
+        assert!(matches!(
+            response.build_bytes::<4096>(),
+            Err(Error::HeaderError("Response headers too large"))
+        ));
+    }
+
+    #[test]
+    fn test_build_bytes_rejects_header_injection() {
+        // E.g. a handler reflecting a query parameter into a header
+        let mut response = HttpResponse::ok("hi");
+        let _ = response
+            .headers
+            .push(HttpHeader::new("X-Echo", "a\r\n\r\n<script>"));
+        assert!(matches!(
+            response.build_bytes::<256>(),
+            Err(Error::HeaderError(_))
+        ));
+    }
+
+    #[test]
+    fn test_raw_header_rejects_injection() {
+        let mut response = HttpResponse::ok("hi");
+        for line in [
+            "X-Evil: 1\r\nSet-Cookie: admin=1",
+            "X-Evil: 1\n\nbody",
+            "X-Evil: 1\r\n",
+            "No colon here",
+            ": no name",
+            "Bad Name: 1",
+        ] {
+            assert!(matches!(
+                response.raw_header(line),
+                Err(Error::HeaderError(_))
+            ));
+        }
+        assert_eq!(response.headers.len(), 1);
+    }
+
+    #[test]
+    fn test_build_bytes_status_without_body() {
+        let cases = [
+            (StatusCode::NoContent, "HTTP/1.1 204 No Content\r\n\r\n"),
+            (StatusCode::NotModified, "HTTP/1.1 304 Not Modified\r\n\r\n"),
+            (
+                StatusCode::SwitchingProtocols,
+                "HTTP/1.1 101 Switching Protocols\r\n\r\n",
+            ),
+        ];
+        for (status_code, expected) in cases {
+            // A body set by mistake is not sent
+            let response = HttpResponse {
+                status_code,
+                headers: Vec::new(),
+                body: ResponseBody::Text("ignored"),
+            };
+            let bytes = response.build_bytes::<128>().unwrap();
+            assert_eq!(core::str::from_utf8(&bytes).unwrap(), expected);
+        }
+
+        let response = HttpResponse::text(StatusCode::Other(299), "kept");
+        let bytes = response.build_bytes::<128>().unwrap();
+        assert!(bytes.ends_with(b"Content-Length: 4\r\n\r\nkept"));
+    }
+
+    #[test]
+    fn test_build_bytes_header_order_and_casing() {
+        let mut response = HttpResponse::ok("hi");
+        response.headers.clear();
+        for (name, value) in [
+            ("x-request-id", "7"),
+            ("connection", "close"),
+            ("cache-control", "no-store"),
+            ("date", "Tue, 14 Oct 2025 08:00:00 GMT"),
+            ("content-type", "text/plain"),
+        ] {
+            response.headers.push(HttpHeader::new(name, value)).unwrap();
+        }
+        let bytes = response.build_bytes::<512>().unwrap();
+        assert_eq!(
+            core::str::from_utf8(&bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: 2\r\n\
+             Date: Tue, 14 Oct 2025 08:00:00 GMT\r\n\
+             Connection: close\r\n\
+             x-request-id: 7\r\n\
+             Cache-Control: no-store\r\n\r\nhi"
+        );
+    }
+
+    #[test]
+    fn test_multiple_cookies() {
+        let response = HttpResponse::ok("hi")
+            .with_cookie("session=abc; HttpOnly")
+            .with_cache_control("no-store")
+            .with_cookie("theme=dark, blue; Path=/");
+        let bytes = response.build_bytes::<512>().unwrap();
+        let text = core::str::from_utf8(&bytes).unwrap();
+        assert!(text.contains(
+            "Set-Cookie: session=abc; HttpOnly\r\n\
+             Cache-Control: no-store\r\n\
+             Set-Cookie: theme=dark, blue; Path=/\r\n"
+        ));
+        assert_eq!(text.matches("Set-Cookie").count(), 2);
+    }
+
+    #[test]
+    fn test_content_length_counts_sent_body() {
+        // A handler passing on the length from before compression
+        let mut response = HttpResponse {
+            status_code: StatusCode::Ok,
+            headers: Vec::new(),
+            body: ResponseBody::Binary(b"\x1f\x8b\x08\x00"),
+        };
+        let _ = response
+            .headers
+            .push(HttpHeader::new("Content-Encoding", "gzip"));
+        let _ = response
+            .headers
+            .push(HttpHeader::new("content-length", "4096"));
+        let bytes = response.build_bytes::<256>().unwrap();
+        let text = core::str::from_utf8(&bytes[..bytes.len() - 4]).unwrap();
+        assert!(text.contains("Content-Length: 4\r\n"));
+        assert_eq!(text.matches("ength:").count(), 1);
+        assert!(text.ends_with("Content-Encoding: gzip\r\n\r\n"));
+
+        // Without a body the handler's value is the only one
+        response.status_code = StatusCode::NotModified;
+        let bytes = response.build_bytes::<256>().unwrap();
+        let text = core::str::from_utf8(&bytes).unwrap();
+        assert!(text.ends_with("Content-Length: 4096\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_text_fmt() {
+        let mut buf = [0u8; 16];
+        let response =
+            HttpResponse::text_fmt(StatusCode::Ok, &mut buf, format_args!("{}: {}C", "t", -4))
+                .unwrap();
+        assert_eq!(response.body.as_str(), Some("t: -4C"));
+        assert_eq!(response.content_type(), Some("text/plain"));
+
+        // Exactly full still fits, one byte more does not
+        let mut buf = [0u8; 4];
+        let body = ResponseBody::from_fmt(&mut buf, format_args!("{}", 1234)).unwrap();
+        assert_eq!(body.as_str(), Some("1234"));
+        let mut buf = [0u8; 4];
+        assert!(matches!(
+            ResponseBody::from_fmt(&mut buf, format_args!("{}", 12345)),
+            Err(Error::ResponseTooLarge)
+        ));
+        assert!(matches!(
+            HttpResponse::text_fmt(StatusCode::Ok, &mut [], format_args!("x")),
+            Err(Error::ResponseTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_lazy_body() {
+        let renders = core::cell::Cell::new(0);
+        let produce = |buf: &mut [u8]| {
+            renders.set(renders.get() + 1);
+            buf[..5].copy_from_slice(b"hello");
+            Ok(5)
+        };
+        let response = HttpResponse::lazy(StatusCode::Ok, "text/plain", &produce);
+        let bytes = response.build_bytes::<128>().unwrap();
+        assert_eq!(
+            core::str::from_utf8(&bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello"
+        );
+        assert_eq!(renders.get(), 1);
+
+        // Not produced without a body
+        let bytes = response.build::<128>(false).unwrap();
+        assert!(bytes.ends_with(b"Content-Type: text/plain\r\n\r\n"));
+        let mut not_modified = HttpResponse::lazy(StatusCode::NotModified, "text/plain", &produce);
+        not_modified.headers.clear();
+        let bytes = not_modified.build_bytes::<128>().unwrap();
+        assert_eq!(&bytes[..], b"HTTP/1.1 304 Not Modified\r\n\r\n");
+        assert_eq!(renders.get(), 1);
+
+        // Body fits the buffer, but not together with the head
+        assert!(matches!(
+            response.build_bytes::<60>(),
+            Err(Error::ResponseTooLarge)
+        ));
+        assert!(format!("{:?}", response.body).starts_with("Lazy"));
+    }
+
+    #[test]
+    fn test_lazy_body_errors() {
+        let lying = HttpResponse::lazy(StatusCode::Ok, "text/plain", &|_: &mut [u8]| Ok(500));
+        assert!(matches!(
+            lying.build_bytes::<128>(),
+            Err(Error::ResponseTooLarge)
+        ));
+        let failing = HttpResponse::lazy(StatusCode::Ok, "text/plain", &|_: &mut [u8]| {
+            Err(Error::HeaderError("render failed"))
+        });
+        assert!(matches!(
+            failing.build_bytes::<128>(),
+            Err(Error::HeaderError("render failed"))
+        ));
+    }
+
     #[test]
     fn test_write_decimal_to_buffer() {
         let mut bytes: Vec<u8, 64> = Vec::new();
 
         // Test zero
-        write_decimal_to_buffer(&mut bytes, 0);
+        write_decimal_to_buffer(&mut bytes, 0).unwrap();
         assert_eq!(bytes, b"0");
 
         // Test single digit
         bytes.clear();
-        write_decimal_to_buffer(&mut bytes, 5);
+        write_decimal_to_buffer(&mut bytes, 5).unwrap();
         assert_eq!(bytes, b"5");
 
         // Test multi-digit numbers
         bytes.clear();
-        write_decimal_to_buffer(&mut bytes, 42);
+        write_decimal_to_buffer(&mut bytes, 42).unwrap();
         assert_eq!(bytes, b"42");
 
         bytes.clear();
-        write_decimal_to_buffer(&mut bytes, 123);
+        write_decimal_to_buffer(&mut bytes, 123).unwrap();
         assert_eq!(bytes, b"123");
 
         bytes.clear();
-        write_decimal_to_buffer(&mut bytes, 9999);
+        write_decimal_to_buffer(&mut bytes, 9999).unwrap();
         assert_eq!(bytes, b"9999");
     }
 
@@ -341,19 +1437,19 @@ mod tests {
         let mut bytes: Vec<u8, 64> = Vec::new();
 
         // Test common status codes
-        write_status_line(&mut bytes, StatusCode::Ok);
+        write_status_line(&mut bytes, StatusCode::Ok).unwrap();
         assert_eq!(bytes, b"HTTP/1.1 200 OK\r\n");
 
         bytes.clear();
-        write_status_line(&mut bytes, StatusCode::NotFound);
+        write_status_line(&mut bytes, StatusCode::NotFound).unwrap();
         assert_eq!(bytes, b"HTTP/1.1 404 Not Found\r\n");
 
         bytes.clear();
-        write_status_line(&mut bytes, StatusCode::InternalServerError);
+        write_status_line(&mut bytes, StatusCode::InternalServerError).unwrap();
         assert_eq!(bytes, b"HTTP/1.1 500 Internal Server Error\r\n");
 
         bytes.clear();
-        write_status_line(&mut bytes, StatusCode::Created);
+        write_status_line(&mut bytes, StatusCode::Created).unwrap();
         assert_eq!(bytes, b"HTTP/1.1 201 Created\r\n");
     }
 
@@ -379,7 +1475,7 @@ mod tests {
                 body: ResponseBody::Text(body_text),
             };
 
-            let bytes = response.build_bytes::<4096>();
+            let bytes = response.build_bytes::<4096>().unwrap();
             let response_str = core::str::from_utf8(&bytes).unwrap();
 
             if *expected_len > 0 {
@@ -1,28 +1,60 @@
 use crate::{
+    deadline::Deadline,
     error::Error,
-    handler::HttpHandler,
-    header::HttpHeader,
-    request::HttpRequest,
-    response::{HttpResponse, ResponseBody},
+    handler::HttpHandlerMut,
+    header::{HttpHeader, headers},
+    method::HttpMethod,
+    request::{
+        HttpRequest, MAX_HEADERS, TOO_MANY_HEADERS, find_double_crlf, head_content_length,
+        is_request_line,
+    },
+    response::HttpResponse,
     status_code::StatusCode,
+    writer::ResponseWriter,
 };
-use embassy_net::{Stack, tcp::TcpSocket};
-use embassy_time::{Duration, Timer, with_timeout};
-use embedded_io_async::Write as EmbeddedWrite;
-use heapless::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+use core::future::{Future, poll_fn};
+use core::net::{IpAddr, SocketAddr};
+use core::pin::{Pin, pin};
+use core::task::Poll;
+use embassy_net::{IpAddress, Stack, tcp::TcpSocket};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    mutex::{Mutex as AsyncMutex, MutexGuard},
+};
+use embassy_time::{Duration, Instant, TimeoutError, Timer, with_timeout};
+use embedded_io_async::{Error as _, Read as EmbeddedRead, Write as EmbeddedWrite};
+use heapless::{String, Vec};
 
 const SERVER_BUFFER_SIZE: usize = 4096;
 const MAX_REQUEST_SIZE: usize = 4096;
 const DEFAULT_MAX_RESPONSE_SIZE: usize = 4096;
+const MAX_LOCATION_SIZE: usize = 256;
+/// Enough for `timeout=<u64::MAX>`
+const KEEP_ALIVE_HINT_SIZE: usize = 32;
+pub(crate) const MAX_PATH_SIZE: usize = 256;
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+/// How often a paused server checks whether it may accept again
+const ACCEPT_PAUSE_POLL: Duration = Duration::from_millis(50);
+
+/// Maximum number of client addresses a [`PerIpConnectionLimit`] tracks at once
+pub const MAX_TRACKED_IPS: usize = 8;
 
 /// HTTP server timeout configuration
 #[derive(Debug, Clone, Copy)]
 pub struct ServerTimeouts {
     /// Socket accept timeout in seconds
     pub accept_timeout: u64,
-    /// Socket read timeout in seconds  
+    /// Socket read timeout in seconds
+    ///
+    /// A single deadline for reading a whole request, however many reads it takes,
+    /// including continuing it in the [`LargeRequestBuffer`].
     pub read_timeout: u64,
     /// Request handler timeout in seconds
+    ///
+    /// Does not apply to a streamed response once it has started.
     pub handler_timeout: u64,
 }
 
@@ -48,11 +80,404 @@ impl ServerTimeouts {
     }
 }
 
+/// HTTP server behaviour options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServerOptions {
+    /// Rewrite relative `Location` response headers (e.g. `/login`) into absolute
+    /// `http://host/login` URLs using the request's `Host` header (and `https://`
+    /// for requests that arrived over TLS).
+    ///
+    /// Locations that are already absolute are left untouched. Disabled by default.
+    pub absolute_location: bool,
+    /// Maximum request body size in bytes
+    ///
+    /// Requests declaring a larger `Content-Length` are answered with
+    /// `413 Request Entity Too Large`. With `None` the body is only limited by what
+    /// fits into the request buffer next to the request headers.
+    pub max_request_body_size: Option<usize>,
+    /// Maximum length of the request target (path and query) in bytes
+    ///
+    /// Longer targets are answered with `414 Request-URI Too Long` before the path is
+    /// normalized or the handler runs. With `None` only the request buffer limits it.
+    pub max_uri_length: Option<usize>,
+    /// Called with the [`RequestTimings`] of every answered request
+    ///
+    /// Useful for profiling on-device, e.g. to tell slow handlers from slow clients.
+    pub on_timings: Option<fn(&RequestTimings)>,
+    /// Called with every buffered response right before it is serialized
+    ///
+    /// The hook may modify the response, e.g. to add security headers with static
+    /// values. Streamed responses and the server's own error responses are not
+    /// passed through it.
+    pub on_response: Option<fn(&HttpRequest<'_>, &mut HttpResponse<'_>)>,
+    /// Cap on simultaneous connections from a single client address
+    ///
+    /// Share one limit between all server tasks so a single host cannot occupy
+    /// every socket. Connections beyond the cap are answered with
+    /// `503 Service Unavailable` and closed.
+    pub per_ip_limit: Option<&'static PerIpConnectionLimit>,
+    /// Keep HTTP/1.1 connections open for further requests, closing them after this
+    /// many idle seconds
+    ///
+    /// Pipelined requests already in the buffer are answered in order. Clients
+    /// sending `Connection: close`, HTTP/1.0 clients and requests that failed are
+    /// still closed after the response. As a server task serves one connection at a
+    /// time, an idle kept-alive client delays everyone else, so keep this short.
+    /// Responses on a connection that stays open carry `Keep-Alive: timeout=<secs>`,
+    /// so clients can drop it before the server does. With `None` (the default)
+    /// every connection is closed after one response.
+    pub keep_alive_timeout: Option<u64>,
+    /// Buffer for requests whose body does not fit into the server's request buffer
+    ///
+    /// Keeps `REQ_SIZE`, which every server task allocates, sized for the common
+    /// case while still accepting an occasional large upload: a request whose
+    /// headers fit but whose declared `Content-Length` does not is copied over and
+    /// read to the end in this buffer, if it fits there. All server tasks share the
+    /// buffer, so a task waits while another one uses it. With `None` (the default)
+    /// such requests are answered with `413 Request Entity Too Large`.
+    pub large_request_buffer: Option<&'static LargeRequestBuffer>,
+    /// Switch for temporarily not accepting new connections, e.g. during a flash write
+    ///
+    /// Checked before every `accept`: while it is paused the server does not listen,
+    /// so new clients are not connected (and retry or time out) until it is resumed.
+    /// Connections that are already open, including kept-alive ones, are served as
+    /// usual. Share one switch between all server tasks to pause them together.
+    pub accept_pause: Option<&'static AcceptPause>,
+    /// Addresses of reverse proxies whose `X-Forwarded-For` and `X-Forwarded-Proto`
+    /// headers are trusted
+    ///
+    /// For requests from one of these peers, [`HttpRequest::client_ip`] is taken from
+    /// `X-Forwarded-For` (the rightmost address that is not a trusted proxy itself)
+    /// and [`HttpRequest::secure`] from `X-Forwarded-Proto`. Requests from any other
+    /// peer keep the socket's address, so clients cannot spoof them. Empty (the
+    /// default) ignores both headers.
+    pub trusted_proxies: &'static [IpAddr],
+    /// Seconds [`HttpServer::serve`] waits for the network stack to be configured
+    /// (link up and an IP address assigned, e.g. by DHCP) before accepting connections
+    ///
+    /// Avoids a burst of failing accepts while a Wi-Fi device is still connecting at
+    /// boot. Once the time is up the server starts anyway and retries failed accepts
+    /// with a backoff. With `None` (the default) it starts right away.
+    pub wait_for_network: Option<u64>,
+    /// Run maintenance after every this many connections
+    ///
+    /// For very long uptimes: between two connections the server zeroes its socket
+    /// and request buffers, so no old request data lingers in memory, and calls
+    /// [`HttpHandlerMut::on_maintenance`] for the handler's own periodic upkeep.
+    /// Counted per server task. With `None` (the default) this never happens.
+    pub maintenance_interval: Option<usize>,
+}
+
+/// Shared switch pausing and resuming the acceptance of new connections at runtime
+///
+/// See [`ServerOptions::accept_pause`].
+///
+/// ```rust,ignore
+/// use nanofish::{AcceptPause, ServerOptions};
+///
+/// static PAUSE: AcceptPause = AcceptPause::new();
+///
+/// let options = ServerOptions {
+///     accept_pause: Some(&PAUSE),
+///     ..Default::default()
+/// };
+///
+/// // Elsewhere, around a critical section
+/// PAUSE.pause();
+/// write_flash().await;
+/// PAUSE.resume();
+/// ```
+pub struct AcceptPause {
+    paused: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+}
+
+impl AcceptPause {
+    /// Create a switch that lets connections in
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            paused: Mutex::new(Cell::new(false)),
+        }
+    }
+
+    /// Stop accepting new connections once the current ones are done
+    pub fn pause(&self) {
+        self.paused.lock(|paused| paused.set(true));
+    }
+
+    /// Accept new connections again
+    pub fn resume(&self) {
+        self.paused.lock(|paused| paused.set(false));
+    }
+
+    /// Check if new connections are currently held back
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.lock(Cell::get)
+    }
+
+    /// Wait until the switch is not paused
+    pub(crate) async fn wait_resumed(&self) {
+        if self.is_paused() {
+            debug!("Accepting paused");
+            while self.is_paused() {
+                Timer::after(ACCEPT_PAUSE_POLL).await;
+            }
+            debug!("Accepting resumed");
+        }
+    }
+}
+
+impl Default for AcceptPause {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for AcceptPause {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AcceptPause")
+            .field("paused", &self.is_paused())
+            .finish()
+    }
+}
+
+/// Shared switch letting a handler stop the server once its response is sent
+///
+/// For admin actions like a reboot or a network reconfiguration: the handler calls
+/// [`ServerStop::stop`] and returns its confirmation as usual. A server running
+/// [`HttpServer::serve_until`] sends that response with `Connection: close`, ends the
+/// connection and returns, so the caller can tear down with the client informed.
+/// The switch is checked after every request and before accepting a connection;
+/// every server task sharing it stops. It stays triggered until
+/// [`ServerStop::reset`] is called, which should only happen once all of these tasks
+/// have returned.
+///
+/// ```rust,ignore
+/// use nanofish::{DefaultHttpServer, ServerStop};
+///
+/// static STOP: ServerStop = ServerStop::new();
+///
+/// // In the handler
+/// if request.path == "/reboot" {
+///     STOP.stop();
+///     return Ok(HttpResponse::ok("Rebooting"));
+/// }
+///
+/// // In the server task
+/// server.serve_until(stack, handler, &STOP).await;
+/// cortex_m::peripheral::SCB::sys_reset();
+/// ```
+pub struct ServerStop {
+    stopped: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+}
+
+impl ServerStop {
+    /// Create a switch that lets the server run
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            stopped: Mutex::new(Cell::new(false)),
+        }
+    }
+
+    /// Stop the server after the response to the current request
+    pub fn stop(&self) {
+        self.stopped.lock(|stopped| stopped.set(true));
+    }
+
+    /// Check if the server was asked to stop
+    #[must_use]
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.lock(Cell::get)
+    }
+
+    /// Let the server run again
+    ///
+    /// Call this only once every server task sharing the switch has returned, or the
+    /// ones that did not notice the stop yet keep running.
+    pub fn reset(&self) {
+        self.stopped.lock(|stopped| stopped.set(false));
+    }
+}
+
+impl Default for ServerStop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for ServerStop {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ServerStop")
+            .field("stopped", &self.is_stopped())
+            .finish()
+    }
+}
+
+/// Request buffer shared by server tasks for oversized requests
+///
+/// See [`ServerOptions::large_request_buffer`].
+///
+/// ```rust,ignore
+/// use nanofish::{LargeRequestBuffer, ServerOptions};
+///
+/// static LARGE: LargeRequestBuffer<[u8; 16384]> = LargeRequestBuffer::new();
+///
+/// let options = ServerOptions {
+///     large_request_buffer: Some(&LARGE),
+///     ..Default::default()
+/// };
+/// ```
+pub struct LargeRequestBuffer<B: ?Sized = [u8]> {
+    size: usize,
+    buffer: AsyncMutex<CriticalSectionRawMutex, B>,
+}
+
+impl<const N: usize> LargeRequestBuffer<[u8; N]> {
+    /// Create a buffer for requests of up to `N` bytes
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            size: N,
+            buffer: AsyncMutex::new([0; N]),
+        }
+    }
+}
+
+impl<const N: usize> Default for LargeRequestBuffer<[u8; N]> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: ?Sized> core::fmt::Debug for LargeRequestBuffer<B> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LargeRequestBuffer")
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Lock on the [`LargeRequestBuffer`] while a request read into it is handled
+type LargeRequestGuard = MutexGuard<'static, CriticalSectionRawMutex, [u8]>;
+
+/// Counts active connections per client address across server tasks
+///
+/// ```rust,ignore
+/// use nanofish::{PerIpConnectionLimit, ServerOptions};
+///
+/// static PER_IP: PerIpConnectionLimit = PerIpConnectionLimit::new(2);
+///
+/// let options = ServerOptions {
+///     per_ip_limit: Some(&PER_IP),
+///     ..Default::default()
+/// };
+/// ```
+///
+/// Up to [`MAX_TRACKED_IPS`] different addresses can hold connections at the same
+/// time; connections from further addresses are refused as well.
+pub struct PerIpConnectionLimit {
+    max_per_ip: u8,
+    active: Mutex<CriticalSectionRawMutex, RefCell<Vec<(IpAddress, u8), MAX_TRACKED_IPS>>>,
+}
+
+impl PerIpConnectionLimit {
+    /// Create a limit allowing `max_per_ip` simultaneous connections per address
+    #[must_use]
+    pub const fn new(max_per_ip: u8) -> Self {
+        Self {
+            max_per_ip,
+            active: Mutex::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Number of connections currently open from `ip`
+    #[must_use]
+    pub fn active(&self, ip: IpAddress) -> u8 {
+        self.active.lock(|active| {
+            active
+                .borrow()
+                .iter()
+                .find(|(addr, _)| *addr == ip)
+                .map_or(0, |(_, count)| *count)
+        })
+    }
+
+    /// Register a connection from `ip`, unless it already has `max_per_ip` open
+    fn acquire(&self, ip: IpAddress) -> Option<PerIpPermit<'_>> {
+        self.active.lock(|active| {
+            let mut active = active.borrow_mut();
+            if let Some((_, count)) = active.iter_mut().find(|(addr, _)| *addr == ip) {
+                if *count >= self.max_per_ip {
+                    return None;
+                }
+                *count += 1;
+            } else if self.max_per_ip == 0 || active.push((ip, 1)).is_err() {
+                return None;
+            }
+            Some(PerIpPermit { limit: self, ip })
+        })
+    }
+}
+
+impl core::fmt::Debug for PerIpConnectionLimit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PerIpConnectionLimit")
+            .field("max_per_ip", &self.max_per_ip)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An open connection counted by a [`PerIpConnectionLimit`], released on drop
+struct PerIpPermit<'a> {
+    limit: &'a PerIpConnectionLimit,
+    ip: IpAddress,
+}
+
+impl Drop for PerIpPermit<'_> {
+    fn drop(&mut self) {
+        self.limit.active.lock(|active| {
+            let mut active = active.borrow_mut();
+            if let Some(pos) = active.iter().position(|(addr, _)| *addr == self.ip) {
+                active[pos].1 -= 1;
+                if active[pos].1 == 0 {
+                    active.swap_remove(pos);
+                }
+            }
+        });
+    }
+}
+
+/// How a connection is ended once its request has been served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Disconnect {
+    /// Close the connection gracefully
+    Close,
+    /// Reset the connection, the client is gone
+    Abort,
+    /// Drop the connection without closing it, e.g. after a failed read
+    Drop,
+}
+
+/// Time spent in each phase of serving a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestTimings {
+    /// Waiting for a client to connect
+    pub accept: Duration,
+    /// Reading the request from the socket
+    pub read: Duration,
+    /// Running the handler, including writing a streamed response
+    pub handle: Duration,
+    /// Writing and flushing a buffered response
+    pub write: Duration,
+}
+
 /// Simple HTTP server implementation
 ///
 /// **Note**: This server only supports HTTP connections, not HTTPS/TLS.
 /// For secure connections, consider using a reverse proxy or load balancer
-/// that handles TLS termination.
+/// that handles TLS termination, listed in [`ServerOptions::trusted_proxies`].
 pub struct HttpServer<
     const RX_SIZE: usize,
     const TX_SIZE: usize,
@@ -60,7 +485,10 @@ pub struct HttpServer<
     const MAX_RESPONSE_SIZE: usize,
 > {
     port: u16,
-    timeouts: ServerTimeouts,
+    pub(crate) timeouts: ServerTimeouts,
+    pub(crate) options: ServerOptions,
+    /// Switch ending [`HttpServer::serve_until`]
+    pub(crate) stop: Option<&'static ServerStop>,
 }
 
 impl<
@@ -76,69 +504,272 @@ impl<
         Self {
             port,
             timeouts: ServerTimeouts::default(),
+            options: ServerOptions::default(),
+            stop: None,
         }
     }
 
     /// Create a new HTTP server with custom timeouts
     #[must_use]
     pub fn with_timeouts(port: u16, timeouts: ServerTimeouts) -> Self {
-        Self { port, timeouts }
+        Self {
+            port,
+            timeouts,
+            options: ServerOptions::default(),
+            stop: None,
+        }
+    }
+
+    /// Create a new HTTP server with custom timeouts and options
+    #[must_use]
+    pub fn with_options(port: u16, timeouts: ServerTimeouts, options: ServerOptions) -> Self {
+        Self {
+            port,
+            timeouts,
+            options,
+            stop: None,
+        }
     }
 
     /// Start the HTTP server and handle incoming connections
     ///
+    /// [`HttpHandlerMut::on_start`] is called once before the first connection is
+    /// accepted, after waiting for the network if
+    /// [`ServerOptions::wait_for_network`] is set.
+    ///
+    /// Connections are accepted one at a time. For lower latency on back-to-back
+    /// connections run several server tasks on the same port, each with its own
+    /// listening socket, or let clients keep connections open with
+    /// [`ServerOptions::keep_alive_timeout`].
+    ///
     /// **Important**: This server only accepts plain HTTP connections.
     /// HTTPS/TLS is not supported by the server (only by the client).
     pub async fn serve<H>(&mut self, stack: Stack<'_>, mut handler: H) -> !
     where
-        H: HttpHandler,
+        H: HttpHandlerMut,
+    {
+        self.stop = None;
+        loop {
+            // Only a `ServerStop` ends serving, and there is none
+            self.run(stack, &mut handler).await;
+        }
+    }
+
+    /// Serve like [`serve`](Self::serve) until `stop` is triggered
+    ///
+    /// Returns once [`ServerStop::stop`] has been called and the response to the
+    /// request being handled at that moment is sent, e.g. the confirmation of a
+    /// reboot. The switch is left triggered so that other server tasks sharing it
+    /// stop as well; [`ServerStop::reset`] it once all of them have returned to start
+    /// serving again.
+    pub async fn serve_until<H>(
+        &mut self,
+        stack: Stack<'_>,
+        mut handler: H,
+        stop: &'static ServerStop,
+    ) where
+        H: HttpHandlerMut,
+    {
+        self.stop = Some(stop);
+        self.run(stack, &mut handler).await;
+        self.stop = None;
+    }
+
+    /// Accept and answer connections until the server is stopped
+    async fn run<H>(&mut self, stack: Stack<'_>, handler: &mut H)
+    where
+        H: HttpHandlerMut,
     {
+        if let Some(timeout) = self.options.wait_for_network
+            && with_timeout(Duration::from_secs(timeout), stack.wait_config_up())
+                .await
+                .is_err()
+        {
+            warn!(
+                "Network not configured after {} s, starting anyway",
+                timeout
+            );
+        }
         info!("HTTP server started on port {}", self.port);
+        handler.on_start(stack, self.port).await;
 
         let mut rx_buffer = [0; RX_SIZE];
         let mut tx_buffer = [0; TX_SIZE];
         let mut buf = [0; REQ_SIZE];
+        let mut backoff = Backoff::new();
+        let mut connections = 0;
 
-        loop {
+        while !self.stopping() {
+            if let Some(pause) = self.options.accept_pause {
+                pause.wait_resumed().await;
+            }
+
+            // A fresh socket per connection: one that was closed stays in FIN-WAIT or
+            // TIME-WAIT and can only listen again after an `abort()`, which ends the
+            // old connection just like dropping the socket. Reusing it would only save
+            // re-adding it to the stack, about 20 ns on a host (`cargo bench --bench
+            // socket`), which is nothing next to a TCP handshake.
             let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
             socket.set_timeout(Some(Duration::from_secs(self.timeouts.accept_timeout)));
 
+            let mut timings = RequestTimings::default();
+            let start = Instant::now();
             if let Err(e) = socket.accept(self.port).await {
-                warn!("Accept error: {:?}", e);
-                Timer::after(Duration::from_millis(100)).await;
+                let delay = backoff.next();
+                // Only log the 1st, 2nd, 4th, 8th... failure in a row
+                if backoff.failures.is_power_of_two() {
+                    warn!(
+                        "Accept error: {:?} ({} in a row), retrying in {} ms",
+                        e,
+                        backoff.failures,
+                        delay.as_millis()
+                    );
+                }
+                Timer::after(delay).await;
                 continue;
             }
+            backoff.reset();
+            timings.accept = elapsed(start);
 
-            let n = match with_timeout(
-                Duration::from_secs(self.timeouts.read_timeout),
-                socket.read(&mut buf),
-            )
-            .await
+            let remote = socket.remote_endpoint().map(|endpoint| endpoint.addr);
+            match self
+                .serve_connection(&mut socket, remote, handler, &mut buf, timings)
+                .await
             {
-                Ok(Ok(0)) => {
+                Disconnect::Close => socket.close(),
+                Disconnect::Abort => socket.abort(),
+                Disconnect::Drop => {}
+            }
+
+            connections += 1;
+            if self.maintenance_due(connections) {
+                drop(socket);
+                rx_buffer.fill(0);
+                tx_buffer.fill(0);
+                buf.fill(0);
+                handler.on_maintenance().await;
+                connections = 0;
+            }
+        }
+        info!("HTTP server on port {} stopped", self.port);
+    }
+
+    /// Check whether maintenance is due after `connections` connections
+    pub(crate) fn maintenance_due(&self, connections: usize) -> bool {
+        let due = self
+            .options
+            .maintenance_interval
+            .is_some_and(|interval| connections >= interval);
+        if due {
+            debug!("Running maintenance after {} connections", connections);
+        }
+        due
+    }
+
+    /// Check whether the [`ServerStop`] of [`serve_until`](Self::serve_until) was triggered
+    pub(crate) fn stopping(&self) -> bool {
+        self.stop.is_some_and(ServerStop::is_stopped)
+    }
+
+    /// Read and answer the requests on an accepted connection
+    ///
+    /// Unless [`ServerOptions::keep_alive_timeout`] is set, only a single request is
+    /// answered. Returns how the caller should end the connection afterwards.
+    pub(crate) async fn serve_connection<S, H>(
+        &mut self,
+        socket: &mut S,
+        remote: Option<IpAddress>,
+        handler: &mut H,
+        buf: &mut [u8],
+        mut timings: RequestTimings,
+    ) -> Disconnect
+    where
+        S: EmbeddedRead + EmbeddedWrite,
+        H: HttpHandlerMut,
+    {
+        let _permit = match (self.options.per_ip_limit, remote) {
+            (Some(limit), Some(ip)) => {
+                let permit = limit.acquire(ip);
+                if permit.is_none() {
+                    warn!("Too many connections from {:?}", ip);
+                    if let Ok(bytes) = error_response::<MAX_RESPONSE_SIZE>(
+                        StatusCode::ServiceUnavailable,
+                        "Service Unavailable",
+                    ) {
+                        let _ = write_bytes(socket, &bytes).await;
+                        let _ = socket.flush().await;
+                    }
+                    return Disconnect::Close;
+                }
+                permit
+            }
+            _ => None,
+        };
+
+        // Bytes of the next request already read along with the previous one
+        let mut filled = 0;
+        for served in 0usize.. {
+            // After the first request the client may take its time to send another
+            let (timeout, idle) = match self.options.keep_alive_timeout {
+                Some(idle_timeout) if served > 0 => (idle_timeout, Disconnect::Close),
+                _ => (self.timeouts.read_timeout, Disconnect::Drop),
+            };
+            // One deadline for the whole request, however many reads it takes
+            let deadline = Deadline::after(Duration::from_secs(timeout));
+            let start = Instant::now();
+            let (n, request_len) = match deadline.run(read_request(socket, buf, filled)).await {
+                Ok(Ok((0, _))) => {
                     // Connection closed
-                    continue;
+                    return idle;
                 }
-                Ok(Ok(n)) => n,
+                Ok(Ok(read)) => read,
                 Ok(Err(e)) => {
                     warn!("Read error: {:?}", e);
-                    continue;
+                    return Disconnect::Drop;
+                }
+                Err(_) if served > 0 && filled == 0 => {
+                    debug!("Closing idle connection");
+                    return idle;
                 }
                 Err(_) => {
                     warn!("Socket read timeout");
-                    continue;
+                    return Disconnect::Drop;
                 }
             };
 
-            // Parse the request
-            match self.handle_connection(&buf[..n], &mut handler).await {
-                Ok(response_bytes) => {
-                    if let Err(e) = socket.write_all(&response_bytes).await {
-                        warn!("Failed to write response: {:?}", e);
-                    }
+            let large = match self
+                .read_large_request(socket, &buf[..n], buf.len(), request_len, deadline)
+                .await
+            {
+                Ok(large) => large,
+                Err(disconnect) => return disconnect,
+            };
+            timings.read = elapsed(start);
+
+            // Parse and answer the request
+            let request = match &large {
+                Some((guard, read)) => &guard[..*read],
+                None => &buf[..n],
+            };
+            let keep_alive = match self
+                .handle_connection(request, remote, handler, socket, &mut timings)
+                .await
+            {
+                Ok(keep_alive) => {
+                    let start = Instant::now();
                     if let Err(e) = socket.flush().await {
-                        defmt::warn!("Failed to flush response: {:?}", e);
+                        warn!("Failed to flush response: {:?}", Error::IoError(e.kind()));
+                    }
+                    timings.write += elapsed(start);
+                    if let Some(on_timings) = self.options.on_timings {
+                        on_timings(&timings);
                     }
+                    keep_alive
+                }
+                Err(e @ Error::IoError(_)) => {
+                    // The client is gone, free the socket right away
+                    warn!("Failed to write response: {:?}", e);
+                    return Disconnect::Abort;
                 }
                 Err(e) => {
                     error!("Error handling request: {:?}", e);
@@ -146,84 +777,781 @@ impl<
                     let error_response = b"HTTP/1.1 500 Internal Server Error\r\nContent-Type: text/plain\r\nContent-Length: 21\r\n\r\nInternal Server Error";
                     let _ = socket.write_all(error_response).await;
                     let _ = socket.flush().await;
+                    false
                 }
+            };
+
+            if !keep_alive || self.options.keep_alive_timeout.is_none() || self.stopping() {
+                break;
+            }
+            if large.is_some() {
+                // Only read up to the end of the request, nothing of the next one
+                filled = 0;
+            } else {
+                let Some(consumed) = request_len.filter(|&len| len <= n) else {
+                    break;
+                };
+                // Move what was read of the next request to the front
+                buf.copy_within(consumed..n, 0);
+                filled = n - consumed;
             }
+            timings = RequestTimings::default();
+        }
 
-            socket.close();
+        Disconnect::Close
+    }
+
+    /// Continue reading a request too large for the connection buffer in the
+    /// [`LargeRequestBuffer`]
+    ///
+    /// `read` holds what was read into the connection buffer of `capacity` bytes, and
+    /// `request_len` the length the request declares. Returns the locked large buffer
+    /// and the number of bytes in it, or `None` if the request fits into the connection
+    /// buffer, there is no large buffer or the request is too large for it as well.
+    async fn read_large_request<S: EmbeddedRead>(
+        &self,
+        socket: &mut S,
+        read: &[u8],
+        capacity: usize,
+        request_len: Option<usize>,
+        deadline: Deadline,
+    ) -> Result<Option<(LargeRequestGuard, usize)>, Disconnect> {
+        let Some(large_buffer) = self.options.large_request_buffer else {
+            return Ok(None);
+        };
+        let Some(len) = request_len.filter(|&len| {
+            len > capacity && len <= large_buffer.size && self.allows_body(read, len)
+        }) else {
+            return Ok(None);
+        };
+        debug!("Reading {} byte request into the large buffer", len);
+        let mut guard = large_buffer.buffer.lock().await;
+        guard[..read.len()].copy_from_slice(read);
+        match deadline
+            .run(read_request(socket, &mut guard[..len], read.len()))
+            .await
+        {
+            Ok(Ok((read, _))) => Ok(Some((guard, read))),
+            Ok(Err(e)) => {
+                warn!("Read error: {:?}", e);
+                Err(Disconnect::Drop)
+            }
+            Err(_) => {
+                warn!("Socket read timeout");
+                Err(Disconnect::Drop)
+            }
         }
     }
 
-    async fn handle_connection<H>(
+    /// Size of the largest request that can be read, in the large buffer if there is one
+    fn request_capacity(&self) -> usize {
+        self.options
+            .large_request_buffer
+            .map_or(REQ_SIZE, |large| REQ_SIZE.max(large.size))
+    }
+
+    /// Check whether the body of a request of `len` bytes, starting with the bytes
+    /// `read`, is within [`ServerOptions::max_request_body_size`]
+    fn allows_body(&self, read: &[u8], len: usize) -> bool {
+        let body_len = find_double_crlf(read).map_or(len, |head_len| len - head_len - 4);
+        self.options
+            .max_request_body_size
+            .is_none_or(|max| body_len <= max)
+    }
+
+    /// Parse the request in `buffer`, run the handler and write the response to `socket`
+    ///
+    /// `remote` is the peer address of the connection. The handler and write durations
+    /// are recorded in `timings`. Returns whether the connection can be kept open for
+    /// another request, which it cannot after the handler took it over following a
+    /// `101 Switching Protocols` response.
+    ///
+    /// An error is only returned if nothing could be written, or if writing itself
+    /// failed ([`Error::IoError`]), in which case the connection should be abandoned.
+    async fn handle_connection<H, W>(
         &mut self,
         buffer: &[u8],
+        remote: Option<IpAddress>,
         handler: &mut H,
-    ) -> Result<Vec<u8, MAX_RESPONSE_SIZE>, Error>
+        socket: &mut W,
+        timings: &mut RequestTimings,
+    ) -> Result<bool, Error>
     where
-        H: HttpHandler,
+        H: HttpHandlerMut,
+        W: EmbeddedRead + EmbeddedWrite,
     {
-        // Parse the request
-        let request = HttpRequest::try_from(buffer)?;
+        let mut path = String::<MAX_PATH_SIZE>::new();
+        let request = match self.prepare_request(buffer, remote, &mut path) {
+            Ok(request) => request,
+            Err((status_code, message)) => {
+                let bytes = error_response::<MAX_RESPONSE_SIZE>(status_code, message)?;
+                return write_bytes(socket, &bytes).await.map(|()| false);
+            }
+        };
 
         // Handle the request
-        let response = match with_timeout(
-            Duration::from_secs(self.timeouts.handler_timeout),
-            handler.handle_request(&request),
+        let client_keep_alive = wants_keep_alive(&request);
+        let mut keep_alive_hint = String::<KEEP_ALIVE_HINT_SIZE>::new();
+        let mut writer = ResponseWriter::new(
+            socket,
+            supports_chunked(request.version),
+            request.method == HttpMethod::HEAD,
+        );
+        if client_keep_alive && let Some(timeout) = self.options.keep_alive_timeout {
+            let _ = write!(keep_alive_hint, "timeout={timeout}");
+            writer.set_keep_alive(&keep_alive_hint);
+        }
+        let streaming = Cell::new(false);
+        writer.signal_start(&streaming);
+        let start = Instant::now();
+        let result = with_handler_timeout(
+            Duration::from_secs(
+                handler
+                    .handler_timeout(&request)
+                    .unwrap_or(self.timeouts.handler_timeout),
+            ),
+            &streaming,
+            self.dispatch(&request, handler, &mut writer),
         )
-        .await
-        {
-            Ok(Ok(response)) => response,
+        .await;
+        timings.handle = elapsed(start);
+
+        let (bytes, keep_alive) = match result {
+            // `None` if the response was streamed through the writer
+            // The handler may have asked to close the connection after its response
+            Ok(Ok(bytes)) => (bytes, client_keep_alive && !writer.closes_connection()),
+            Ok(Err(e @ Error::IoError(_))) => return Err(e),
+            Ok(Err(e)) if writer.is_started() => {
+                // Too late for an error response, the client sees the missing final chunk
+                warn!("Streaming handler error: {:?}", e);
+                return Ok(false);
+            }
             Ok(Err(e)) => {
                 warn!("Handler error: {:?}", e);
-                let mut headers = Vec::new();
-                let _ = headers.push(HttpHeader::new("Content-Type", "text/plain"));
-                let error_response = HttpResponse {
-                    status_code: StatusCode::InternalServerError,
-                    headers,
-                    body: ResponseBody::Text("Internal Server Error"),
-                };
-                return Ok(error_response.build_bytes::<MAX_RESPONSE_SIZE>());
+                let bytes =
+                    error_response(StatusCode::InternalServerError, "Internal Server Error")?;
+                (Some(bytes), false)
             }
             Err(_) => {
                 warn!("Request handling timed out");
-                let mut headers = Vec::new();
-                let _ = headers.push(HttpHeader::new("Content-Type", "text/plain"));
-                let timeout_response = HttpResponse {
-                    status_code: StatusCode::BadRequest,
-                    headers,
-                    body: ResponseBody::Text("Request Timeout"),
-                };
-                return Ok(timeout_response.build_bytes::<MAX_RESPONSE_SIZE>());
+                // The client's request was fine, the server was too slow to answer it
+                (
+                    Some(error_response(
+                        StatusCode::GatewayTimeout,
+                        "Gateway Timeout",
+                    )?),
+                    false,
+                )
             }
         };
+        let take_over = writer.switches_protocols();
+
+        if let Some(bytes) = bytes {
+            let start = Instant::now();
+            let result = write_bytes(socket, &bytes).await;
+            timings.write = elapsed(start);
+            result?;
+        }
 
-        Ok(response.build_bytes::<MAX_RESPONSE_SIZE>())
+        if take_over {
+            socket.flush().await.map_err(|e| Error::IoError(e.kind()))?;
+            // What the client sent after the request belongs to the new protocol
+            let body_len = request.declared_content_length().unwrap_or(0);
+            let consumed =
+                find_double_crlf(buffer).map_or(buffer.len(), |head| head + 4 + body_len);
+            let buffered = buffer.get(consumed..).unwrap_or_default();
+            debug!("Handing the connection over to the handler");
+            if let Err(e) = handler.take_over(&request, socket, buffered).await {
+                warn!("Error on taken over connection: {:?}", e);
+            }
+            return Ok(false);
+        }
+        Ok(keep_alive)
     }
-}
 
-/// Type alias for `HttpServer` with default buffer sizes (4KB each)
-pub type DefaultHttpServer =
-    HttpServer<SERVER_BUFFER_SIZE, SERVER_BUFFER_SIZE, MAX_REQUEST_SIZE, DEFAULT_MAX_RESPONSE_SIZE>;
+    /// Parse and check the request in `buffer` before it is handed to the handler
+    ///
+    /// The path is normalized into `path` if needed, an oversized or incomplete body
+    /// is rejected and the client address is taken from `remote`, or from the
+    /// forwarding headers of a trusted proxy. The error carries the status and text
+    /// of the error response to send instead.
+    fn prepare_request<'a>(
+        &self,
+        buffer: &'a [u8],
+        remote: Option<IpAddress>,
+        path: &'a mut String<MAX_PATH_SIZE>,
+    ) -> Result<HttpRequest<'a>, (StatusCode, &'static str)> {
+        let mut request = parse_request(buffer, REQ_SIZE)?;
 
-/// Type alias for `HttpServer` with small buffer sizes for memory-constrained environments (1KB each)
-pub type SmallHttpServer = HttpServer<1024, 1024, 1024, 1024>;
+        if self
+            .options
+            .max_uri_length
+            .is_some_and(|max| request.path.len() > max)
+        {
+            warn!("Request URI too long: {} bytes", request.path.len());
+            return Err((StatusCode::RequestUriTooLong, "URI Too Long"));
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        match normalize_path(request.path, path) {
+            Ok(true) => request.path = path.as_str(),
+            Ok(false) => {}
+            Err(status_code) => {
+                warn!("Rejecting request path: {}", request.path);
+                return Err((status_code, status_code.text()));
+            }
+        }
 
-    #[test]
-    fn test_http_server_creation() {
-        let server: DefaultHttpServer = HttpServer::new(8080);
-        assert_eq!(server.port, 8080);
-        assert_eq!(server.timeouts.accept_timeout, 10);
-        assert_eq!(server.timeouts.read_timeout, 30);
-        assert_eq!(server.timeouts.handler_timeout, 60);
+        if let Some(content_length) = request.declared_content_length() {
+            let header_len = buffer.len() - request.body.len();
+            let max_body_size = self
+                .options
+                .max_request_body_size
+                .unwrap_or(usize::MAX)
+                .min(self.request_capacity().saturating_sub(header_len));
+            if content_length > max_body_size {
+                warn!("Request body too large: {} bytes", content_length);
+                return Err((StatusCode::RequestEntityTooLarge, "Payload Too Large"));
+            }
+            if request.body.len() < content_length {
+                warn!("Request body incomplete");
+                return Err((StatusCode::BadRequest, "Incomplete Request Body"));
+            }
+            // Anything after the declared body belongs to the next request
+            request.body = &request.body[..content_length];
+        }
 
-        let server: SmallHttpServer = HttpServer::new(3000);
-        assert_eq!(server.port, 3000);
+        request.client_ip = remote.map(|ip| IpAddr::from(ip).to_canonical());
+        if request
+            .client_ip
+            .is_some_and(|peer| is_trusted(self.options.trusted_proxies, peer))
+        {
+            apply_forwarded(&mut request, self.options.trusted_proxies);
+        }
+
+        Ok(request)
     }
 
-    #[test]
+    /// Run the handler for `request`
+    ///
+    /// Returns `None` if the handler streamed its response through `writer`, otherwise
+    /// the serialized response.
+    async fn dispatch<H, W>(
+        &self,
+        request: &HttpRequest<'_>,
+        handler: &mut H,
+        writer: &mut ResponseWriter<'_, W>,
+    ) -> Result<Option<Vec<u8, MAX_RESPONSE_SIZE>>, Error>
+    where
+        H: HttpHandlerMut,
+        W: EmbeddedWrite,
+    {
+        if let Some(etag) = handler.etag(request).await
+            && request.etag_matches(etag)
+        {
+            debug!("ETag {} matches, responding 304", etag);
+            let mut response = HttpResponse::empty(StatusCode::NotModified);
+            let _ = response.headers.push(HttpHeader::new(headers::ETAG, etag));
+            if let Some(on_response) = self.options.on_response {
+                on_response(request, &mut response);
+            }
+            add_keep_alive(&mut response, writer);
+            return response.build_bytes().map(Some);
+        }
+
+        handler.handle_stream(request, writer).await?;
+        if writer.is_started() {
+            writer.finish().await?;
+            return Ok(None);
+        }
+
+        let mut location = String::<MAX_LOCATION_SIZE>::new();
+        let mut response = handler.handle_request(request).await?;
+        if self.options.absolute_location {
+            absolutize_location(request, &mut response, &mut location);
+        }
+        if let Some(on_response) = self.options.on_response {
+            on_response(request, &mut response);
+        }
+        if self.stopping() {
+            // The last response before the server goes away
+            response = response.with_connection_close();
+        }
+        if response.headers.iter().any(HttpHeader::closes_connection) {
+            writer.close_connection();
+        } else if response.status_code == StatusCode::SwitchingProtocols {
+            writer.switch_protocols();
+        }
+        add_keep_alive(&mut response, writer);
+
+        // HEAD gets the headers of a GET without the body; a lazy one is not produced
+        response.build(request.method != HttpMethod::HEAD).map(Some)
+    }
+}
+
+/// Read a request into `buffer` until its headers and `Content-Length` body are complete
+///
+/// The first `filled` bytes of `buffer` are left over from the previous request on
+/// the connection and are used before reading more. The body is read for every
+/// method, so a `GET` with a body is consumed just like a `POST`. Stops early if the
+/// connection is closed, `buffer` is full or the request line contains bytes that
+/// are not ASCII text; such requests are rejected when the request is parsed.
+///
+/// Returns the number of bytes in `buffer` and, once the headers are complete, the
+/// length of the request they describe. Bytes beyond it belong to the next request.
+///
+/// Each byte is scanned for the end of the headers only once and the headers are
+/// only searched for a `Content-Length` once, so the work stays linear in the
+/// request size even if it arrives a byte at a time. The request itself is parsed
+/// later, when it is handled.
+async fn read_request<R: EmbeddedRead>(
+    socket: &mut R,
+    buffer: &mut [u8],
+    filled: usize,
+) -> Result<(usize, Option<usize>), Error> {
+    let mut len = filled;
+    let mut scanned = 0usize;
+    let mut request_len = None;
+    let mut line_complete = false;
+    loop {
+        if !line_complete {
+            // Stop at bytes that cannot be HTTP, e.g. a TLS handshake sent to this
+            // port, instead of waiting for a head that never comes
+            let unchecked = &buffer[scanned..len];
+            if !is_request_line(unchecked) {
+                debug!("Invalid request line");
+                break;
+            }
+            line_complete = unchecked.contains(&b'\n');
+        }
+        if request_len.is_none() {
+            // The end of the headers may straddle the previous read
+            let scan_from = scanned.saturating_sub(3);
+            if let Some(end_of_headers) = find_double_crlf(&buffer[scan_from..len]) {
+                let head_len = scan_from + end_of_headers;
+                let body_len = head_content_length(&buffer[..head_len]).unwrap_or(0);
+                request_len = Some((head_len + 4).saturating_add(body_len));
+            }
+            scanned = len;
+        }
+        if len >= buffer.len() || request_len.is_some_and(|request_len| len >= request_len) {
+            break;
+        }
+
+        let n = socket
+            .read(&mut buffer[len..])
+            .await
+            .map_err(|e| Error::IoError(e.kind()))?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    Ok((len, request_len))
+}
+
+/// Add the `Keep-Alive` header `writer` was given, unless the connection is closed
+/// after the response or the handler set its own
+fn add_keep_alive<'a, W: EmbeddedWrite>(
+    response: &mut HttpResponse<'a>,
+    writer: &ResponseWriter<'a, W>,
+) {
+    if let Some(value) = writer.keep_alive()
+        && response.get_header(headers::KEEP_ALIVE).is_none()
+    {
+        let _ = response
+            .headers
+            .push(HttpHeader::new(headers::KEEP_ALIVE, value));
+    }
+}
+
+/// Check whether `ip` is one of the `trusted` proxies
+fn is_trusted(trusted: &[IpAddr], ip: IpAddr) -> bool {
+    trusted.iter().any(|proxy| proxy.to_canonical() == ip)
+}
+
+/// Take the client address and scheme of a request from a trusted proxy from its
+/// `X-Forwarded-For` and `X-Forwarded-Proto` headers
+///
+/// Each proxy appends the address it received the request from, so the client is the
+/// rightmost address that is not a trusted proxy. Walking stops at an entry that is
+/// not an address, keeping the last trusted hop as the client.
+fn apply_forwarded(request: &mut HttpRequest<'_>, trusted: &[IpAddr]) {
+    let forwarded = request
+        .headers
+        .iter()
+        .rev()
+        .filter(|h| h.name.eq_ignore_ascii_case(headers::X_FORWARDED_FOR))
+        .flat_map(|h| h.value.rsplit(','));
+    for entry in forwarded {
+        let entry = entry.trim();
+        let Some(ip) = entry
+            .parse::<IpAddr>()
+            .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+            .ok()
+        else {
+            break;
+        };
+        request.client_ip = Some(ip.to_canonical());
+        if !is_trusted(trusted, ip.to_canonical()) {
+            break;
+        }
+    }
+
+    // The first proxy saw the scheme the client used
+    if let Some(proto) = request.get_header(headers::X_FORWARDED_PROTO) {
+        let proto = proto.split(',').next().unwrap_or_default().trim();
+        request.secure = proto.eq_ignore_ascii_case("https");
+    }
+}
+
+/// Check whether the client lets the connection stay open after answering `request`
+///
+/// Only HTTP/1.1 connections are persistent by default, unless the client asks for
+/// `Connection: close`.
+fn wants_keep_alive(request: &HttpRequest<'_>) -> bool {
+    request.version == "HTTP/1.1" && !request.headers.iter().any(HttpHeader::closes_connection)
+}
+
+/// Parse the request read into `buffer`, which holds at most `capacity` bytes
+///
+/// Fails with `431 Request Header Fields Too Large` if the buffer filled up before
+/// the headers ended or there are more than [`MAX_HEADERS`] headers, and with
+/// `400 Bad Request` for any other malformed request, including one whose request
+/// line is not ASCII text. The error carries the text of the error response, which
+/// hints at HTTPS if the client started a TLS handshake.
+fn parse_request(
+    buffer: &[u8],
+    capacity: usize,
+) -> Result<HttpRequest<'_>, (StatusCode, &'static str)> {
+    HttpRequest::try_from(buffer).map_err(|e| {
+        if is_tls_handshake(buffer) {
+            // Most likely an `https://` URL pointing at this port
+            debug!("TLS handshake on the plain HTTP port");
+            (
+                StatusCode::BadRequest,
+                "This is an HTTP port; did you mean HTTPS?",
+            )
+        } else if buffer.len() >= capacity
+            && find_double_crlf(buffer).is_none()
+            && is_request_line(buffer)
+        {
+            warn!("Request headers do not fit into {} bytes", capacity);
+            let status_code = StatusCode::RequestHeaderFieldsTooLarge;
+            (status_code, status_code.text())
+        } else if matches!(e, Error::InvalidResponse(TOO_MANY_HEADERS)) {
+            warn!("More than {} request headers", MAX_HEADERS);
+            let status_code = StatusCode::RequestHeaderFieldsTooLarge;
+            (status_code, status_code.text())
+        } else {
+            warn!("Malformed request: {:?}", e);
+            (StatusCode::BadRequest, StatusCode::BadRequest.text())
+        }
+    })
+}
+
+/// Check whether `data` starts with a TLS handshake record, e.g. a `ClientHello`
+///
+/// Such a record starts with content type 22 and major version 3, which no request
+/// line can.
+fn is_tls_handshake(data: &[u8]) -> bool {
+    data.starts_with(&[0x16, 0x03])
+}
+
+/// Exponential backoff between failed accepts
+pub(crate) struct Backoff {
+    delay: Duration,
+    pub(crate) failures: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Self {
+            delay: ACCEPT_BACKOFF_MIN,
+            failures: 0,
+        }
+    }
+
+    /// Record a failure and return how long to wait before retrying
+    pub(crate) fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (delay + delay).min(ACCEPT_BACKOFF_MAX);
+        self.failures = self.failures.saturating_add(1);
+        delay
+    }
+
+    /// Start over after a successful accept
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Run the handler's `future` until it completes or `timeout` passes
+///
+/// The timeout stops counting once `streaming` is set: a streamed response that has
+/// started may take as long as it needs, e.g. an event stream kept open with
+/// [`ResponseWriter::ping_while`]. A client that goes away ends it with a write error.
+async fn with_handler_timeout<F: Future>(
+    timeout: Duration,
+    streaming: &Cell<bool>,
+    future: F,
+) -> Result<F::Output, TimeoutError> {
+    let mut future = pin!(future);
+    let mut timer = Timer::after(timeout);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        if !streaming.get() && Pin::new(&mut timer).poll(cx).is_ready() {
+            return Poll::Ready(Err(TimeoutError));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Check whether a client speaking `version` understands chunked responses
+///
+/// `Transfer-Encoding: chunked` only exists since HTTP/1.1.
+fn supports_chunked(version: &str) -> bool {
+    !matches!(version, "HTTP/1.0" | "HTTP/0.9")
+}
+
+/// Time passed since `start`, saturating at zero instead of panicking
+pub(crate) fn elapsed(start: Instant) -> Duration {
+    Instant::now()
+        .checked_duration_since(start)
+        .unwrap_or_default()
+}
+
+/// Write all of `bytes` to the connection
+async fn write_bytes<W: EmbeddedWrite>(socket: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    socket
+        .write_all(bytes)
+        .await
+        .map_err(|e| Error::IoError(e.kind()))
+}
+
+/// Build a plain text response generated by the server itself
+fn error_response<const MAX_RESPONSE_SIZE: usize>(
+    status_code: StatusCode,
+    text: &'static str,
+) -> Result<Vec<u8, MAX_RESPONSE_SIZE>, Error> {
+    HttpResponse::text(status_code, text).build_bytes()
+}
+
+/// Rewrite a relative `Location` header of `response` into an absolute URL
+///
+/// The absolute URL is written into `location`, which the header then borrows.
+/// Nothing is changed if the location is already absolute, the request has no
+/// `Host` header, or the resulting URL does not fit into `location`.
+fn absolutize_location<'r, const N: usize>(
+    request: &HttpRequest<'_>,
+    response: &mut HttpResponse<'r>,
+    location: &'r mut String<N>,
+) {
+    let Some(header) = response
+        .headers
+        .iter_mut()
+        .find(|h| h.name.eq_ignore_ascii_case(headers::LOCATION))
+    else {
+        return;
+    };
+    if is_absolute_url(header.value) {
+        return;
+    }
+    let Some(host) = request.get_header(headers::HOST) else {
+        return;
+    };
+
+    let written = if header.value.starts_with("//") {
+        // Scheme-relative reference, only the scheme is missing
+        location.push_str(request.scheme()).is_ok()
+            && location.push(':').is_ok()
+            && location.push_str(header.value).is_ok()
+    } else {
+        let base = if header.value.starts_with('/') {
+            ""
+        } else {
+            // Resolve against the directory of the request path
+            let path = request.path.split('?').next().unwrap_or_default();
+            path.rfind('/').map_or("/", |pos| &path[..=pos])
+        };
+        location.push_str(request.scheme()).is_ok()
+            && location.push_str("://").is_ok()
+            && location.push_str(host).is_ok()
+            && location.push_str(base).is_ok()
+            && location.push_str(header.value).is_ok()
+    };
+
+    if written {
+        header.value = location.as_str();
+    } else {
+        warn!("Location header too long to make absolute");
+    }
+}
+
+/// Collapse duplicate slashes and resolve `.` and `..` segments of `path`
+///
+/// Returns `Ok(false)` if the path is already normal, otherwise the normalized path
+/// (including any query string) is written into `normalized`. Paths that try to
+/// climb above the root are rejected with `400 Bad Request`; request targets that
+/// are not origin-form paths (e.g. `*`) are left alone.
+fn normalize_path<const N: usize>(
+    path: &str,
+    normalized: &mut String<N>,
+) -> Result<bool, StatusCode> {
+    let (target, query) = match path.split_once('?') {
+        Some((target, query)) => (target, Some(query)),
+        None => (path, None),
+    };
+    let Some(rest) = target.strip_prefix('/') else {
+        return Ok(false);
+    };
+
+    // Only copy the path if it needs to change
+    let count = rest.split('/').count();
+    let is_normal = rest.split('/').enumerate().all(|(i, segment)| {
+        !matches!(segment, "" | "." | "..") || (segment.is_empty() && i + 1 == count)
+    });
+    if is_normal {
+        return Ok(false);
+    }
+
+    let mut segments = rest.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        match segment {
+            "" if last => {
+                // Keep the trailing slash
+                normalized
+                    .push('/')
+                    .map_err(|_| StatusCode::RequestUriTooLong)?;
+            }
+            "" | "." => {}
+            ".." => {
+                let parent = normalized.rfind('/').ok_or(StatusCode::BadRequest)?;
+                normalized.truncate(parent);
+            }
+            _ => {
+                normalized
+                    .push('/')
+                    .and_then(|()| normalized.push_str(segment))
+                    .map_err(|_| StatusCode::RequestUriTooLong)?;
+            }
+        }
+        if last && matches!(segment, "." | "..") {
+            // `/a/b/..` refers to the directory `/a/`
+            normalized
+                .push('/')
+                .map_err(|_| StatusCode::RequestUriTooLong)?;
+        }
+    }
+    if normalized.is_empty() {
+        normalized
+            .push('/')
+            .map_err(|_| StatusCode::RequestUriTooLong)?;
+    }
+    if let Some(query) = query {
+        normalized
+            .push('?')
+            .and_then(|()| normalized.push_str(query))
+            .map_err(|_| StatusCode::RequestUriTooLong)?;
+    }
+    Ok(true)
+}
+
+/// Check whether `url` starts with a URI scheme (e.g. `http:` or `mailto:`)
+fn is_absolute_url(url: &str) -> bool {
+    let Some(colon) = url.find(':') else {
+        return false;
+    };
+    let scheme = &url[..colon];
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Type alias for `HttpServer` with default buffer sizes (4KB each)
+pub type DefaultHttpServer =
+    HttpServer<SERVER_BUFFER_SIZE, SERVER_BUFFER_SIZE, MAX_REQUEST_SIZE, DEFAULT_MAX_RESPONSE_SIZE>;
+
+/// Type alias for `HttpServer` with small buffer sizes for memory-constrained environments (1KB each)
+///
+/// A request, headers included, must fit into 1 KB. That is plenty for API clients
+/// but browsers sending cookies and many headers can exceed it; such requests are
+/// answered with `431 Request Header Fields Too Large`.
+pub type SmallHttpServer = HttpServer<1024, 1024, 1024, 1024>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{ResettingSocket, TestSocket};
+    use crate::{HttpHandler, HttpHeader, ResponseBody, SharedState, SimpleHandler, fn_handler};
+
+    /// Feed `request` through the server and return what was written back
+    fn respond<const RX: usize, const TX: usize, const RQ: usize, const RS: usize, H>(
+        server: &mut HttpServer<RX, TX, RQ, RS>,
+        handler: &mut H,
+        request: &[u8],
+    ) -> Result<std::string::String, Error>
+    where
+        H: HttpHandlerMut,
+    {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut timings = RequestTimings::default();
+        futures_lite::future::block_on(server.handle_connection(
+            request,
+            None,
+            handler,
+            &mut socket,
+            &mut timings,
+        ))?;
+        Ok(std::string::String::from_utf8(socket.0).unwrap())
+    }
+
+    /// Handler counting its requests, shared by reference
+    struct CountingHandler {
+        hits: SharedState<CriticalSectionRawMutex, u32>,
+    }
+
+    impl HttpHandler for CountingHandler {
+        async fn handle_request(
+            &self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            self.hits.lock(|hits| *hits += 1);
+            Ok(HttpResponse::ok("counted"))
+        }
+    }
+
+    #[test]
+    fn test_shared_handler() {
+        let handler = CountingHandler {
+            hits: SharedState::new(0),
+        };
+        let mut first: DefaultHttpServer = HttpServer::new(8080);
+        let mut second: DefaultHttpServer = HttpServer::new(8081);
+        for server in [&mut first, &mut second] {
+            let response = respond(server, &mut &handler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            assert!(response.ends_with("counted"));
+        }
+        assert_eq!(handler.hits.get(), 2);
+    }
+
+    #[test]
+    fn test_http_server_creation() {
+        let server: DefaultHttpServer = HttpServer::new(8080);
+        assert_eq!(server.port, 8080);
+        assert_eq!(server.timeouts.accept_timeout, 10);
+        assert_eq!(server.timeouts.read_timeout, 30);
+        assert_eq!(server.timeouts.handler_timeout, 60);
+
+        let server: SmallHttpServer = HttpServer::new(3000);
+        assert_eq!(server.port, 3000);
+    }
+
+    #[test]
     fn test_server_timeouts() {
         // Test default timeouts
         let timeouts = ServerTimeouts::default();
@@ -244,4 +1572,1547 @@ mod tests {
         assert_eq!(server.timeouts.read_timeout, 15);
         assert_eq!(server.timeouts.handler_timeout, 45);
     }
+
+    fn redirect_response(location: &str) -> HttpResponse<'_> {
+        let mut headers = Vec::new();
+        let _ = headers.push(HttpHeader::new("Location", location));
+        HttpResponse {
+            status_code: StatusCode::Found,
+            headers,
+            body: ResponseBody::Empty,
+        }
+    }
+
+    #[test]
+    fn test_absolutize_location() {
+        let request = HttpRequest::try_from(
+            b"GET /admin/settings HTTP/1.1\r\nHost: device.local\r\n\r\n".as_slice(),
+        )
+        .unwrap();
+
+        let cases = [
+            ("/login", "http://device.local/login"),
+            ("login", "http://device.local/admin/login"),
+            ("//other.local/x", "http://other.local/x"),
+            ("http://example.com/", "http://example.com/"),
+            ("https://example.com/", "https://example.com/"),
+        ];
+        for (relative, expected) in cases {
+            let mut location = String::<MAX_LOCATION_SIZE>::new();
+            let mut response = redirect_response(relative);
+            absolutize_location(&request, &mut response, &mut location);
+            assert_eq!(response.get_header("Location"), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_absolutize_location_secure() {
+        let mut request =
+            HttpRequest::try_from(b"GET /a HTTP/1.1\r\nHost: device.local\r\n\r\n".as_slice())
+                .unwrap();
+        request.secure = true;
+
+        for (relative, expected) in [
+            ("/login", "https://device.local/login"),
+            ("//other.local/x", "https://other.local/x"),
+        ] {
+            let mut location = String::<MAX_LOCATION_SIZE>::new();
+            let mut response = redirect_response(relative);
+            absolutize_location(&request, &mut response, &mut location);
+            assert_eq!(response.get_header("Location"), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_absolutize_location_without_host() {
+        let request = HttpRequest::try_from(b"GET / HTTP/1.0\r\n\r\n".as_slice()).unwrap();
+        let mut location = String::<MAX_LOCATION_SIZE>::new();
+        let mut response = redirect_response("/login");
+        absolutize_location(&request, &mut response, &mut location);
+        assert_eq!(response.get_header("Location"), Some("/login"));
+    }
+
+    #[test]
+    fn test_absolute_location_option() {
+        let options = ServerOptions {
+            absolute_location: true,
+            ..Default::default()
+        };
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_options(
+            8080,
+            ServerTimeouts::default(),
+            options,
+        );
+        let request = b"GET /old HTTP/1.1\r\nHost: device.local:8080\r\n\r\n";
+
+        let response = respond(&mut server, &mut RedirectHandler, request).unwrap();
+
+        assert!(response.contains("Location: http://device.local:8080/new\r\n"));
+    }
+
+    struct RedirectHandler;
+
+    impl HttpHandlerMut for RedirectHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(redirect_response("/new"))
+        }
+    }
+
+    struct EchoHandler {
+        body: [u8; 64],
+        len: usize,
+    }
+
+    impl HttpHandlerMut for EchoHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            self.len = request.body.len();
+            self.body[..self.len].copy_from_slice(request.body);
+            let status_code = if request.method == HttpMethod::PATCH {
+                StatusCode::Ok
+            } else {
+                StatusCode::MethodNotAllowed
+            };
+            Ok(HttpResponse {
+                status_code,
+                headers: Vec::new(),
+                body: ResponseBody::Binary(&self.body[..self.len]),
+            })
+        }
+    }
+
+    #[test]
+    fn test_patch_request_reaches_handler() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut handler = EchoHandler {
+            body: [0; 64],
+            len: 0,
+        };
+        let request = b"PATCH /resource HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 15\r\n\r\n{\"name\":\"fish\"}";
+
+        let response = respond(&mut server, &mut handler, request).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("{\"name\":\"fish\"}"));
+    }
+
+    #[test]
+    fn test_request_body_too_large() {
+        let options = ServerOptions {
+            max_request_body_size: Some(8),
+            ..Default::default()
+        };
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_options(
+            8080,
+            ServerTimeouts::default(),
+            options,
+        );
+        let request = b"POST /upload HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789";
+
+        let response = respond(&mut server, &mut SimpleHandler, request).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Request Entity Too Large\r\n"));
+
+        // Within the limit the request reaches the handler
+        let request = b"POST /upload HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678";
+        let response = respond(&mut server, &mut SimpleHandler, request).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_request_body_larger_than_buffer() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let request = b"POST /upload HTTP/1.1\r\nContent-Length: 4096\r\n\r\n";
+
+        let response = respond(&mut server, &mut SimpleHandler, request).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Request Entity Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_response_too_large() {
+        let mut server = HttpServer::<1024, 1024, 1024, 64>::new(8080);
+        let request = b"GET / HTTP/1.1\r\n\r\n";
+
+        let result = respond(&mut server, &mut SimpleHandler, request);
+        assert!(matches!(result, Err(Error::ResponseTooLarge)));
+    }
+
+    #[test]
+    fn test_response_too_large_answers_500() {
+        // Room for the error response, but not for the handler's 120 byte body
+        let mut server = HttpServer::<1024, 1024, 1024, 128>::new(8080);
+        let mut handler = fn_handler(async |_: &HttpRequest<'_>| {
+            Ok(HttpResponse::ok(
+                "0123456789012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789abcdefghijklmnopqrst",
+            ))
+        });
+
+        let response = respond(&mut server, &mut handler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        // Never a truncated 200
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.ends_with("\r\n\r\nInternal Server Error"));
+    }
+
+    #[test]
+    fn test_response_header_budget_answers_500() {
+        static COOKIE: [u8; 4096] = [b'c'; 4096];
+        let mut server = HttpServer::<1024, 1024, 1024, 8192>::new(8080);
+        let mut handler = fn_handler(async |_: &HttpRequest<'_>| {
+            let cookie = core::str::from_utf8(&COOKIE).unwrap();
+            let mut response = HttpResponse::ok("hi");
+            let _ = response.headers.push(HttpHeader::new("Set-Cookie", cookie));
+            Ok(response)
+        });
+
+        let response = respond(&mut server, &mut handler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    }
+
+    struct ProgressHandler;
+
+    impl HttpHandlerMut for ProgressHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(HttpResponse {
+                status_code: StatusCode::Ok,
+                headers: Vec::new(),
+                body: ResponseBody::Text("buffered"),
+            })
+        }
+
+        async fn handle_stream<W: EmbeddedWrite>(
+            &mut self,
+            request: &HttpRequest<'_>,
+            writer: &mut ResponseWriter<'_, W>,
+        ) -> Result<(), Error> {
+            if request.path != "/update" {
+                return Ok(());
+            }
+            writer
+                .start(
+                    StatusCode::Ok,
+                    &[HttpHeader::new("Content-Type", "text/plain")],
+                )
+                .await?;
+            writer.write_chunk(b"erasing\n").await?;
+            writer.flush().await?;
+            writer.write_chunk(b"").await?;
+            writer.write_chunk(b"done\n").await
+        }
+    }
+
+    #[test]
+    fn test_streamed_response() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+
+        let response = respond(
+            &mut server,
+            &mut ProgressHandler,
+            b"POST /update HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nerasing\n\r\n5\r\ndone\n\r\n0\r\n\r\n"
+        );
+
+        // Requests the handler does not stream fall back to `handle_request`
+        let response =
+            respond(&mut server, &mut ProgressHandler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nbuffered"));
+    }
+
+    #[test]
+    fn test_streamed_response_head() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let response = respond(
+            &mut server,
+            &mut ProgressHandler,
+            b"HEAD /update HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_streamed_response_http_1_0() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+
+        let response = respond(
+            &mut server,
+            &mut ProgressHandler,
+            b"POST /update HTTP/1.0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nerasing\ndone\n"
+        );
+        assert!(supports_chunked("HTTP/1.1"));
+        assert!(!supports_chunked("HTTP/0.9"));
+    }
+
+    struct SlowHandler;
+
+    impl HttpHandlerMut for SlowHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Timer::after(Duration::from_secs(2)).await;
+            Ok(HttpResponse {
+                status_code: StatusCode::Ok,
+                headers: Vec::new(),
+                body: ResponseBody::Empty,
+            })
+        }
+    }
+
+    /// Slow on every path, but allows `/ota` more time
+    struct OtaHandler;
+
+    impl HttpHandlerMut for OtaHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            SlowHandler.handle_request(request).await?;
+            Ok(HttpResponse::ok("updated"))
+        }
+
+        fn handler_timeout(&self, request: &HttpRequest<'_>) -> Option<u64> {
+            (request.path == "/ota").then_some(3)
+        }
+    }
+
+    #[test]
+    fn test_handler_timeout_override() {
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_timeouts(
+            8080,
+            ServerTimeouts::new(10, 30, 1),
+        );
+
+        let response =
+            respond(&mut server, &mut OtaHandler, b"POST /ota HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("updated"));
+
+        let response = respond(&mut server, &mut OtaHandler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+    }
+
+    #[test]
+    fn test_handler_timeout_response() {
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_timeouts(
+            8080,
+            ServerTimeouts::new(10, 30, 1),
+        );
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+        let response = respond(&mut server, &mut SlowHandler, request).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+        assert!(response.ends_with("Gateway Timeout"));
+    }
+
+    /// Streams an event after waiting longer than the handler timeout
+    struct EventHandler;
+
+    impl HttpHandlerMut for EventHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Timer::after(Duration::from_secs(2)).await;
+            Ok(HttpResponse::ok("late"))
+        }
+
+        async fn handle_stream<W: EmbeddedWrite>(
+            &mut self,
+            request: &HttpRequest<'_>,
+            writer: &mut ResponseWriter<'_, W>,
+        ) -> Result<(), Error> {
+            if request.path != "/events" {
+                return Ok(());
+            }
+            let headers = [HttpHeader::content_type("text/event-stream")];
+            writer.start(StatusCode::Ok, &headers).await?;
+            let event = writer
+                .ping_while(Duration::from_millis(800), async {
+                    Timer::after(Duration::from_millis(1500)).await;
+                    "data: 1\n\n"
+                })
+                .await?;
+            writer.write_chunk(event.as_bytes()).await
+        }
+    }
+
+    #[test]
+    fn test_handler_timeout_spares_started_stream() {
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_timeouts(
+            8080,
+            ServerTimeouts::new(10, 30, 1),
+        );
+
+        let response = respond(
+            &mut server,
+            &mut EventHandler,
+            b"GET /events HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.contains("\r\n8\r\n: ping\n\n\r\n"));
+        assert!(response.ends_with("9\r\ndata: 1\n\n\r\n0\r\n\r\n"));
+
+        // Before the response has started the timeout still applies
+        let response = respond(&mut server, &mut EventHandler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+    }
+
+    /// Streams chunks until writing fails, ignoring errors like a careless handler
+    struct EndlessHandler {
+        chunks: usize,
+    }
+
+    impl HttpHandlerMut for EndlessHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Err(Error::NoResponse)
+        }
+
+        async fn handle_stream<W: EmbeddedWrite>(
+            &mut self,
+            _request: &HttpRequest<'_>,
+            writer: &mut ResponseWriter<'_, W>,
+        ) -> Result<(), Error> {
+            while !writer.is_closed() {
+                let _ = writer.write_chunk(b"tick\n").await;
+                self.chunks += 1;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_connection_reset_while_streaming() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut handler = EndlessHandler { chunks: 0 };
+        let mut socket = ResettingSocket::new(100);
+
+        let result = futures_lite::future::block_on(server.handle_connection(
+            b"GET /events HTTP/1.1\r\n\r\n",
+            None,
+            &mut handler,
+            &mut socket,
+            &mut RequestTimings::default(),
+        ));
+
+        // The handler ignored the write errors, the server still abandons the connection
+        assert!(matches!(result, Err(Error::IoError(_))));
+        assert!(handler.chunks < 10);
+        assert!(!socket.written.ends_with(b"0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_connection_reset_while_writing_response() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut socket = ResettingSocket::new(10);
+
+        let result = futures_lite::future::block_on(server.handle_connection(
+            b"GET / HTTP/1.1\r\n\r\n",
+            None,
+            &mut SimpleHandler,
+            &mut socket,
+            &mut RequestTimings::default(),
+        ));
+
+        assert!(matches!(result, Err(Error::IoError(_))));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cases = [
+            ("/a//b/./c/../d", "/a/b/d"),
+            ("//index.html", "/index.html"),
+            ("/a/b/..", "/a/"),
+            ("/a/.", "/a/"),
+            ("/a/../..x", "/..x"),
+            ("/a/..", "/"),
+            ("/./x?q=/../", "/x?q=/../"),
+        ];
+        for (path, expected) in cases {
+            let mut normalized = String::<MAX_PATH_SIZE>::new();
+            assert_eq!(normalize_path(path, &mut normalized), Ok(true), "{path}");
+            assert_eq!(normalized, expected);
+        }
+
+        let long = "/a".repeat(MAX_PATH_SIZE);
+        for path in ["/", "/a/b/", "/a.b/..c", "*", "/x?path=a//b", &long] {
+            let mut normalized = String::<MAX_PATH_SIZE>::new();
+            assert_eq!(normalize_path(path, &mut normalized), Ok(false), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_path_rejects_escaping_root() {
+        for path in ["/..", "/a/../../etc/passwd", "/../?x"] {
+            let mut normalized = String::<MAX_PATH_SIZE>::new();
+            assert_eq!(
+                normalize_path(path, &mut normalized),
+                Err(StatusCode::BadRequest),
+                "{path}"
+            );
+        }
+
+        let mut normalized = String::<8>::new();
+        assert_eq!(
+            normalize_path("/abc//defgh", &mut normalized),
+            Err(StatusCode::RequestUriTooLong)
+        );
+    }
+
+    struct PathHandler {
+        path: std::string::String,
+        line: std::string::String,
+    }
+
+    impl HttpHandlerMut for PathHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            self.path = request.path.into();
+            self.line = request.request_line().into();
+            Ok(HttpResponse {
+                status_code: StatusCode::Ok,
+                headers: Vec::new(),
+                body: ResponseBody::Empty,
+            })
+        }
+    }
+
+    #[test]
+    fn test_handler_sees_normalized_path() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut handler = PathHandler {
+            path: std::string::String::new(),
+            line: std::string::String::new(),
+        };
+
+        let response = respond(
+            &mut server,
+            &mut handler,
+            b"GET /static/./css//../app.js HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(handler.path, "/static/app.js");
+        // The request line still shows what the client sent
+        assert_eq!(handler.line, "GET /static/./css//../app.js HTTP/1.1");
+
+        handler.path.clear();
+        let response = respond(
+            &mut server,
+            &mut handler,
+            b"GET /static/../../secret HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(handler.path.is_empty());
+    }
+
+    struct NapHandler;
+
+    impl HttpHandlerMut for NapHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Timer::after(Duration::from_millis(50)).await;
+            Ok(HttpResponse {
+                status_code: StatusCode::NoContent,
+                headers: Vec::new(),
+                body: ResponseBody::Empty,
+            })
+        }
+    }
+
+    #[test]
+    fn test_request_timings() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut timings = RequestTimings::default();
+
+        futures_lite::future::block_on(server.handle_connection(
+            b"GET / HTTP/1.1\r\n\r\n",
+            None,
+            &mut NapHandler,
+            &mut socket,
+            &mut timings,
+        ))
+        .unwrap();
+
+        assert!(timings.handle >= Duration::from_millis(50));
+        assert!(timings.write < timings.handle);
+        assert_eq!(timings.accept, Duration::default());
+    }
+
+    /// Connection delivering a request in separate reads
+    struct ChunkedReader<'a> {
+        chunks: &'a [&'a [u8]],
+    }
+
+    impl embedded_io_async::ErrorType for ChunkedReader<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl EmbeddedRead for ChunkedReader<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let Some((chunk, rest)) = self.chunks.split_first() else {
+                return Ok(0);
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            self.chunks = rest;
+            Ok(n)
+        }
+    }
+
+    /// Delivers `data` one byte per read, like a slow or malicious client
+    struct ByteReader<'a> {
+        data: &'a [u8],
+    }
+
+    impl embedded_io_async::ErrorType for ByteReader<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl EmbeddedRead for ByteReader<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let Some((byte, rest)) = self.data.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *byte;
+            self.data = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_read_request_byte_by_byte() {
+        let request = b"POST /data HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcextra";
+        let mut reader = ByteReader { data: request };
+        let mut buf = [0u8; 128];
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+        assert_eq!(&buf[..n], &request[..request.len() - 5]);
+    }
+
+    #[test]
+    fn test_read_request_without_delimiters() {
+        // 4 KB without a single line break, sent a byte at a time
+        let garbage = [b'a'; MAX_REQUEST_SIZE];
+        let mut reader = ByteReader { data: &garbage };
+        let mut buf = [0u8; MAX_REQUEST_SIZE];
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+        assert_eq!(n, MAX_REQUEST_SIZE);
+
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let response = respond(&mut server, &mut SimpleHandler, &buf[..n]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_read_request_waits_for_body() {
+        let mut reader = ChunkedReader {
+            chunks: &[
+                b"GET /search HTTP/1.1\r\nContent-",
+                b"Length: 11\r\n\r\n",
+                b"{\"q\":",
+                b"\"fish\"}",
+                b"never read",
+            ],
+        };
+        let mut buf = [0u8; 256];
+
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+
+        assert_eq!(
+            &buf[..n],
+            b"GET /search HTTP/1.1\r\nContent-Length: 11\r\n\r\n{\"q\":\"fish\"}"
+        );
+        assert_eq!(reader.chunks, &[b"never read".as_slice()]);
+    }
+
+    #[test]
+    fn test_read_request_without_body() {
+        let mut reader = ChunkedReader {
+            chunks: &[b"GET / HTTP/1.1\r\n\r\n", b"next"],
+        };
+        let mut buf = [0u8; 256];
+
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+        assert_eq!(n, 18);
+
+        // A closed connection ends the read with what has arrived
+        let mut reader = ChunkedReader {
+            chunks: &[b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab"],
+        };
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+        assert!(buf[..n].ends_with(b"ab"));
+    }
+
+    #[test]
+    fn test_read_request_stops_at_invalid_request_line() {
+        // A TLS ClientHello never contains the blank line ending a head
+        let mut reader = ChunkedReader {
+            chunks: &[b"\x16\x03\x01\x02\x00", b"\x01\x00\x01\xfc", b"never read"],
+        };
+        let mut buf = [0u8; 256];
+        let (n, request_len) =
+            futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0)).unwrap();
+        assert_eq!((n, request_len), (5, None));
+        assert_eq!(reader.chunks.len(), 2);
+
+        // Checked across reads, and only up to the end of the line
+        let mut reader = ChunkedReader {
+            chunks: &[b"GET /a", b"\x01b HTTP/1.1\r\n", b"never read"],
+        };
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+        assert_eq!(&buf[..n], b"GET /a\x01b HTTP/1.1\r\n");
+        let mut reader = ChunkedReader {
+            chunks: &[b"GET / HTTP/1.1\r\n", b"X-Bin: \x01\r\n\r\n", b"next"],
+        };
+        let n = futures_lite::future::block_on(read_request(&mut reader, &mut buf, 0))
+            .unwrap()
+            .0;
+        assert_eq!(n, 28);
+
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let response = respond(&mut server, &mut SimpleHandler, &[0x16; MAX_REQUEST_SIZE]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_tls_handshake_on_http_port() {
+        let client_hello = b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03";
+        assert!(is_tls_handshake(client_hello));
+        assert!(!is_tls_handshake(b"GET / HTTP/1.1\r\n"));
+        assert!(!is_tls_handshake(b"\x16"));
+
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let response = respond(&mut server, &mut SimpleHandler, client_hello).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.ends_with("\r\n\r\nThis is an HTTP port; did you mean HTTPS?"));
+
+        // Other garbage gets the plain error
+        let response = respond(&mut server, &mut SimpleHandler, b"\x01\x02\r\n\r\n").unwrap();
+        assert!(response.ends_with("\r\n\r\nBad Request"));
+    }
+
+    /// Client connection sending `input` and collecting the responses
+    struct DuplexSocket<'a> {
+        input: ChunkedReader<'a>,
+        output: std::vec::Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for DuplexSocket<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl EmbeddedRead for DuplexSocket<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.input.read(buf).await
+        }
+    }
+
+    impl EmbeddedWrite for DuplexSocket<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Serve one connection sending `chunks` and return the responses
+    fn serve_chunks(
+        keep_alive_timeout: Option<u64>,
+        handler: &mut impl HttpHandlerMut,
+        chunks: &[&[u8]],
+    ) -> std::string::String {
+        let options = ServerOptions {
+            keep_alive_timeout,
+            ..ServerOptions::default()
+        };
+        let mut server: DefaultHttpServer =
+            HttpServer::with_options(8080, ServerTimeouts::default(), options);
+        let mut socket = DuplexSocket {
+            input: ChunkedReader { chunks },
+            output: std::vec::Vec::new(),
+        };
+        let mut buf = [0u8; MAX_REQUEST_SIZE];
+        let disconnect = futures_lite::future::block_on(server.serve_connection(
+            &mut socket,
+            None,
+            handler,
+            &mut buf,
+            RequestTimings::default(),
+        ));
+        assert_eq!(disconnect, Disconnect::Close);
+        std::string::String::from_utf8(socket.output).unwrap()
+    }
+
+    /// Handler switching to a protocol echoing everything back in upper case
+    ///
+    /// The `101 Switching Protocols` is streamed for `/shout/stream`.
+    struct ShoutHandler;
+
+    impl HttpHandlerMut for ShoutHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(HttpResponse::switching_protocols("shout"))
+        }
+
+        async fn handle_stream<W: EmbeddedWrite>(
+            &mut self,
+            request: &HttpRequest<'_>,
+            writer: &mut ResponseWriter<'_, W>,
+        ) -> Result<(), Error> {
+            if request.path != "/shout/stream" {
+                return Ok(());
+            }
+            let response = HttpResponse::switching_protocols("shout");
+            writer.start(response.status_code, &response.headers).await
+        }
+
+        async fn take_over<S: EmbeddedRead + EmbeddedWrite>(
+            &mut self,
+            _request: &HttpRequest<'_>,
+            socket: &mut S,
+            buffered: &[u8],
+        ) -> Result<(), Error> {
+            let mut buf = [0u8; 64];
+            buf[..buffered.len()].copy_from_slice(buffered);
+            let mut n = buffered.len();
+            loop {
+                socket
+                    .write_all(&buf[..n].to_ascii_uppercase())
+                    .await
+                    .map_err(|e| Error::IoError(e.kind()))?;
+                n = socket
+                    .read(&mut buf)
+                    .await
+                    .map_err(|e| Error::IoError(e.kind()))?;
+                if n == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_take_over() {
+        // Bytes after the request arrive with it and in later reads
+        let output = serve_chunks(
+            Some(5),
+            &mut ShoutHandler,
+            &[
+                b"GET /shout HTTP/1.1\r\nUpgrade: shout\r\nConnection: Upgrade\r\n\r\nhello",
+                b" world",
+            ],
+        );
+        assert_eq!(
+            output,
+            "HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: shout\r\n\r\n\
+             HELLO WORLD"
+        );
+        let output = serve_chunks(
+            Some(5),
+            &mut ShoutHandler,
+            &[b"GET /shout/stream HTTP/1.1\r\nUpgrade: shout\r\nConnection: Upgrade\r\n\r\nhi"],
+        );
+        assert_eq!(
+            output,
+            "HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: shout\r\n\r\nHI"
+        );
+
+        // Without `take_over` the connection is closed after the response
+        let mut handler =
+            fn_handler(async |_: &HttpRequest<'_>| Ok(HttpResponse::switching_protocols("shout")));
+        let output = serve_chunks(
+            Some(5),
+            &mut handler,
+            &[b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"],
+        );
+        assert_eq!(output.matches("HTTP/1.1 101").count(), 1);
+    }
+
+    #[test]
+    fn test_large_request_buffer() {
+        static LARGE: LargeRequestBuffer<[u8; 512]> = LargeRequestBuffer::new();
+        static SMALL: LargeRequestBuffer<[u8; 256]> = LargeRequestBuffer::new();
+        let serve = |large_request_buffer, chunks: &[&[u8]]| {
+            let options = ServerOptions {
+                keep_alive_timeout: Some(5),
+                large_request_buffer,
+                ..ServerOptions::default()
+            };
+            let mut server = HttpServer::<64, 64, 128, 1024>::with_options(
+                8080,
+                ServerTimeouts::default(),
+                options,
+            );
+            let mut handler = fn_handler(async |request: &HttpRequest<'_>| {
+                Ok(match request.body {
+                    [] => HttpResponse::ok("empty"),
+                    body if body.len() == 300 && body.iter().all(|&b| b == b'x') => {
+                        HttpResponse::ok("uploaded")
+                    }
+                    _ => HttpResponse::text(StatusCode::BadRequest, "corrupt"),
+                })
+            });
+            let mut socket = DuplexSocket {
+                input: ChunkedReader { chunks },
+                output: std::vec::Vec::new(),
+            };
+            let mut buf = [0u8; 128];
+            futures_lite::future::block_on(server.serve_connection(
+                &mut socket,
+                None,
+                &mut handler,
+                &mut buf,
+                RequestTimings::default(),
+            ));
+            std::string::String::from_utf8(socket.output).unwrap()
+        };
+        // 46 bytes of headers and 300 of body, read into the 128 byte buffer first
+        let head = b"POST /upload HTTP/1.1\r\nContent-Length: 300\r\n\r\n";
+        let first = [head.as_slice(), &[b'x'; 50]].concat();
+        let chunks: &[&[u8]] = &[&first, &[b'x'; 32], &[b'x'; 218], b"GET / HTTP/1.1\r\n\r\n"];
+
+        let response = serve(Some(&LARGE), chunks);
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.contains("uploaded"));
+        assert!(response.ends_with("empty"));
+
+        let response = serve(None, chunks);
+        assert!(response.starts_with("HTTP/1.1 413 "));
+
+        let response = serve(Some(&SMALL), chunks);
+        assert!(response.starts_with("HTTP/1.1 413 "));
+    }
+
+    #[test]
+    fn test_keep_alive_pipelined_requests() {
+        let response = serve_chunks(
+            Some(5),
+            &mut SimpleHandler,
+            &[
+                b"GET /health HTTP/1.1\r\n\r\nPOST /missing HTTP/1.1\r\nContent-Length: 2\r\n\r\nokGET ",
+                b"/ HTTP/1.1\r\n\r\n",
+            ],
+        );
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 3);
+        let health = response.find("{\"status\":\"ok\"}").unwrap();
+        let missing = response.find("404 Not Found").unwrap();
+        let root = response.find("Hello from nanofish").unwrap();
+        assert!(health < missing && missing < root);
+    }
+
+    #[test]
+    fn test_keep_alive_empty_body() {
+        // Without `Content-Length: 0` the client could not tell where the response ends
+        let mut handler =
+            fn_handler(async |_: &HttpRequest<'_>| Ok(HttpResponse::empty(StatusCode::Ok)));
+        let response = serve_chunks(
+            Some(3),
+            &mut handler,
+            &[b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n"],
+        );
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nKeep-Alive: timeout=3\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_keep_alive_closes_when_asked() {
+        let pipelined: &[&[u8]] =
+            &[b"GET / HTTP/1.1\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\n\r\n"];
+        assert_eq!(
+            serve_chunks(Some(5), &mut SimpleHandler, pipelined)
+                .matches("HTTP/1.1 ")
+                .count(),
+            1
+        );
+
+        let pipelined: &[&[u8]] = &[b"GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n"];
+        assert_eq!(
+            serve_chunks(Some(5), &mut SimpleHandler, pipelined)
+                .matches("HTTP/1.1 ")
+                .count(),
+            1
+        );
+
+        // Without a keep-alive timeout every connection is closed after one response
+        let pipelined: &[&[u8]] = &[b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"];
+        assert_eq!(
+            serve_chunks(None, &mut SimpleHandler, pipelined)
+                .matches("HTTP/1.1 ")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_keep_alive_header() {
+        let requests: &[&[u8]] =
+            &[b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n"];
+        let response = serve_chunks(Some(5), &mut SimpleHandler, requests);
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(response.matches("\r\nKeep-Alive: timeout=5\r\n").count(), 1);
+        let second = response.rfind("HTTP/1.1 200 OK").unwrap();
+        assert!(response.find("Keep-Alive").unwrap() < second);
+
+        let requests: &[&[u8]] = &[b"GET / HTTP/1.1\r\n\r\n"];
+        assert!(!serve_chunks(None, &mut SimpleHandler, requests).contains("Keep-Alive"));
+    }
+
+    /// Asks to close the connection after answering `/bye`, buffered or streamed
+    struct ClosingHandler;
+
+    impl HttpHandlerMut for ClosingHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            let response = HttpResponse::ok("hi");
+            Ok(if request.path == "/bye" {
+                response.with_connection_close()
+            } else {
+                response
+            })
+        }
+
+        async fn handle_stream<W: EmbeddedWrite>(
+            &mut self,
+            request: &HttpRequest<'_>,
+            writer: &mut ResponseWriter<'_, W>,
+        ) -> Result<(), Error> {
+            if request.path == "/stream/bye" {
+                let close = HttpHeader::new(headers::CONNECTION, "close");
+                writer.start(StatusCode::Ok, &[close]).await?;
+                writer.write_chunk(b"bye").await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handler_closes_kept_alive_connection() {
+        let requests: &[&[u8]] =
+            &[b"GET / HTTP/1.1\r\n\r\nGET /bye HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"];
+        let response = serve_chunks(Some(5), &mut ClosingHandler, requests);
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.ends_with("Content-Length: 2\r\nConnection: close\r\n\r\nhi"));
+
+        let requests: &[&[u8]] = &[b"GET /stream/bye HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n"];
+        let response = serve_chunks(Some(5), &mut ClosingHandler, requests);
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(response.contains("Connection: close\r\nTransfer-Encoding: chunked\r\n"));
+    }
+
+    #[test]
+    fn test_read_request_reuses_leftover() {
+        let mut buf = [0u8; 64];
+        buf[..10].copy_from_slice(b"GET / HTTP");
+        let mut reader = ChunkedReader {
+            chunks: &[b"/1.1\r\n\r\nGET /next HTTP/1.1\r\n\r\n"],
+        };
+        let (n, request_len) =
+            futures_lite::future::block_on(read_request(&mut reader, &mut buf, 10)).unwrap();
+        assert_eq!(request_len, Some(18));
+        assert_eq!(&buf[18..n], b"GET /next HTTP/1.1\r\n\r\n");
+
+        // A complete request already in the buffer needs no read at all
+        let mut reader = ChunkedReader { chunks: &[] };
+        buf.copy_within(18..n, 0);
+        let (n, request_len) =
+            futures_lite::future::block_on(read_request(&mut reader, &mut buf, n - 18)).unwrap();
+        assert_eq!((n, request_len), (22, Some(22)));
+    }
+
+    #[test]
+    fn test_get_request_with_body() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut handler = EchoHandler {
+            body: [0; 64],
+            len: 0,
+        };
+
+        // Bytes after the declared body are not part of it
+        let response = respond(
+            &mut server,
+            &mut handler,
+            b"GET /search HTTP/1.1\r\nContent-Length: 4\r\n\r\nfishGET / HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.ends_with("\r\n\r\nfish"));
+
+        let response = respond(
+            &mut server,
+            &mut handler,
+            b"GET /search HTTP/1.1\r\nContent-Length: 4\r\n\r\nfi",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    /// Stores a single resource at `/config`, created or replaced by `PUT`
+    struct ResourceHandler {
+        value: Option<([u8; 64], usize)>,
+    }
+
+    impl HttpHandlerMut for ResourceHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            if request.method == HttpMethod::PUT {
+                let mut body = [0; 64];
+                body[..request.body.len()].copy_from_slice(request.body);
+                let replaced = self.value.replace((body, request.body.len())).is_some();
+                return Ok(if replaced {
+                    HttpResponse::no_content()
+                } else {
+                    HttpResponse::created("/config")
+                });
+            }
+            match &self.value {
+                Some((body, len)) if request.method == HttpMethod::GET => Ok(HttpResponse {
+                    status_code: StatusCode::Ok,
+                    headers: Vec::new(),
+                    body: ResponseBody::Binary(&body[..*len]),
+                }),
+                _ => Ok(HttpResponse::not_found()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_put_round_trip() {
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let mut handler = ResourceHandler { value: None };
+
+        let put = b"PUT /config HTTP/1.1\r\nContent-Length: 9\r\n\r\n{\"led\":1}";
+        let response = respond(&mut server, &mut handler, put).unwrap();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.contains("Location: /config\r\n"));
+
+        let put = b"PUT /config HTTP/1.1\r\nContent-Length: 9\r\n\r\n{\"led\":0}";
+        let response = respond(&mut server, &mut handler, put).unwrap();
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+
+        let response = respond(&mut server, &mut handler, b"GET /config HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.ends_with("\r\n\r\n{\"led\":0}"));
+    }
+
+    struct TokenHandler;
+
+    impl HttpHandlerMut for TokenHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            Ok(match request.get_header("Authorization") {
+                Some(value) if value.len() > 2000 => HttpResponse::ok("authorized"),
+                _ => HttpResponse::text(StatusCode::Unauthorized, "Unauthorized"),
+            })
+        }
+    }
+
+    #[test]
+    fn test_large_authorization_header() {
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let request = format!(
+            "GET /api HTTP/1.1\r\nHost: device.local\r\nAuthorization: Bearer {}\r\n\r\n",
+            "t".repeat(2048)
+        );
+        assert!(request.len() < MAX_REQUEST_SIZE);
+
+        let response = respond(&mut server, &mut TokenHandler, request.as_bytes()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("authorized"));
+    }
+
+    /// Counts how often the response body is built
+    struct EtagHandler {
+        built: usize,
+    }
+
+    impl HttpHandlerMut for EtagHandler {
+        async fn handle_request(
+            &mut self,
+            _request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            self.built += 1;
+            let mut response = HttpResponse::ok("large asset");
+            let _ = response
+                .headers
+                .push(HttpHeader::new(headers::ETAG, "\"v1\""));
+            Ok(response)
+        }
+
+        async fn etag(&mut self, _request: &HttpRequest<'_>) -> Option<&str> {
+            Some("\"v1\"")
+        }
+    }
+
+    #[test]
+    fn test_etag_short_circuits_with_304() {
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let mut handler = EtagHandler { built: 0 };
+
+        let response = respond(
+            &mut server,
+            &mut handler,
+            b"GET /app.js HTTP/1.1\r\nIf-None-Match: W/\"v1\"\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n"
+        );
+        assert_eq!(handler.built, 0);
+
+        let response = respond(
+            &mut server,
+            &mut handler,
+            b"GET /app.js HTTP/1.1\r\nIf-None-Match: \"v0\"\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("ETag: \"v1\"\r\n"));
+        assert_eq!(handler.built, 1);
+
+        respond(&mut server, &mut handler, b"GET /app.js HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(handler.built, 2);
+    }
+
+    #[test]
+    fn test_head_skips_body() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static RENDERS: AtomicUsize = AtomicUsize::new(0);
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let mut handler = fn_handler(async |request: &HttpRequest<'_>| {
+            Ok(if request.path == "/lazy" {
+                HttpResponse::lazy(StatusCode::Ok, "text/plain", &|buf: &mut [u8]| {
+                    RENDERS.fetch_add(1, Ordering::Relaxed);
+                    buf[..8].copy_from_slice(b"rendered");
+                    Ok(8)
+                })
+            } else {
+                HttpResponse::ok("eager")
+            })
+        });
+
+        let response = respond(&mut server, &mut handler, b"HEAD / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n"
+        );
+
+        let response = respond(&mut server, &mut handler, b"HEAD /lazy HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n"
+        );
+        assert_eq!(RENDERS.load(Ordering::Relaxed), 0);
+
+        let response = respond(&mut server, &mut handler, b"GET /lazy HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.ends_with("Content-Length: 8\r\n\r\nrendered"));
+        assert_eq!(RENDERS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_headers_larger_than_request_buffer() {
+        // A browser-like request that does not fit into 1 KB
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: device.local\r\nCookie: {}\r\n\r\n",
+            "c".repeat(1100)
+        );
+        let mut server: SmallHttpServer = HttpServer::new(8080);
+        let response =
+            respond(&mut server, &mut SimpleHandler, &request.as_bytes()[..1024]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_too_many_headers() {
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        let request = |count: usize| {
+            let mut request = std::string::String::from("GET / HTTP/1.1\r\n");
+            for i in 0..count {
+                let _ = write!(request, "X-Header-{i}: {i}\r\n");
+            }
+            request + "\r\n"
+        };
+
+        let response = respond(
+            &mut server,
+            &mut SimpleHandler,
+            request(MAX_HEADERS).as_bytes(),
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let response = respond(
+            &mut server,
+            &mut SimpleHandler,
+            request(MAX_HEADERS + 1).as_bytes(),
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let mut server: DefaultHttpServer = HttpServer::new(8080);
+        for request in [
+            &b"BREW /pot HTTP/1.1\r\n\r\n"[..],
+            b"GET\r\n\r\n",
+            // Connection closed before the headers were complete
+            b"GET / HTTP/1.1\r\nHost: dev",
+        ] {
+            let response = respond(&mut server, &mut SimpleHandler, request).unwrap();
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_accept_backoff() {
+        let mut backoff = Backoff::new();
+        let delays: std::vec::Vec<u64> = (0..9).map(|_| backoff.next().as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1600, 3200, 5000, 5000, 5000]);
+        assert_eq!(backoff.failures, 9);
+
+        backoff.reset();
+        assert_eq!(backoff.failures, 0);
+        assert_eq!(backoff.next(), ACCEPT_BACKOFF_MIN);
+    }
+
+    fn add_security_headers(request: &HttpRequest<'_>, response: &mut HttpResponse<'_>) {
+        let _ = response
+            .headers
+            .push(HttpHeader::new("X-Content-Type-Options", "nosniff"));
+        if request.path == "/private" {
+            response.status_code = StatusCode::Forbidden;
+        }
+    }
+
+    #[test]
+    fn test_on_response_hook() {
+        let options = ServerOptions {
+            on_response: Some(add_security_headers),
+            ..Default::default()
+        };
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_options(
+            8080,
+            ServerTimeouts::default(),
+            options,
+        );
+
+        let response = respond(&mut server, &mut SimpleHandler, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nX-Content-Type-Options: nosniff\r\n"));
+
+        let response = respond(
+            &mut server,
+            &mut SimpleHandler,
+            b"GET /private HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    #[test]
+    fn test_max_uri_length() {
+        let options = ServerOptions {
+            max_uri_length: Some(7),
+            ..Default::default()
+        };
+        let mut server = HttpServer::<1024, 1024, 1024, 1024>::with_options(
+            8080,
+            ServerTimeouts::default(),
+            options,
+        );
+
+        let response = respond(
+            &mut server,
+            &mut SimpleHandler,
+            b"GET /health? HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 414 Request-URI Too Long\r\n"));
+
+        let response = respond(
+            &mut server,
+            &mut SimpleHandler,
+            b"GET /health HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    fn ip(host: u16) -> IpAddress {
+        IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, host)
+    }
+
+    #[test]
+    fn test_per_ip_connection_limit() {
+        let limit = PerIpConnectionLimit::new(2);
+        let a = ip(1);
+        let b = ip(2);
+
+        let first = limit.acquire(a).unwrap();
+        let second = limit.acquire(a).unwrap();
+        assert!(limit.acquire(a).is_none());
+        assert_eq!(limit.active(a), 2);
+
+        // Other clients are unaffected
+        let other = limit.acquire(b).unwrap();
+        assert_eq!(limit.active(b), 1);
+
+        drop(first);
+        assert_eq!(limit.active(a), 1);
+        let third = limit.acquire(a).unwrap();
+
+        drop((second, third, other));
+        assert_eq!(limit.active(a), 0);
+        assert_eq!(limit.active(b), 0);
+    }
+
+    #[test]
+    fn test_per_ip_connection_limit_table_full() {
+        let limit = PerIpConnectionLimit::new(1);
+        #[allow(clippy::cast_possible_truncation)]
+        let permits: std::vec::Vec<_> = (0..MAX_TRACKED_IPS)
+            .map(|i| limit.acquire(ip(i as u16)).unwrap())
+            .collect();
+
+        assert!(limit.acquire(ip(100)).is_none());
+        drop(permits);
+        assert!(limit.acquire(ip(100)).is_some());
+    }
+
+    #[test]
+    fn test_apply_forwarded() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let edge: IpAddr = "10.0.0.2".parse().unwrap();
+        let trusted = [proxy, edge];
+        let forwarded = |head: &str| {
+            let mut request = HttpRequest::parse_from(head, b"").unwrap();
+            request.client_ip = Some(proxy);
+            apply_forwarded(&mut request, &trusted);
+            (request.client_ip.unwrap().to_string(), request.secure)
+        };
+
+        // The spoofed leftmost entry is ignored, trusted hops are skipped
+        let (client, secure) = forwarded(
+            "GET / HTTP/1.1\r\nX-Forwarded-For: 6.6.6.6, 203.0.113.7, 10.0.0.2\r\n\
+             X-Forwarded-Proto: https\r\n\r\n",
+        );
+        assert_eq!(client, "203.0.113.7");
+        assert!(secure);
+
+        // Several header lines, IPv6 with port and IPv4 mapped into IPv6
+        let (client, secure) = forwarded(
+            "GET / HTTP/1.1\r\nX-Forwarded-For: 203.0.113.7\r\n\
+             X-Forwarded-For: [2001:db8::1]:4711\r\nX-Forwarded-Proto: http\r\n\r\n",
+        );
+        assert_eq!(client, "2001:db8::1");
+        assert!(!secure);
+        let (client, _) = forwarded("GET / HTTP/1.1\r\nX-Forwarded-For: ::ffff:192.0.2.1\r\n\r\n");
+        assert_eq!(client, "192.0.2.1");
+
+        // Garbage stops the walk at the last trusted hop
+        let (client, _) = forwarded("GET / HTTP/1.1\r\nX-Forwarded-For: unknown, 10.0.0.2\r\n\r\n");
+        assert_eq!(client, "10.0.0.2");
+        let (client, _) = forwarded("GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(client, "10.0.0.1");
+    }
+
+    /// Answers with the client address and scheme the handler sees
+    struct ClientHandler(std::string::String);
+
+    impl HttpHandlerMut for ClientHandler {
+        async fn handle_request(
+            &mut self,
+            request: &HttpRequest<'_>,
+        ) -> Result<HttpResponse<'_>, Error> {
+            self.0 = format!("{:?} {}", request.client_ip, request.scheme());
+            Ok(HttpResponse::ok(&self.0))
+        }
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        static TRUSTED: [IpAddr; 1] = [IpAddr::V6(core::net::Ipv6Addr::new(
+            0xfe80, 0, 0, 0, 0, 0, 0, 1,
+        ))];
+        let options = ServerOptions {
+            trusted_proxies: &TRUSTED,
+            ..ServerOptions::default()
+        };
+        let mut server: DefaultHttpServer =
+            HttpServer::with_options(8080, ServerTimeouts::default(), options);
+        let request =
+            b"GET / HTTP/1.1\r\nX-Forwarded-For: 203.0.113.7\r\nX-Forwarded-Proto: https\r\n\r\n";
+        let mut serve = |remote| {
+            let mut socket = TestSocket(std::vec::Vec::new());
+            futures_lite::future::block_on(server.handle_connection(
+                request,
+                Some(remote),
+                &mut ClientHandler(std::string::String::new()),
+                &mut socket,
+                &mut RequestTimings::default(),
+            ))
+            .unwrap();
+            std::string::String::from_utf8(socket.0).unwrap()
+        };
+
+        assert!(serve(ip(1)).ends_with("Some(203.0.113.7) https"));
+        // Anyone else cannot spoof their address or the scheme
+        assert!(serve(ip(2)).ends_with("Some(fe80::2) http"));
+    }
 }
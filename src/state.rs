@@ -0,0 +1,104 @@
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::RawMutex};
+
+/// Mutable state shared by handlers on several server tasks
+///
+/// A handler with per-task state implements `HttpHandlerMut`, so state kept in the
+/// handler itself is per task. State all tasks see, like a request counter or the latest sensor
+/// readings, goes into a `static SharedState` the handlers refer to. Access is
+/// guarded by an `embassy_sync` mutex of kind `M`, typically
+/// `CriticalSectionRawMutex`, and only lasts for a closure, so the lock is never held
+/// across an `.await`:
+///
+/// ```rust
+/// use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+/// use nanofish::{Error, HttpHandlerMut, HttpRequest, HttpResponse, SharedState, StatusCode};
+///
+/// static HITS: SharedState<CriticalSectionRawMutex, u32> = SharedState::new(0);
+///
+/// struct Counter {
+///     buf: [u8; 16],
+/// }
+///
+/// impl HttpHandlerMut for Counter {
+///     async fn handle_request(
+///         &mut self,
+///         _request: &HttpRequest<'_>,
+///     ) -> Result<HttpResponse<'_>, Error> {
+///         let hits = HITS.lock(|hits| {
+///             *hits += 1;
+///             *hits
+///         });
+///         HttpResponse::text_fmt(StatusCode::Ok, &mut self.buf, format_args!("{hits}"))
+///     }
+/// }
+/// ```
+pub struct SharedState<M: RawMutex, T> {
+    value: Mutex<M, RefCell<T>>,
+}
+
+impl<M: RawMutex, T> SharedState<M, T> {
+    /// Create the state with its initial `value`
+    #[must_use]
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: Mutex::new(RefCell::new(value)),
+        }
+    }
+
+    /// Run `f` with exclusive access to the value and return its result
+    ///
+    /// `f` must not lock the same state again, which would panic.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.value.lock(|value| f(&mut value.borrow_mut()))
+    }
+
+    /// Replace the value
+    pub fn set(&self, value: T) {
+        self.lock(|current| *current = value);
+    }
+
+    /// Get a copy of the value
+    #[must_use]
+    pub fn get(&self) -> T
+    where
+        T: Copy,
+    {
+        self.lock(|value| *value)
+    }
+}
+
+impl<M: RawMutex, T: Default> Default for SharedState<M, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+    #[test]
+    fn test_shared_state() {
+        static STATE: SharedState<CriticalSectionRawMutex, (u32, bool)> =
+            SharedState::new((0, false));
+
+        let threads: std::vec::Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..100 {
+                        STATE.lock(|(count, _)| *count += 1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(STATE.get(), (400, false));
+
+        STATE.set((1, true));
+        assert_eq!(STATE.lock(|(count, flag)| (*count, *flag)), (1, true));
+    }
+}
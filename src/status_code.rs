@@ -40,6 +40,8 @@ pub enum StatusCode {
     // 306 is unused
     /// 307 Temporary Redirect: The request should be repeated with another URI, but future requests should still use the original URI.
     TemporaryRedirect = 307,
+    /// 308 Permanent Redirect: This and all future requests should be repeated with another URI, keeping the method and body.
+    PermanentRedirect = 308,
 
     // 4xx Client Error
     /// 400 Bad Request: The server could not understand the request due to invalid syntax.
@@ -78,6 +80,12 @@ pub enum StatusCode {
     RequestedRangeNotSatisfiable = 416,
     /// 417 Expectation Failed: The server cannot meet the requirements of the Expect request-header field.
     ExpectationFailed = 417,
+    /// 422 Unprocessable Entity: The request is well-formed, but its content cannot be processed.
+    UnprocessableEntity = 422,
+    /// 426 Upgrade Required: The server refuses the request over the current protocol but will answer it after the client upgrades to the protocol named in the Upgrade header.
+    UpgradeRequired = 426,
+    /// 431 Request Header Fields Too Large: The request's headers are too large for the server to process.
+    RequestHeaderFieldsTooLarge = 431,
 
     // 5xx Server Error
     /// 500 Internal Server Error: The server has encountered a situation it doesn't know how to handle.
@@ -118,6 +126,7 @@ impl StatusCode {
             StatusCode::NotModified => 304,
             StatusCode::UseProxy => 305,
             StatusCode::TemporaryRedirect => 307,
+            StatusCode::PermanentRedirect => 308,
             StatusCode::BadRequest => 400,
             StatusCode::Unauthorized => 401,
             StatusCode::PaymentRequired => 402,
@@ -136,6 +145,9 @@ impl StatusCode {
             StatusCode::UnsupportedMediaType => 415,
             StatusCode::RequestedRangeNotSatisfiable => 416,
             StatusCode::ExpectationFailed => 417,
+            StatusCode::UnprocessableEntity => 422,
+            StatusCode::UpgradeRequired => 426,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
             StatusCode::InternalServerError => 500,
             StatusCode::NotImplemented => 501,
             StatusCode::BadGateway => 502,
@@ -168,6 +180,7 @@ impl StatusCode {
             StatusCode::NotModified => "Not Modified",
             StatusCode::UseProxy => "Use Proxy",
             StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
             // 4xx
             StatusCode::BadRequest => "Bad Request",
             StatusCode::Unauthorized => "Unauthorized",
@@ -187,6 +200,9 @@ impl StatusCode {
             StatusCode::UnsupportedMediaType => "Unsupported Media Type",
             StatusCode::RequestedRangeNotSatisfiable => "Requested Range Not Satisfiable",
             StatusCode::ExpectationFailed => "Expectation Failed",
+            StatusCode::UnprocessableEntity => "Unprocessable Entity",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             // 5xx
             StatusCode::InternalServerError => "Internal Server Error",
             StatusCode::NotImplemented => "Not Implemented",
@@ -198,6 +214,22 @@ impl StatusCode {
         }
     }
 
+    /// Check if the status code is informational (1xx status codes)
+    #[must_use]
+    pub fn is_informational(self) -> bool {
+        let code = self.as_u16();
+        (100..200).contains(&code)
+    }
+
+    /// Check if a response with this status code may carry a body
+    ///
+    /// 1xx, `204 No Content` and `304 Not Modified` responses end after their
+    /// headers.
+    #[must_use]
+    pub fn allows_body(self) -> bool {
+        !self.is_informational() && !matches!(self.as_u16(), 204 | 304)
+    }
+
     /// Check if the status code indicates success (2xx status codes)
     #[must_use]
     pub fn is_success(self) -> bool {
@@ -239,6 +271,7 @@ impl From<u16> for StatusCode {
             304 => StatusCode::NotModified,
             305 => StatusCode::UseProxy,
             307 => StatusCode::TemporaryRedirect,
+            308 => StatusCode::PermanentRedirect,
             400 => StatusCode::BadRequest,
             401 => StatusCode::Unauthorized,
             402 => StatusCode::PaymentRequired,
@@ -257,6 +290,9 @@ impl From<u16> for StatusCode {
             415 => StatusCode::UnsupportedMediaType,
             416 => StatusCode::RequestedRangeNotSatisfiable,
             417 => StatusCode::ExpectationFailed,
+            422 => StatusCode::UnprocessableEntity,
+            426 => StatusCode::UpgradeRequired,
+            431 => StatusCode::RequestHeaderFieldsTooLarge,
             500 => StatusCode::InternalServerError,
             501 => StatusCode::NotImplemented,
             502 => StatusCode::BadGateway,
@@ -314,6 +350,18 @@ mod tests {
 
         let code: StatusCode = 307_u16.into();
         assert_eq!(code, StatusCode::TemporaryRedirect);
+
+        let code: StatusCode = 308_u16.into();
+        assert_eq!(code, StatusCode::PermanentRedirect);
+
+        let code: StatusCode = 422_u16.into();
+        assert_eq!(code, StatusCode::UnprocessableEntity);
+
+        let code: StatusCode = 426_u16.into();
+        assert_eq!(code, StatusCode::UpgradeRequired);
+
+        let code: StatusCode = 431_u16.into();
+        assert_eq!(code, StatusCode::RequestHeaderFieldsTooLarge);
     }
 
     #[test]
@@ -335,6 +383,12 @@ mod tests {
         );
         assert_eq!(StatusCode::BadRequest.text(), "Bad Request");
         assert_eq!(StatusCode::TemporaryRedirect.text(), "Temporary Redirect");
+        assert_eq!(StatusCode::PermanentRedirect.text(), "Permanent Redirect");
+        assert_eq!(StatusCode::UpgradeRequired.text(), "Upgrade Required");
+        assert_eq!(
+            StatusCode::RequestHeaderFieldsTooLarge.text(),
+            "Request Header Fields Too Large"
+        );
     }
 
     #[test]
@@ -345,6 +399,24 @@ mod tests {
         assert_eq!(StatusCode::InternalServerError.as_u16(), 500);
         assert_eq!(StatusCode::Continue.as_u16(), 100);
         assert_eq!(StatusCode::TemporaryRedirect.as_u16(), 307);
+        assert_eq!(StatusCode::PermanentRedirect.as_u16(), 308);
+        assert_eq!(StatusCode::UnprocessableEntity.as_u16(), 422);
+        assert_eq!(StatusCode::UpgradeRequired.as_u16(), 426);
+        assert_eq!(StatusCode::RequestHeaderFieldsTooLarge.as_u16(), 431);
+    }
+
+    #[test]
+    fn test_allows_body() {
+        assert!(StatusCode::Ok.allows_body());
+        assert!(StatusCode::NotFound.allows_body());
+        assert!(StatusCode::ResetContent.allows_body());
+        assert!(!StatusCode::Continue.allows_body());
+        assert!(!StatusCode::SwitchingProtocols.allows_body());
+        assert!(!StatusCode::NoContent.allows_body());
+        assert!(!StatusCode::NotModified.allows_body());
+        assert!(!StatusCode::Other(103).allows_body());
+        assert!(StatusCode::SwitchingProtocols.is_informational());
+        assert!(!StatusCode::Ok.is_informational());
     }
 
     #[test]
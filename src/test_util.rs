@@ -0,0 +1,69 @@
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+/// In-memory connection capturing everything written to it
+pub(crate) struct TestSocket(pub(crate) std::vec::Vec<u8>);
+
+impl ErrorType for TestSocket {
+    type Error = core::convert::Infallible;
+}
+
+impl Read for TestSocket {
+    async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // The client sends nothing beyond the request
+        Ok(0)
+    }
+}
+
+impl Write for TestSocket {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Connection that is reset by the client after `capacity` bytes
+pub(crate) struct ResettingSocket {
+    pub(crate) written: std::vec::Vec<u8>,
+    pub(crate) capacity: usize,
+    /// Number of writes attempted, including the failing one
+    pub(crate) writes: usize,
+}
+
+impl ResettingSocket {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            written: std::vec::Vec::new(),
+            capacity,
+            writes: 0,
+        }
+    }
+}
+
+impl ErrorType for ResettingSocket {
+    type Error = ErrorKind;
+}
+
+impl Read for ResettingSocket {
+    async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Err(ErrorKind::ConnectionReset)
+    }
+}
+
+impl Write for ResettingSocket {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.writes += 1;
+        if self.written.len() + buf.len() > self.capacity {
+            return Err(ErrorKind::ConnectionReset);
+        }
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
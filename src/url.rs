@@ -0,0 +1,255 @@
+use crate::error::Error;
+
+/// Builder writing a percent-encoded URL into a caller-provided buffer
+///
+/// Meant for `Location` headers and links with query state, e.g. a
+/// POST-redirect-GET that carries the search the user just submitted. Every
+/// component is encoded as it is appended, so the result can never contain CR, LF
+/// or other characters that would break out of a header or the URL itself.
+///
+/// ```rust
+/// use nanofish::UrlBuilder;
+///
+/// let mut buf = [0u8; 64];
+/// let mut url = UrlBuilder::new(&mut buf);
+/// url.path("/search results")?
+///     .query("q", "fish & chips")?
+///     .query("page", "2")?;
+/// assert_eq!(url.as_str(), "/search%20results?q=fish%20%26%20chips&page=2");
+/// # Ok::<(), nanofish::Error>(())
+/// ```
+pub struct UrlBuilder<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    has_query: bool,
+}
+
+impl<'b> UrlBuilder<'b> {
+    /// Create an empty URL writing into `buf`
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            has_query: false,
+        }
+    }
+
+    /// Append `path`, keeping its `/` separators and encoding everything else unsafe
+    ///
+    /// Call this before adding query parameters. A scheme and host can be added as
+    /// part of the first path, e.g. `"http://device.local/status"`, as `:` is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidUrl`] if query parameters were already added, or
+    /// [`Error::ResponseTooLarge`] if the buffer is full.
+    pub fn path(&mut self, path: &str) -> Result<&mut Self, Error> {
+        if self.has_query {
+            return Err(Error::InvalidUrl);
+        }
+        self.encode(path, |b| b == b'/' || is_path_safe(b))?;
+        Ok(self)
+    }
+
+    /// Append the query parameter `name=value`, percent-encoding both
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ResponseTooLarge`] if the buffer is full.
+    pub fn query(&mut self, name: &str, value: &str) -> Result<&mut Self, Error> {
+        let separator = if self.has_query { b'&' } else { b'?' };
+        self.push(separator)?;
+        self.has_query = true;
+        self.encode(name, is_unreserved)?;
+        self.push(b'=')?;
+        self.encode(value, is_unreserved)?;
+        Ok(self)
+    }
+
+    /// The URL built so far
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        // Only ASCII is ever written
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+
+    /// Finish the URL, returning it borrowed from the buffer
+    #[must_use]
+    pub fn finish(self) -> &'b str {
+        let Self { buf, len, .. } = self;
+        core::str::from_utf8(&buf[..len]).unwrap_or_default()
+    }
+
+    fn encode(&mut self, text: &str, keep: impl Fn(u8) -> bool) -> Result<(), Error> {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+        for b in text.bytes() {
+            if keep(b) {
+                self.push(b)?;
+            } else {
+                self.push(b'%')?;
+                self.push(HEX[usize::from(b >> 4)])?;
+                self.push(HEX[usize::from(b & 0x0F)])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, b: u8) -> Result<(), Error> {
+        *self.buf.get_mut(self.len).ok_or(Error::ResponseTooLarge)? = b;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Characters that never need encoding (RFC 3986 unreserved)
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Characters allowed as-is within a path segment
+fn is_path_safe(b: u8) -> bool {
+    is_unreserved(b)
+        || matches!(
+            b,
+            b'!' | b'$' | b'\'' | b'(' | b')' | b'*' | b',' | b';' | b'=' | b':' | b'@'
+        )
+}
+
+/// Part of a URL a percent-encoded text was taken from
+///
+/// Decides what `+` means: in a query string (and form bodies) it stands for a space,
+/// in a path it is a literal `+`, e.g. in a file name like `c++.txt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Component {
+    Path,
+    Query,
+}
+
+/// Why percent-decoding failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DecodeError {
+    /// A `%` not followed by two hex digits
+    InvalidEscape,
+    /// The decoded text does not fit into the buffer
+    TooLong,
+    /// The decoded bytes are not valid UTF-8
+    InvalidUtf8,
+}
+
+/// Decode the `%XX` escapes in `text` into `buf`, and `+` as a space in a query
+pub(crate) fn percent_decode<'b>(
+    text: &str,
+    component: Component,
+    buf: &'b mut [u8],
+) -> Result<&'b str, DecodeError> {
+    let hex = |digit: u8| (digit as char).to_digit(16);
+    let mut len = 0;
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        let decoded = match byte {
+            b'+' if component == Component::Query => b' ',
+            b'%' => {
+                let high = bytes.next().and_then(hex);
+                let low = bytes.next().and_then(hex);
+                let (Some(high), Some(low)) = (high, low) else {
+                    return Err(DecodeError::InvalidEscape);
+                };
+                // Two hex digits always fit into a byte
+                u8::try_from(high << 4 | low).map_err(|_| DecodeError::InvalidEscape)?
+            }
+            byte => byte,
+        };
+        *buf.get_mut(len).ok_or(DecodeError::TooLong)? = decoded;
+        len += 1;
+    }
+    core::str::from_utf8(&buf[..len]).map_err(|_| DecodeError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_builder() {
+        let mut buf = [0u8; 128];
+        let mut url = UrlBuilder::new(&mut buf);
+        url.path("/files/a b/ü.txt")
+            .unwrap()
+            .query("from", "2025-01-01")
+            .unwrap()
+            .query("tag", "a+b=c&d")
+            .unwrap();
+        assert_eq!(
+            url.finish(),
+            "/files/a%20b/%C3%BC.txt?from=2025-01-01&tag=a%2Bb%3Dc%26d"
+        );
+
+        let mut buf = [0u8; 64];
+        let mut url = UrlBuilder::new(&mut buf);
+        url.path("http://device.local:8080/login").unwrap();
+        assert_eq!(url.as_str(), "http://device.local:8080/login");
+    }
+
+    #[test]
+    fn test_url_builder_rejects_injection() {
+        let mut buf = [0u8; 64];
+        let mut url = UrlBuilder::new(&mut buf);
+        url.path("/a?b#c\r\nSet-Cookie: x")
+            .unwrap()
+            .query("next", "\r\n")
+            .unwrap();
+        assert_eq!(url.as_str(), "/a%3Fb%23c%0D%0ASet-Cookie:%20x?next=%0D%0A");
+        assert!(matches!(url.path("/late"), Err(Error::InvalidUrl)));
+    }
+
+    #[test]
+    fn test_url_builder_buffer_full() {
+        let mut buf = [0u8; 8];
+        let mut url = UrlBuilder::new(&mut buf);
+        assert!(url.path("/status").is_ok());
+        assert!(matches!(url.query("a", "1"), Err(Error::ResponseTooLarge)));
+    }
+
+    #[test]
+    fn test_percent_decode_plus_depends_on_component() {
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            percent_decode("c++%20notes.txt", Component::Path, &mut buf),
+            Ok("c++ notes.txt")
+        );
+        assert_eq!(
+            percent_decode("fish+%26+chips%2B", Component::Query, &mut buf),
+            Ok("fish & chips+")
+        );
+        // An encoded plus is a literal plus in both
+        assert_eq!(
+            percent_decode("a%2Bb", Component::Path, &mut buf),
+            Ok("a+b")
+        );
+        assert_eq!(
+            percent_decode("a%2Bb", Component::Query, &mut buf),
+            Ok("a+b")
+        );
+    }
+
+    #[test]
+    fn test_percent_decode_errors() {
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            percent_decode("%2", Component::Path, &mut buf),
+            Err(DecodeError::InvalidEscape)
+        );
+        assert_eq!(
+            percent_decode("%zz", Component::Query, &mut buf),
+            Err(DecodeError::InvalidEscape)
+        );
+        assert_eq!(
+            percent_decode("%FF", Component::Path, &mut buf),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(
+            percent_decode("abcde", Component::Path, &mut buf),
+            Err(DecodeError::TooLong)
+        );
+    }
+}
@@ -0,0 +1,621 @@
+use crate::{
+    error::Error,
+    header::{HttpHeader, headers::KEEP_ALIVE, named, ordered},
+    response::{check_headers, write_status_line},
+    status_code::StatusCode,
+};
+use core::{cell::Cell, future::Future, pin::pin};
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Error as _, ErrorKind, Read, Write};
+use heapless::Vec;
+
+/// Maximum length of a status line (`HTTP/1.1 <code> <reason>\r\n`)
+const STATUS_LINE_SIZE: usize = 64;
+
+/// Writer for streaming a response directly to the connection
+///
+/// Handed to [`HttpHandler::handle_stream`](crate::HttpHandler::handle_stream). The
+/// response is sent with `Transfer-Encoding: chunked`, so every chunk written with
+/// [`ResponseWriter::write_chunk`] can reach the client while the handler is still
+/// working, e.g. to report the progress of a long OTA update.
+///
+/// HTTP/1.0 clients do not understand chunked framing. For them the data is written
+/// as-is with `Connection: close`, and the end of the body is signalled by closing
+/// the connection.
+///
+/// Responses to `HEAD` requests and statuses that do not allow a body (1xx, 204 and
+/// 304, see [`StatusCode::allows_body`]) are sent as the head alone: the writer adds
+/// no framing and ignores any data written after [`ResponseWriter::start`].
+///
+/// Once a write fails, e.g. because the client disconnected, the writer is closed:
+/// every further call returns [`Error::IoError`] without touching the connection, so
+/// streaming loops stop at their next `?`.
+// Each flag tracks an independent aspect of the response
+#[allow(clippy::struct_excessive_bools)]
+pub struct ResponseWriter<'w, W> {
+    writer: &'w mut W,
+    chunked: bool,
+    with_body: bool,
+    started: bool,
+    closed: bool,
+    close_connection: bool,
+    switch_protocols: bool,
+    keep_alive: Option<&'w str>,
+    start_signal: Option<&'w Cell<bool>>,
+}
+
+impl<'w, W: Write> ResponseWriter<'w, W> {
+    /// Create a writer using chunked framing if `chunked`, otherwise close-delimited
+    ///
+    /// With `head`, the response answers a `HEAD` request and its body is left out.
+    pub(crate) fn new(writer: &'w mut W, chunked: bool, head: bool) -> Self {
+        Self {
+            writer,
+            chunked,
+            with_body: !head,
+            started: false,
+            closed: false,
+            close_connection: false,
+            switch_protocols: false,
+            keep_alive: None,
+            start_signal: None,
+        }
+    }
+
+    /// Check if the status line and headers have already been sent
+    #[must_use]
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Check if writing to the connection has failed
+    ///
+    /// Streaming handlers can poll this to stop producing data for a client that is
+    /// gone.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Check if the connection is closed once the response has been sent
+    pub(crate) fn closes_connection(&self) -> bool {
+        self.close_connection
+    }
+
+    /// Close the connection after the response even if the client wants to keep it open
+    pub(crate) fn close_connection(&mut self) {
+        self.close_connection = true;
+    }
+
+    /// Check if the handler takes over the connection once the response has been sent
+    pub(crate) fn switches_protocols(&self) -> bool {
+        self.switch_protocols
+    }
+
+    /// Hand the connection to the handler after a `101 Switching Protocols` response
+    pub(crate) fn switch_protocols(&mut self) {
+        self.switch_protocols = true;
+    }
+
+    /// Announce that the connection stays open with a `Keep-Alive: <value>` header
+    pub(crate) fn set_keep_alive(&mut self, value: &'w str) {
+        self.keep_alive = Some(value);
+    }
+
+    /// Set `signal` once the response is started
+    pub(crate) fn signal_start(&mut self, signal: &'w Cell<bool>) {
+        self.start_signal = Some(signal);
+    }
+
+    /// Value of the `Keep-Alive` header, unless the connection is closed or taken over
+    /// after the response
+    pub(crate) fn keep_alive(&self) -> Option<&'w str> {
+        self.keep_alive
+            .filter(|_| !self.close_connection && !self.switch_protocols)
+    }
+
+    /// Send the status line and headers of the response
+    ///
+    /// `Transfer-Encoding: chunked` (or `Connection: close` for HTTP/1.0 clients) is
+    /// added automatically, and a `Content-Length` in `headers` is left out. For a
+    /// `HEAD` request or a status without a body neither is added and `headers` are
+    /// sent unchanged; a `101 Switching Protocols` hands the connection to
+    /// [`HttpHandlerMut::take_over`](crate::HttpHandlerMut::take_over) afterwards. Pass
+    /// a `Connection: close` header to have the server close the connection once the
+    /// response is complete. Headers are sent in the same order and casing as by
+    /// [`HttpResponse::build_bytes`](crate::HttpResponse::build_bytes).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response was already started, a header fails
+    /// [`HttpHeader::is_valid`] or the headers exceed the limits of a buffered response
+    /// ([`MAX_RESPONSE_HEADER_BYTES`](crate::response::MAX_RESPONSE_HEADER_BYTES); nothing is sent
+    /// then), or writing to the connection fails.
+    pub async fn start(
+        &mut self,
+        status_code: StatusCode,
+        headers: &[HttpHeader<'_>],
+    ) -> Result<(), Error> {
+        if self.started {
+            return Err(Error::HeaderError("Response already started"));
+        }
+        // Checked up front so nothing is sent for a response that cannot be completed
+        check_headers(headers)?;
+        self.started = true;
+        if let Some(signal) = self.start_signal {
+            signal.set(true);
+        }
+        self.with_body &= status_code.allows_body();
+        if headers.iter().any(HttpHeader::closes_connection) {
+            self.close_connection = true;
+        } else if status_code == StatusCode::SwitchingProtocols {
+            self.switch_protocols = true;
+        }
+
+        let mut status_line = Vec::<u8, STATUS_LINE_SIZE>::new();
+        write_status_line(&mut status_line, status_code)?;
+        self.write_all(&status_line).await?;
+
+        for header in ordered(headers, self.with_body) {
+            self.write_all(header.canonical_name().as_bytes()).await?;
+            self.write_all(b": ").await?;
+            self.write_all(header.value.as_bytes()).await?;
+            self.write_all(b"\r\n").await?;
+        }
+        if let Some(keep_alive) = self.keep_alive()
+            && named(headers, KEEP_ALIVE).next().is_none()
+        {
+            self.write_all(b"Keep-Alive: ").await?;
+            self.write_all(keep_alive.as_bytes()).await?;
+            self.write_all(b"\r\n").await?;
+        }
+        if !self.with_body {
+            // Nothing to frame
+            self.write_all(b"\r\n").await
+        } else if self.chunked {
+            self.write_all(b"Transfer-Encoding: chunked\r\n\r\n").await
+        } else if self.close_connection {
+            // Already announced by the handler
+            self.write_all(b"\r\n").await
+        } else {
+            self.write_all(b"Connection: close\r\n\r\n").await
+        }
+    }
+
+    /// Send `data` as a single chunk
+    ///
+    /// Starts a `200 OK` response without extra headers if [`ResponseWriter::start`]
+    /// has not been called yet. Empty data is ignored, as an empty chunk would end
+    /// the response, and so is all data for a status without a body.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the connection fails.
+    pub async fn write_chunk(&mut self, data: &[u8]) -> Result<(), Error> {
+        if !self.started {
+            self.start(StatusCode::Ok, &[]).await?;
+        }
+        if data.is_empty() || !self.with_body {
+            return Ok(());
+        }
+        if !self.chunked {
+            return self.write_all(data).await;
+        }
+
+        let (header, len) = chunk_header(data.len());
+        self.write_all(&header[..len]).await?;
+        self.write_all(data).await?;
+        self.write_all(b"\r\n").await
+    }
+
+    /// Copy everything `reader` produces into the response, `buf.len()` bytes at a time
+    ///
+    /// Pipes a source like a sensor stream or a flash reader straight to the client
+    /// without buffering it whole: every read becomes one chunk. Starts a `200 OK`
+    /// response if [`ResponseWriter::start`] has not been called yet. Returns the
+    /// number of bytes copied once `reader` reports the end of its data.
+    ///
+    /// The size of `buf` sets the chunk size: larger chunks need more memory but fewer
+    /// reads and less framing. Match it to the source, e.g. the flash page size;
+    /// [`DEFAULT_CHUNK_SIZE`](crate::file::DEFAULT_CHUNK_SIZE) is a sensible start.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IoError`] if reading from `reader` or writing to the
+    /// connection fails.
+    pub async fn write_from<R: Read>(
+        &mut self,
+        reader: &mut R,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let mut total = 0;
+        loop {
+            let n = reader
+                .read(buf)
+                .await
+                .map_err(|e| Error::IoError(e.kind()))?;
+            if n == 0 {
+                if !self.started {
+                    self.start(StatusCode::Ok, &[]).await?;
+                }
+                return Ok(total);
+            }
+            // A reader claiming more than it was given must not panic the server
+            let chunk = buf.get(..n).ok_or(Error::IoError(ErrorKind::InvalidData))?;
+            self.write_chunk(chunk).await?;
+            total += n;
+        }
+    }
+
+    /// Wait for `future`, sending an event stream comment every `interval` until it is done
+    ///
+    /// Keeps a `text/event-stream` (server-sent events) response from looking idle to
+    /// proxies and browsers, which drop streams that stay silent too long, while a
+    /// handler waits for its next event, e.g. a sensor change. The comment
+    /// (`: ping`) is ignored by `EventSource` clients and flushed right away. Start the
+    /// response with [`ResponseWriter::start`] first, otherwise the first ping starts
+    /// a `200 OK` without a `Content-Type`. The handler timeout ends with the start of
+    /// the response, so the stream stays open for as long as the handler keeps it.
+    ///
+    /// ```rust,ignore
+    /// writer
+    ///     .start(StatusCode::Ok, &[HttpHeader::content_type("text/event-stream")])
+    ///     .await?;
+    /// loop {
+    ///     let reading = writer
+    ///         .ping_while(Duration::from_secs(15), sensor.wait_for_change())
+    ///         .await?;
+    ///     writer.write_chunk(format_event(&mut buf, reading)).await?;
+    ///     writer.flush().await?;
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IoError`] if sending a ping fails, e.g. because the client
+    /// disconnected; `future` is dropped then.
+    pub async fn ping_while<F: Future>(
+        &mut self,
+        interval: Duration,
+        future: F,
+    ) -> Result<F::Output, Error> {
+        let mut future = pin!(future);
+        loop {
+            if let Ok(output) = with_timeout(interval, future.as_mut()).await {
+                return Ok(output);
+            }
+            self.write_chunk(b": ping\n\n").await?;
+            self.flush().await?;
+        }
+    }
+
+    /// Push everything written so far out to the client
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing the connection fails.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Err(Error::IoError(ErrorKind::NotConnected));
+        }
+        let result = self.writer.flush().await;
+        self.check(result)
+    }
+
+    /// Terminate the response
+    ///
+    /// A close-delimited response ends when the connection is closed afterwards.
+    pub(crate) async fn finish(&mut self) -> Result<(), Error> {
+        if self.chunked && self.with_body {
+            self.write_all(b"0\r\n\r\n").await?;
+        }
+        self.flush().await
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.closed {
+            return Err(Error::IoError(ErrorKind::NotConnected));
+        }
+        let result = self.writer.write_all(data).await;
+        self.check(result)
+    }
+
+    /// Close the writer if `result` is a connection error
+    fn check(&mut self, result: Result<(), W::Error>) -> Result<(), Error> {
+        result.map_err(|e| {
+            self.closed = true;
+            Error::IoError(e.kind())
+        })
+    }
+}
+
+/// Format the `<hex length>\r\n` line preceding a chunk
+fn chunk_header(mut len: usize) -> ([u8; 18], usize) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+
+    let mut digits = [0u8; 16];
+    let mut count = 0;
+    loop {
+        digits[count] = HEX[len & 0xf];
+        count += 1;
+        len >>= 4;
+        if len == 0 {
+            break;
+        }
+    }
+
+    let mut header = [0u8; 18];
+    for (i, digit) in digits[..count].iter().rev().enumerate() {
+        header[i] = *digit;
+    }
+    header[count] = b'\r';
+    header[count + 1] = b'\n';
+    (header, count + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{ResettingSocket, TestSocket};
+
+    #[test]
+    fn test_chunk_header() {
+        let cases: [(usize, &[u8]); 5] = [
+            (1, b"1\r\n"),
+            (10, b"a\r\n"),
+            (16, b"10\r\n"),
+            (4096, b"1000\r\n"),
+            (0xffff_ffff, b"ffffffff\r\n"),
+        ];
+        for (len, expected) in cases {
+            let (header, n) = chunk_header(len);
+            assert_eq!(&header[..n], expected);
+        }
+    }
+
+    #[test]
+    fn test_writer_closes_after_reset() {
+        let mut socket = ResettingSocket::new(64);
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+
+        futures_lite::future::block_on(async {
+            writer.write_chunk(b"progress").await.unwrap();
+            assert!(!writer.is_closed());
+
+            let result = writer.write_chunk(&[b'x'; 64]).await;
+            assert!(matches!(
+                result,
+                Err(Error::IoError(ErrorKind::ConnectionReset))
+            ));
+            assert!(writer.is_closed());
+
+            // Nothing more reaches the connection
+            let result = writer.write_chunk(b"more").await;
+            assert!(matches!(
+                result,
+                Err(Error::IoError(ErrorKind::NotConnected))
+            ));
+            assert!(writer.flush().await.is_err());
+        });
+        // The failed write was the last one attempted
+        assert_eq!(socket.writes, 7);
+    }
+
+    #[test]
+    fn test_write_from_reader() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        let mut reader: &[u8] = b"0123456789";
+        let mut buf = [0u8; 4];
+
+        let copied = futures_lite::future::block_on(async {
+            writer
+                .start(StatusCode::Ok, &[HttpHeader::content_type("text/plain")])
+                .await
+                .unwrap();
+            let copied = writer.write_from(&mut reader, &mut buf).await.unwrap();
+            writer.finish().await.unwrap();
+            copied
+        });
+        assert_eq!(copied, 10);
+        assert_eq!(
+            core::str::from_utf8(&socket.0).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4\r\n0123\r\n4\r\n4567\r\n2\r\n89\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_start_announces_keep_alive() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        writer.set_keep_alive("timeout=5");
+        futures_lite::future::block_on(writer.start(StatusCode::Ok, &[])).unwrap();
+        assert_eq!(
+            core::str::from_utf8(&socket.0).unwrap(),
+            "HTTP/1.1 200 OK\r\nKeep-Alive: timeout=5\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+
+        // Not when the handler closes the connection
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        writer.set_keep_alive("timeout=5");
+        let close = HttpHeader::new("Connection", "close");
+        futures_lite::future::block_on(writer.start(StatusCode::Ok, &[close])).unwrap();
+        assert!(!socket.0.windows(10).any(|w| w == b"Keep-Alive"));
+    }
+
+    #[test]
+    fn test_ping_while_waiting() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        let event = futures_lite::future::block_on(async {
+            let headers = [HttpHeader::content_type("text/event-stream")];
+            writer.start(StatusCode::Ok, &headers).await.unwrap();
+            let event = writer
+                .ping_while(Duration::from_millis(40), async {
+                    embassy_time::Timer::after(Duration::from_millis(100)).await;
+                    "data: 21.5\n\n"
+                })
+                .await
+                .unwrap();
+            writer.write_chunk(event.as_bytes()).await.unwrap();
+            // Ready right away, so nothing is sent in between
+            writer
+                .ping_while(Duration::from_millis(40), async {})
+                .await
+                .unwrap();
+            event
+        });
+        assert_eq!(event, "data: 21.5\n\n");
+        let output = core::str::from_utf8(&socket.0).unwrap();
+        // About two pings, more or less depending on timer resolution
+        assert!(output.contains("Transfer-Encoding: chunked\r\n\r\n8\r\n: ping\n\n\r\n"));
+        assert!(output.ends_with("8\r\n: ping\n\n\r\nc\r\ndata: 21.5\n\n\r\n"));
+    }
+
+    #[test]
+    fn test_start_keeps_cookies_apart() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        let headers = [
+            HttpHeader::new("set-cookie", "a=1"),
+            HttpHeader::new("Set-Cookie", "b=2"),
+        ];
+        futures_lite::future::block_on(writer.start(StatusCode::Ok, &headers)).unwrap();
+        assert_eq!(
+            core::str::from_utf8(&socket.0).unwrap(),
+            "HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\
+             Transfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_start_drops_content_length() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        let headers = [
+            HttpHeader::new("Content-Encoding", "gzip"),
+            HttpHeader::new("Content-Length", "4096"),
+        ];
+        futures_lite::future::block_on(writer.start(StatusCode::Ok, &headers)).unwrap();
+        assert_eq!(
+            core::str::from_utf8(&socket.0).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    /// Stream a `status_code` response with `headers` and a body, return what was sent
+    fn stream_with_body(
+        status_code: StatusCode,
+        headers: &[HttpHeader<'_>],
+    ) -> std::string::String {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        writer.set_keep_alive("timeout=5");
+        futures_lite::future::block_on(async {
+            writer.start(status_code, headers).await.unwrap();
+            writer.write_chunk(b"ignored").await.unwrap();
+            writer.finish().await.unwrap();
+        });
+        std::string::String::from_utf8(socket.0).unwrap()
+    }
+
+    #[test]
+    fn test_stream_without_body() {
+        assert_eq!(
+            stream_with_body(StatusCode::NoContent, &[]),
+            "HTTP/1.1 204 No Content\r\nKeep-Alive: timeout=5\r\n\r\n"
+        );
+        // The length of the representation a 304 stands for is passed on
+        let headers = [
+            HttpHeader::new("ETag", "\"v1\""),
+            HttpHeader::new("Content-Length", "4096"),
+        ];
+        assert_eq!(
+            stream_with_body(StatusCode::NotModified, &headers),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nContent-Length: 4096\r\n\
+             Keep-Alive: timeout=5\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_stream_switching_protocols() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+        writer.set_keep_alive("timeout=5");
+        let headers = [
+            HttpHeader::new("Connection", "Upgrade"),
+            HttpHeader::new("Upgrade", "shout"),
+        ];
+        futures_lite::future::block_on(writer.start(StatusCode::SwitchingProtocols, &headers))
+            .unwrap();
+        assert!(writer.switches_protocols());
+        assert_eq!(
+            core::str::from_utf8(&socket.0).unwrap(),
+            "HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: shout\r\n\r\n"
+        );
+    }
+
+    /// Reader reporting more bytes than fit into the buffer it was given
+    struct LyingReader;
+
+    impl embedded_io_async::ErrorType for LyingReader {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for LyingReader {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len() + 1)
+        }
+    }
+
+    #[test]
+    fn test_write_from_lying_reader() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+
+        let result =
+            futures_lite::future::block_on(writer.write_from(&mut LyingReader, &mut [0u8; 8]));
+        assert!(matches!(
+            result,
+            Err(Error::IoError(ErrorKind::InvalidData))
+        ));
+    }
+
+    #[test]
+    fn test_write_from_empty_reader_starts_response() {
+        let mut socket = TestSocket(std::vec::Vec::new());
+        let mut writer = ResponseWriter::new(&mut socket, false, false);
+        let mut reader: &[u8] = b"";
+
+        let copied =
+            futures_lite::future::block_on(writer.write_from(&mut reader, &mut [0u8; 8])).unwrap();
+        assert_eq!(copied, 0);
+        assert!(writer.is_started());
+        assert!(socket.0.ends_with(b"Connection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_start_rejects_header_injection() {
+        let mut socket = ResettingSocket::new(256);
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+
+        let headers = [HttpHeader::new("X-Name", "a\r\nSet-Cookie: admin=1")];
+        let result = futures_lite::future::block_on(writer.start(StatusCode::Ok, &headers));
+        assert!(matches!(result, Err(Error::HeaderError(_))));
+        assert!(!writer.is_started());
+        assert_eq!(socket.writes, 0);
+    }
+
+    #[test]
+    fn test_start_rejects_too_many_headers() {
+        let mut socket = ResettingSocket::new(256);
+        let mut writer = ResponseWriter::new(&mut socket, true, false);
+
+        let headers: [_; 17] = core::array::from_fn(|_| HttpHeader::new("X-Tag", "a"));
+        let result = futures_lite::future::block_on(writer.start(StatusCode::Ok, &headers));
+        assert!(matches!(result, Err(Error::HeaderError(_))));
+        assert_eq!(socket.writes, 0);
+    }
+}